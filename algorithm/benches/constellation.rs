@@ -0,0 +1,222 @@
+//! Compares the `f32` spectral pipeline against the original `Decimal` one it
+//! replaced, and checks both produce the same constellation map first. The
+//! legacy pipeline has no peak interpolation, so it is compared against the
+//! default configuration with interpolation turned off.
+
+use audio_identifier::{
+    audio::{
+        BandpassFilterMonoSource, ConstellationPoint, FingerprintConfig, PeakInterpolation,
+        constellation_points_with,
+    },
+    testing::XorShift,
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use rodio::buffer::SamplesBuffer;
+use std::{collections::BTreeMap, f32::consts::PI, hint::black_box};
+
+const SAMPLE_RATE: u32 = 11025;
+const SECONDS: usize = 30;
+
+/// Chords plus a little deterministic noise, so every chunk has peaks to pick.
+fn synthetic_audio() -> Vec<i16> {
+    let mut noise = XorShift::new(0x2545_f491);
+    (0..SAMPLE_RATE as usize * SECONDS)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let root = [220.0, 261.63, 329.63, 392.0][(i / SAMPLE_RATE as usize) % 4];
+            let tone: f32 = [1.0, 1.25, 1.5, 2.0]
+                .iter()
+                .map(|ratio| (2.0 * PI * root * ratio * t).sin())
+                .sum();
+            let jitter = noise.next_f32() - 0.5;
+            (tone * 4000.0 + jitter * 500.0) as i16
+        })
+        .collect()
+}
+
+fn source(samples: &[i16]) -> BandpassFilterMonoSource {
+    BandpassFilterMonoSource::new(
+        Box::new(SamplesBuffer::new(1, SAMPLE_RATE, samples.to_vec())),
+        SAMPLE_RATE,
+    )
+}
+
+fn constellation_points(
+    source: BandpassFilterMonoSource,
+) -> BTreeMap<usize, Vec<ConstellationPoint>> {
+    let config = FingerprintConfig {
+        interpolation: PeakInterpolation::None,
+        ..FingerprintConfig::default()
+    };
+    constellation_points_with(source, &config)
+}
+
+/// The `rust_decimal` implementation `constellation_points` used before the
+/// move to native floats, kept verbatim as the baseline.
+mod legacy {
+    use audio_identifier::audio::{BandpassFilterMonoSource, ConstellationPoint};
+    use itertools::Itertools;
+    use num_complex::Complex;
+    use rodio::Source;
+    use rust_decimal::{Decimal, MathematicalOps, prelude::ToPrimitive};
+    use rust_decimal_macros::dec;
+    use rustfft::{FftPlanner, num_traits::FromPrimitive};
+    use std::{
+        collections::{BTreeMap, VecDeque},
+        sync::Arc,
+    };
+
+    pub fn constellation_points(
+        source: BandpassFilterMonoSource,
+    ) -> BTreeMap<usize, Vec<ConstellationPoint>> {
+        let sample_rate = Decimal::from(source.sample_rate());
+        let channels = source.channels() as usize;
+        let chunk_size = 4096 * 2 / (2 * channels);
+        let hamming_window = (0..chunk_size)
+            .map(|i| {
+                dec!(0.54)
+                    - dec!(0.46)
+                        * (dec!(2.0) * Decimal::PI * Decimal::from(i)
+                            / Decimal::from(chunk_size - 1))
+                        .cos()
+            })
+            .collect_vec();
+        let step_size = chunk_size - chunk_size * 50 / 100;
+
+        let mut sample_buffer = VecDeque::with_capacity(chunk_size * 2);
+        let mut chunk = Vec::with_capacity(chunk_size);
+        let mut fft_buffer = Vec::with_capacity(chunk_size);
+        let fft = FftPlanner::new().plan_fft_forward(chunk_size);
+
+        let mut points: BTreeMap<usize, Vec<ConstellationPoint>> = BTreeMap::new();
+        let mut chunk_idx = 0_usize;
+        for sample in source {
+            sample_buffer.push_back(Decimal::from(sample));
+            if sample_buffer.len() >= chunk_size {
+                chunk.clear();
+                chunk.extend(sample_buffer.range(..chunk_size).cloned());
+                let frequency_resolution = sample_rate / Decimal::from(chunk_size);
+                chunk
+                    .iter_mut()
+                    .zip(hamming_window.iter())
+                    .for_each(|(sample, &window)| *sample *= window);
+                apply_fft(&mut chunk, fft.clone(), &mut fft_buffer);
+                points
+                    .entry(chunk_idx)
+                    .or_default()
+                    .extend(significant_peaks(
+                        &chunk,
+                        frequency_resolution,
+                        Decimal::from(chunk_idx),
+                        Decimal::from(step_size),
+                        sample_rate,
+                    ));
+                for _ in 0..step_size {
+                    sample_buffer.pop_front();
+                }
+                chunk_idx += 1;
+            }
+        }
+        points
+    }
+
+    fn apply_fft(
+        chunk: &mut Vec<Decimal>,
+        fft: Arc<dyn rustfft::Fft<f32>>,
+        fft_buffer: &mut Vec<Complex<f32>>,
+    ) {
+        fft_buffer.clear();
+        fft_buffer.extend(
+            chunk
+                .iter()
+                .map(|&sample| Complex::new(sample.to_f32().unwrap(), 0.0)),
+        );
+        fft.process(fft_buffer);
+        chunk.clear();
+        chunk.extend(
+            fft_buffer
+                .iter()
+                .map(|c| Decimal::from_f32(c.norm()).unwrap()),
+        );
+    }
+
+    fn significant_peaks(
+        chunk: &[Decimal],
+        frequency_resolution: Decimal,
+        index: Decimal,
+        step_size: Decimal,
+        sample_rate: Decimal,
+    ) -> Vec<ConstellationPoint> {
+        let max_magnitude = *chunk
+            .iter()
+            .max_by(|a, b| a.partial_cmp(b).unwrap())
+            .unwrap_or(&Decimal::ZERO);
+        chunk
+            .windows(5)
+            .enumerate()
+            .filter_map(|(bin, window)| {
+                if window[0] < window[1]
+                    && window[1] < window[2]
+                    && window[2] > window[3]
+                    && window[3] > window[4]
+                {
+                    Some((Decimal::from(bin) * frequency_resolution, window[2]))
+                } else {
+                    None
+                }
+            })
+            .filter(|(freq, _)| (dec!(20)..=dec!(5000)).contains(freq))
+            .sorted_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap())
+            .take(4)
+            .map(|(freq, magnitude)| ConstellationPoint {
+                time: index * step_size / sample_rate,
+                frequency: freq,
+                magnitude: (magnitude / max_magnitude) * dec!(100.0),
+            })
+            .collect()
+    }
+}
+
+/// The tolerance documented on `ConstellationPoint`.
+fn assert_equivalent(
+    ours: &BTreeMap<usize, Vec<ConstellationPoint>>,
+    legacy: &BTreeMap<usize, Vec<ConstellationPoint>>,
+) {
+    assert_eq!(ours.len(), legacy.len(), "chunk count differs");
+    for ((chunk, a), (_, b)) in ours.iter().zip(legacy) {
+        assert_eq!(a.len(), b.len(), "peak count differs in chunk {chunk}");
+        for (a, b) in a.iter().zip(b) {
+            assert_eq!(a.time, b.time, "time differs in chunk {chunk}");
+            assert_eq!(
+                a.frequency, b.frequency,
+                "frequency differs in chunk {chunk}"
+            );
+            let diff = (a.magnitude - b.magnitude).abs();
+            assert!(
+                diff < rust_decimal_macros::dec!(0.001),
+                "magnitude differs by {diff} in chunk {chunk}"
+            );
+        }
+    }
+}
+
+fn bench_constellation(c: &mut Criterion) {
+    let samples = synthetic_audio();
+    assert_equivalent(
+        &constellation_points(source(&samples)),
+        &legacy::constellation_points(source(&samples)),
+    );
+
+    let mut group = c.benchmark_group("constellation_points_30s");
+    group.sample_size(10);
+    group.bench_function("f32", |b| {
+        b.iter(|| constellation_points(black_box(source(&samples))))
+    });
+    group.bench_function("decimal", |b| {
+        b.iter(|| legacy::constellation_points(black_box(source(&samples))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_constellation);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+//! Compares hash lookup through SQLite against the memory-mapped hash index on
+//! a 10,000 song catalogue, after checking both find the same fingerprints.
+
+use audio_identifier::{
+    Fingerprint,
+    audio::HashScheme,
+    model::{HashIndex, find_similar_fingerprints, setup_database},
+    testing::XorShift,
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use sqlx::SqlitePool;
+use std::{collections::HashMap, hint::black_box};
+
+const SONGS: i64 = 10_000;
+const FINGERPRINTS_PER_SONG: usize = 300;
+/// Distinct hashes in use, about as many as a catalogue this size would have.
+const HASH_SPACE: u64 = 1 << 22;
+/// A ten second query.
+const QUERY_LEN: usize = 400;
+const SCHEME: HashScheme = HashScheme::Packed;
+
+/// Random fingerprints for every song, one transaction for the lot.
+async fn populate(pool: &SqlitePool) {
+    let mut random = XorShift::new(0x2545_f491_4f6c_dd1d);
+    let mut tx = pool.begin().await.unwrap();
+    for song_id in 1..=SONGS {
+        sqlx::query("INSERT INTO songs (id, title, artist, duration) VALUES (?, ?, 'Artist', 200)")
+            .bind(song_id)
+            .bind(format!("Song {song_id}"))
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let mut builder = sqlx::QueryBuilder::new(
+            "INSERT INTO fingerprints (song_id, hash_scheme, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time)",
+        );
+        builder.push_values(0..FINGERPRINTS_PER_SONG, |mut b, i| {
+            b.push_bind(song_id)
+                .push_bind(SCHEME.version())
+                .push_bind((random.next_u64() % HASH_SPACE) as i64)
+                .push_bind(i as f64 * 0.05)
+                .push_bind(80)
+                .push_bind(0)
+                .push_bind(0)
+                .push_bind(0.0);
+        });
+        builder.build().execute(&mut *tx).await.unwrap();
+    }
+    tx.commit().await.unwrap();
+}
+
+/// The fingerprints of part of one song, as a query would have them.
+async fn query(pool: &SqlitePool) -> Vec<Fingerprint> {
+    sqlx::query_as::<_, (i64, f64)>(
+        "SELECT hash, time_offset FROM fingerprints WHERE song_id = ? ORDER BY time_offset LIMIT ?",
+    )
+    .bind(SONGS / 2)
+    .bind(QUERY_LEN as i64)
+    .fetch_all(pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|(hash, time_offset)| (hash, time_offset, 0, 0, 0, 0.0).into())
+    .collect()
+}
+
+fn counts(found: &HashMap<i64, Vec<Fingerprint>>) -> HashMap<i64, usize> {
+    found.iter().map(|(&song, fps)| (song, fps.len())).collect()
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let (pool, index, query) = runtime.block_on(async {
+        let url = format!("sqlite:{}", dir.path().join("fingerprints.db").display());
+        let pool = setup_database(&url).await.unwrap();
+        populate(&pool).await;
+        let index = HashIndex::build(&pool, SCHEME, &dir.path().join("index"))
+            .await
+            .unwrap();
+        let query = query(&pool).await;
+        (pool, index, query)
+    });
+
+    let sql = runtime
+        .block_on(find_similar_fingerprints(&pool, SCHEME, &query))
+        .unwrap();
+    assert_eq!(counts(&index.find_similar(&query).unwrap()), counts(&sql));
+
+    let mut group = c.benchmark_group("hash_lookup_10k_songs");
+    group.bench_function("sqlite", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(find_similar_fingerprints(&pool, SCHEME, black_box(&query)))
+                .unwrap()
+        })
+    });
+    group.bench_function("mmap_index", |b| {
+        b.iter(|| index.find_similar(black_box(&query)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookup);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+-- Hashes are left as they are; rehash to FNV-1a before reverting a catalogue
+-- that has been migrated to another scheme.
+DROP INDEX IF EXISTS idx_fingerprints_scheme_hash;
+CREATE INDEX IF NOT EXISTS idx_fingerprints_hash ON fingerprints(hash);
+ALTER TABLE fingerprints DROP COLUMN hash_scheme;
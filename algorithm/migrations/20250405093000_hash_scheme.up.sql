@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- Existing fingerprints were all hashed with FNV-1a (scheme version 1)
+ALTER TABLE fingerprints ADD COLUMN hash_scheme INTEGER NOT NULL DEFAULT 1;
+DROP INDEX IF EXISTS idx_fingerprints_hash;
+CREATE INDEX IF NOT EXISTS idx_fingerprints_scheme_hash ON fingerprints(hash_scheme, hash);
@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- A title and artist identify a song. Copies catalogued before that was
+-- enforced are merged into the first: their fingerprints move to it, and
+-- then the copies go.
+CREATE TEMPORARY TABLE merged_songs AS
+    SELECT copy.id AS copy_id, MIN(kept.id) AS kept_id
+    FROM songs AS copy
+    JOIN songs AS kept
+        ON kept.title = copy.title AND kept.artist = copy.artist AND kept.id < copy.id
+    GROUP BY copy.id;
+UPDATE fingerprints
+    SET song_id = (SELECT kept_id FROM merged_songs WHERE copy_id = fingerprints.song_id)
+    WHERE song_id IN (SELECT copy_id FROM merged_songs);
+-- The same audio catalogued twice would otherwise count every match twice
+DELETE FROM fingerprints
+    WHERE song_id IN (SELECT kept_id FROM merged_songs)
+    AND id NOT IN (
+        SELECT MIN(id) FROM fingerprints
+        WHERE song_id IN (SELECT kept_id FROM merged_songs)
+        GROUP BY song_id, hash_scheme, hash, time_offset
+    );
+DELETE FROM songs WHERE id IN (SELECT copy_id FROM merged_songs);
+DROP TABLE merged_songs;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_songs_title_artist ON songs(title, artist);
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE songs ADD COLUMN isrc TEXT;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS songs_generation_insert;
+DROP TRIGGER IF EXISTS songs_generation_update;
+DROP TRIGGER IF EXISTS songs_generation_delete;
+DROP TABLE IF EXISTS catalogue_generation;
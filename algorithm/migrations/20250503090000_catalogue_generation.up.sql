@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- Bumped whenever songs are added, removed or changed, so data derived from
+-- the catalogue, such as a hash index, can tell it is out of date
+CREATE TABLE IF NOT EXISTS catalogue_generation (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    generation INTEGER NOT NULL
+);
+INSERT INTO catalogue_generation (id, generation) VALUES (1, 0);
+CREATE TRIGGER IF NOT EXISTS songs_generation_insert AFTER INSERT ON songs
+BEGIN
+    UPDATE catalogue_generation SET generation = generation + 1 WHERE id = 1;
+END;
+CREATE TRIGGER IF NOT EXISTS songs_generation_update AFTER UPDATE ON songs
+BEGIN
+    UPDATE catalogue_generation SET generation = generation + 1 WHERE id = 1;
+END;
+CREATE TRIGGER IF NOT EXISTS songs_generation_delete AFTER DELETE ON songs
+BEGIN
+    UPDATE catalogue_generation SET generation = generation + 1 WHERE id = 1;
+END;
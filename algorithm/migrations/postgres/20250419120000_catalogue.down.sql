@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE catalogue_fingerprints;
+DROP TABLE catalogue_songs;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+-- Named apart from the server's songs and fingerprints tables, and without
+-- IF NOT EXISTS, so pointing the store at the wrong database fails here
+CREATE TABLE catalogue_songs (
+    id BIGSERIAL PRIMARY KEY,
+    title TEXT NOT NULL,
+    artist TEXT NOT NULL,
+    album TEXT,
+    duration DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (title, artist)
+);
+CREATE TABLE catalogue_fingerprints (
+    id BIGSERIAL PRIMARY KEY,
+    song_id BIGINT NOT NULL REFERENCES catalogue_songs(id) ON DELETE CASCADE,
+    hash_scheme BIGINT NOT NULL,
+    hash BIGINT NOT NULL,
+    time_offset DOUBLE PRECISION NOT NULL,
+    confidence BIGINT NOT NULL,
+    anchor_frequency BIGINT NOT NULL,
+    target_frequency BIGINT NOT NULL,
+    delta_time DOUBLE PRECISION NOT NULL
+);
+CREATE INDEX idx_catalogue_fingerprints_scheme_hash ON catalogue_fingerprints(hash_scheme, hash);
+CREATE INDEX idx_catalogue_fingerprints_song ON catalogue_fingerprints(song_id);
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE catalogue_songs DROP COLUMN isrc;
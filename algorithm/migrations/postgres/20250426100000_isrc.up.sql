@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE catalogue_songs ADD COLUMN isrc TEXT;
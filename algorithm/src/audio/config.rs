@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use super::{HashScheme, PeakInterpolation, PeakPicker, WindowFunction};
+
+/// Every tunable of the fingerprinting pipeline, from resampling to pairing.
+///
+/// A catalogue can only be queried with the configuration it was built with,
+/// so the configuration is stored alongside it (see [`crate::model::catalogue_config`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FingerprintConfig {
+    /// Rate the audio is resampled to before analysis, in Hz.
+    pub sample_rate: u32,
+    /// Lower edge of the bandpass filter and of the band searched for peaks, in Hz.
+    pub low_cutoff: f32,
+    /// Upper edge of the bandpass filter and of the band searched for peaks, in Hz.
+    pub high_cutoff: f32,
+    /// STFT frame length in samples.
+    pub fft_size: usize,
+    /// Share of each frame overlapping the next, in percent.
+    pub overlap_percent: usize,
+    /// Taper applied to each frame before the FFT.
+    pub window: WindowFunction,
+    pub peak_picker: PeakPicker,
+    /// Sub-bin refinement of peak positions. Configurations stored before this
+    /// option existed read back as [`PeakInterpolation::None`].
+    #[serde(default)]
+    pub interpolation: PeakInterpolation,
+    /// Strongest peaks of each frame used as anchors.
+    pub anchors_per_frame: usize,
+    /// Frame distances from an anchor at which a target peak is looked for.
+    pub target_offsets: Vec<usize>,
+    /// Maximum fingerprints generated per anchor.
+    pub pairs_per_anchor: usize,
+    /// Minimum geometric mean of the anchor and target magnitudes (0–100).
+    pub min_pair_confidence: f32,
+    /// Only pair an anchor with a target at a musical interval from it (or
+    /// within 20 Hz). Useful for music, too restrictive for speech.
+    pub harmonic_pairs: bool,
+    /// How pairs are hashed. Configurations stored before this option existed
+    /// read back as [`HashScheme::Fnv1a`].
+    #[serde(default)]
+    pub hash_scheme: HashScheme,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        Self::music()
+    }
+}
+
+impl FingerprintConfig {
+    /// The original tuning: ~0.37 s frames over 20 Hz–5 kHz, harmonic pairs
+    /// only, with peak frequencies interpolated between bins.
+    pub fn music() -> Self {
+        Self {
+            sample_rate: 11025,
+            low_cutoff: 20.0,
+            high_cutoff: 5000.0,
+            fft_size: 4096,
+            overlap_percent: 50,
+            window: WindowFunction::Hamming,
+            peak_picker: PeakPicker::default(),
+            interpolation: PeakInterpolation::Frequency,
+            anchors_per_frame: 3,
+            target_offsets: vec![1, 2, 3, 4, 5, 6, 8, 12],
+            pairs_per_anchor: 3,
+            min_pair_confidence: 40.0,
+            harmonic_pairs: true,
+            hash_scheme: HashScheme::Packed,
+        }
+    }
+
+    /// What catalogues built before the configuration was stored with them
+    /// used: the music tuning with grid-aligned peaks and FNV-1a hashes.
+    pub fn legacy() -> Self {
+        Self {
+            interpolation: PeakInterpolation::None,
+            hash_scheme: HashScheme::Fnv1a,
+            ..Self::music()
+        }
+    }
+
+    /// Shorter ~93 ms frames over the voice band, with sparse neighbourhood
+    /// peaks interpolated in time and frequency, and no harmonic constraint on
+    /// pairs.
+    pub fn speech() -> Self {
+        Self {
+            sample_rate: 11025,
+            low_cutoff: 100.0,
+            high_cutoff: 4000.0,
+            fft_size: 1024,
+            overlap_percent: 50,
+            window: WindowFunction::Hann,
+            peak_picker: PeakPicker::neighbourhood(),
+            interpolation: PeakInterpolation::TimeFrequency,
+            anchors_per_frame: 3,
+            target_offsets: vec![1, 2, 3, 4, 6, 8],
+            pairs_per_anchor: 3,
+            min_pair_confidence: 30.0,
+            harmonic_pairs: false,
+            hash_scheme: HashScheme::Packed,
+        }
+    }
+
+    /// Samples between the starts of consecutive frames.
+    pub fn step_size(&self) -> usize {
+        self.fft_size - self.fft_size * self.overlap_percent / 100
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_round_trip_through_json() {
+        for config in [FingerprintConfig::music(), FingerprintConfig::speech()] {
+            let json = serde_json::to_string(&config).unwrap();
+            assert_eq!(
+                serde_json::from_str::<FingerprintConfig>(&json).unwrap(),
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn missing_fields_fall_back_to_the_music_preset() {
+        let config: FingerprintConfig = serde_json::from_str(
+            r#"{"fft_size": 2048, "peak_picker": {"kind": "frame", "peaks_per_frame": 6}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.fft_size, 2048);
+        assert_eq!(config.peak_picker, PeakPicker::Frame { peaks_per_frame: 6 });
+        assert_eq!(
+            config.target_offsets,
+            FingerprintConfig::music().target_offsets
+        );
+        assert_eq!(config.step_size(), 1024);
+        // Catalogues stored before interpolation existed keep grid-aligned peaks
+        assert_eq!(config.interpolation, PeakInterpolation::None);
+        assert_eq!(config.hash_scheme, HashScheme::Fnv1a);
+    }
+}
@@ -1,9 +1,7 @@
-use itertools::Itertools;
 use num_complex::Complex;
 use rodio::Source;
-use rust_decimal::{Decimal, MathematicalOps, prelude::ToPrimitive};
-use rust_decimal_macros::dec;
-use rustfft::{FftPlanner, num_traits::FromPrimitive};
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use rustfft::FftPlanner;
 use std::{
     collections::{BTreeMap, VecDeque},
     sync::Arc,
@@ -11,7 +9,17 @@ use std::{
 use tracing::info;
 
 pub use super::BandpassFilterMonoSource;
+use super::{FingerprintConfig, peaks::PeakExtractor};
 
+/// A spectral peak on the constellation map.
+///
+/// The spectrum is computed on `f32` samples; only the selected peaks are
+/// converted to `Decimal`. Without [`PeakInterpolation`](super::PeakInterpolation)
+/// `time` and `frequency` are derived from integer chunk and bin indices and
+/// are exact; interpolated positions carry the `f32` precision of the fit.
+/// `magnitude` (0–100, relative to the loudest
+/// bin of its chunk) agrees with a full-`Decimal` pipeline to within 1e-3, so
+/// peak selection only differs where two candidates are tied to that precision.
 #[derive(Debug, Clone)]
 pub struct ConstellationPoint {
     pub time: Decimal,      // Time in seconds
@@ -19,175 +27,248 @@ pub struct ConstellationPoint {
     pub magnitude: Decimal, // Magnitude of the peak
 }
 
+/// The constellation map as [`FingerprintConfig::legacy`] picks it, as this
+/// always has; see [`constellation_points_with`] for any other configuration.
 pub fn constellation_points(
     source: BandpassFilterMonoSource,
 ) -> BTreeMap<usize, Vec<ConstellationPoint>> {
-    // Calculate chunk size in samples (for processed mono audio)
-    let sample_rate = Decimal::from(source.sample_rate());
-    let bytes_per_sample = 2; // i16 = 2 bytes
-    let channels = source.channels() as usize;
-    let chunk_size = 4096 * 2 / (bytes_per_sample * channels);
-
-    // Create a Hamming window
-    let hamming_window = (0..chunk_size)
-        .map(|i| {
-            // Hamming window function: 0.54 - 0.46 * cos(2π * n / (N-1))
-            dec!(0.54)
-                - dec!(0.46)
-                    * (dec!(2.0) * Decimal::PI * Decimal::from(i) / Decimal::from(chunk_size - 1))
-                        .cos()
-            //0.54 - 0.46 * (2.0 * PI * i as f32 / (chunk_size - 1) as f32).cos()
-        })
-        .collect_vec();
-    // Configure overlap
-    let overlap_percent = 50; // 50% overlap between consecutive chunks
-    let overlap_samples = (chunk_size * overlap_percent) / 100;
-    let step_size = chunk_size - overlap_samples;
-    info!(
-        "Processing in chunks of {} samples with {}% overlap ({} samples)",
-        chunk_size, overlap_percent, overlap_samples
-    );
-    let chunk_duration = Decimal::from(chunk_size) / sample_rate;
-    let step_duration = Decimal::from(step_size) / sample_rate;
-
-    info!(
-        "Processing in chunks of {} samples ({:.3} seconds) with {}% overlap ({:.3} second steps)",
-        chunk_size, chunk_duration, overlap_percent, step_duration
-    );
-
-    // Use a VecDeque to efficiently handle the sliding window of samples
-    let mut sample_buffer = VecDeque::with_capacity(chunk_size * 2);
-    let mut chunk = Vec::with_capacity(chunk_size);
-    let mut fft_buffer = Vec::with_capacity(chunk_size);
-    // Perform FFT on the windowed data for spectral analysis
-    // This is where you would add frequency domain processing for fingerprinting
-
-    let mut planner = FftPlanner::new();
-    let fft = planner.plan_fft_forward(chunk_size);
+    constellation_points_with(source, &FingerprintConfig::legacy())
+}
 
+pub fn constellation_points_with(
+    source: BandpassFilterMonoSource,
+    config: &FingerprintConfig,
+) -> BTreeMap<usize, Vec<ConstellationPoint>> {
+    let mut builder = ConstellationBuilder::new(config, source.sample_rate());
     let mut constellation_points: BTreeMap<usize, Vec<ConstellationPoint>> = BTreeMap::new();
-    let mut chunk_idx = 0_usize;
+    let mut settled = Vec::new();
 
     // Process each sample
     for sample in source {
-        sample_buffer.push_back(Decimal::from(sample));
+        builder.push(sample, &mut settled);
+        for (index, peaks) in settled.drain(..) {
+            constellation_points.entry(index).or_default().extend(peaks);
+        }
+    }
 
-        // When we've filled a chunk
-        if sample_buffer.len() >= chunk_size {
-            // Copy the chunk from the sample buffer
-            chunk.clear();
-            chunk.extend(sample_buffer.range(..chunk_size).cloned());
+    builder.finish(&mut settled);
+    for (index, peaks) in settled {
+        constellation_points.entry(index).or_default().extend(peaks);
+    }
+
+    // After processing all chunks...
+    info!(
+        "Generated {} constellation points from {} chunks",
+        constellation_points.values().flatten().count(),
+        builder.chunks()
+    );
+    constellation_points
+}
 
-            // Process the chunk ---
+/// The STFT and peak picking behind [`constellation_points_with`], fed one
+/// filtered mono sample at a time so audio can also be processed as it arrives.
+pub(crate) struct ConstellationBuilder {
+    chunk_size: usize,
+    step_size: usize,
+    window: Vec<f32>,
+    fft: Arc<dyn rustfft::Fft<f32>>,
+    sample_buffer: VecDeque<f32>,
+    fft_buffer: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+    peak_extractor: PeakExtractor,
+    chunk_idx: usize,
+}
 
-            let current_chunk_size = Decimal::from(hamming_window.len().min(chunk.len()));
-            let frequency_resolution = sample_rate / current_chunk_size;
+impl ConstellationBuilder {
+    pub fn new(config: &FingerprintConfig, sample_rate: u32) -> Self {
+        // Chunk size in samples (for processed mono audio)
+        let chunk_size = config.fft_size;
+        let step_size = config.step_size();
+        let sample_rate = Decimal::from(sample_rate);
 
-            // Apply the Hamming window to the chunk in place
-            apply_hamming_window(&mut chunk, &hamming_window);
+        // Configure overlap
+        let overlap_percent = config.overlap_percent;
+        let overlap_samples = chunk_size - step_size;
+        info!(
+            "Processing in chunks of {} samples with {}% overlap ({} samples)",
+            chunk_size, overlap_percent, overlap_samples
+        );
+        let chunk_duration = Decimal::from(chunk_size) / sample_rate;
+        let step_duration = Decimal::from(step_size) / sample_rate;
 
-            // Apply the FFT to the chunk
-            apply_fft(&mut chunk, fft.clone(), &mut fft_buffer);
+        info!(
+            "Processing in chunks of {} samples ({:.3} seconds) with {}% overlap ({:.3} second steps)",
+            chunk_size, chunk_duration, overlap_percent, step_duration
+        );
 
-            let significant_peaks = significant_peaks(
-                &chunk,
+        let mut planner = FftPlanner::new();
+        let frequency_resolution = sample_rate / Decimal::from(chunk_size);
+        let band = Decimal::from_f32(config.low_cutoff).unwrap_or_default()
+            ..=Decimal::from_f32(config.high_cutoff).unwrap_or_default();
+
+        Self {
+            chunk_size,
+            step_size,
+            // Precompute the window once for this frame size
+            window: config.window.coefficients(chunk_size),
+            fft: planner.plan_fft_forward(chunk_size),
+            // Use a VecDeque to efficiently handle the sliding window of samples
+            sample_buffer: VecDeque::with_capacity(chunk_size * 2),
+            fft_buffer: Vec::with_capacity(chunk_size),
+            magnitudes: Vec::with_capacity(chunk_size / 2 + 1),
+            peak_extractor: PeakExtractor::new(
+                config.peak_picker,
+                config.interpolation,
                 frequency_resolution,
-                Decimal::from(chunk_idx),
                 Decimal::from(step_size),
                 sample_rate,
+                band,
+            ),
+            chunk_idx: 0,
+        }
+    }
+
+    /// Add one sample, appending the `(chunk, peaks)` of every chunk it settles.
+    pub fn push(
+        &mut self,
+        sample: i16,
+        settled: &mut impl Extend<(usize, Vec<ConstellationPoint>)>,
+    ) {
+        self.sample_buffer.push_back(f32::from(sample));
+
+        // When we've filled a chunk
+        if self.sample_buffer.len() >= self.chunk_size {
+            // Window the chunk and run the FFT on it
+            apply_window(
+                self.sample_buffer.range(..self.chunk_size),
+                &self.window,
+                &mut self.fft_buffer,
             );
+            apply_fft(self.fft.clone(), &mut self.fft_buffer, &mut self.magnitudes);
 
-            constellation_points
-                .entry(chunk_idx)
-                .or_default()
-                .extend(significant_peaks);
+            settled.extend(self.peak_extractor.push(self.chunk_idx, &self.magnitudes));
 
-            // ------------------------
             // Remove step_size samples from the front (keeping the overlap portion)
-            for _ in 0..step_size {
-                sample_buffer.pop_front();
-            }
-            chunk_idx += 1;
+            self.sample_buffer.drain(..self.step_size);
+            self.chunk_idx += 1;
         }
     }
 
-    // After processing all chunks...
-    info!(
-        "Generated {} constellation points from {} chunks",
-        constellation_points.values().flatten().count(),
-        chunk_idx
-    );
-    constellation_points
-}
+    /// Settle the chunks still waiting on later ones. A trailing partial chunk
+    /// is dropped, as it always has been.
+    pub fn finish(&mut self, settled: &mut impl Extend<(usize, Vec<ConstellationPoint>)>) {
+        settled.extend(self.peak_extractor.finish());
+    }
 
-fn apply_hamming_window(chunk: &mut [Decimal], hamming_window: &[Decimal]) {
-    chunk
-        .iter_mut()
-        .zip(hamming_window.iter())
-        .for_each(|(sample, &window)| {
-            *sample *= window;
-        });
+    /// Chunks transformed so far.
+    pub fn chunks(&self) -> usize {
+        self.chunk_idx
+    }
 }
 
-fn apply_fft(
-    chunk: &mut Vec<Decimal>,
-    fft: Arc<dyn rustfft::Fft<f32>>,
+fn apply_window<'a>(
+    chunk: impl Iterator<Item = &'a f32>,
+    window: &[f32],
     fft_buffer: &mut Vec<Complex<f32>>,
 ) {
     fft_buffer.clear();
     fft_buffer.extend(
         chunk
-            .iter()
-            .map(|&sample| Complex::new(sample.to_f32().unwrap(), 0.0)),
+            .zip(window.iter())
+            .map(|(&sample, &weight)| Complex::new(sample * weight, 0.0)),
     );
+}
+
+/// Run the FFT in place and write the magnitudes of the non-negative
+/// frequency bins (the upper half mirrors them for real input).
+fn apply_fft(
+    fft: Arc<dyn rustfft::Fft<f32>>,
+    fft_buffer: &mut [Complex<f32>],
+    magnitudes: &mut Vec<f32>,
+) {
     fft.process(fft_buffer);
-    chunk.clear();
-    chunk.extend(
-        fft_buffer
-            .iter()
-            .map(|c| Decimal::from_f32(c.norm()).unwrap()),
-    );
+    magnitudes.clear();
+    magnitudes.extend(fft_buffer[..=fft_buffer.len() / 2].iter().map(|c| c.norm()));
 }
 
-fn significant_peaks(
-    chunk: &[Decimal],
-    frequency_resolution: Decimal,
-    index: Decimal,
-    step_size: Decimal,
-    sample_rate: Decimal,
-) -> Vec<ConstellationPoint> {
-    let max_magnitude = *chunk
-        .iter()
-        .max_by(|a, b| a.partial_cmp(b).unwrap())
-        .unwrap_or(&Decimal::ZERO);
-
-    chunk
-        .windows(5)
-        .enumerate()
-        .filter_map(|(bin, window)| {
-            if window[0] < window[1]
-                && window[1] < window[2]
-                && window[2] > window[3]
-                && window[3] > window[4]
-            {
-                let freq = Decimal::from(bin) * frequency_resolution;
-                // (frequency, magnitude)
-                Some((freq, window[2]))
-            } else {
-                None
-            }
-        })
-        .filter(|(freq, _)| (dec!(20)..=dec!(5000)).contains(freq))
-        .sorted_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap())
-        .take(4)
-        .map(|(freq, magnitude)| {
-            let time = index * step_size / sample_rate;
-            let normalized_magnitude = (magnitude / max_magnitude) * dec!(100.0);
-            ConstellationPoint {
-                time,
-                frequency: freq,
-                magnitude: normalized_magnitude,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{Fingerprint, generate_fingerprints, generate_fingerprints_with};
+    use itertools::Itertools;
+    use rodio::buffer::SamplesBuffer;
+    use rust_decimal_macros::dec;
+    use std::f32::consts::PI;
+
+    fn tone(frequency: f32, seconds: f32) -> BandpassFilterMonoSource {
+        let samples = (0..(11025.0 * seconds) as usize)
+            .map(|i| ((2.0 * PI * frequency * i as f32 / 11025.0).sin() * 8000.0) as i16)
+            .collect_vec();
+        BandpassFilterMonoSource::new(Box::new(SamplesBuffer::new(1, 11025, samples)), 11025)
+    }
+
+    #[test]
+    fn strongest_peak_of_a_pure_tone_is_near_its_frequency() {
+        let points = constellation_points(tone(1000.0, 2.0));
+        let frequency_resolution = dec!(11025) / dec!(4096);
+
+        assert!(!points.is_empty());
+        for peaks in points.values() {
+            let strongest = peaks.iter().max_by_key(|p| p.magnitude).unwrap();
+            assert!((strongest.frequency - dec!(1000)).abs() <= dec!(3) * frequency_resolution);
+            assert_eq!(strongest.magnitude.round(), dec!(100));
+        }
+    }
+
+    #[test]
+    fn interpolated_peaks_land_within_a_fraction_of_a_bin() {
+        // 1000 Hz sits about a third of the way between bins 371 and 372
+        let points = constellation_points_with(tone(1000.0, 2.0), &FingerprintConfig::music());
+
+        for peaks in points.values() {
+            let strongest = peaks.iter().max_by_key(|p| p.magnitude).unwrap();
+            assert!(
+                (strongest.frequency - dec!(1000)).abs() < dec!(0.5),
+                "{}",
+                strongest.frequency
+            );
+        }
+    }
+
+    #[test]
+    fn the_legacy_entry_points_agree_with_each_other() {
+        let points = constellation_points(tone(1000.0, 2.0));
+        let legacy = constellation_points_with(tone(1000.0, 2.0), &FingerprintConfig::legacy());
+        assert_eq!(
+            points.values().flatten().map(|p| p.frequency).collect_vec(),
+            legacy.values().flatten().map(|p| p.frequency).collect_vec()
+        );
+
+        let mut pair = points.values().flatten();
+        let (anchor, target) = (pair.next().unwrap(), pair.next().unwrap());
+        assert_eq!(
+            Fingerprint::from((anchor, target)).hash,
+            Fingerprint::new(anchor, target, &FingerprintConfig::legacy()).hash
+        );
+        let generated = generate_fingerprints(points.clone());
+        assert!(!generated.is_empty());
+        assert_eq!(
+            generated.iter().map(|f| f.hash).collect_vec(),
+            generate_fingerprints_with(points, &FingerprintConfig::legacy())
+                .iter()
+                .map(|f| f.hash)
+                .collect_vec()
+        );
+    }
+
+    #[test]
+    fn points_are_timestamped_by_chunk_step() {
+        let points = constellation_points(tone(440.0, 2.0));
+
+        for (chunk, peaks) in &points {
+            for peak in peaks {
+                assert_eq!(peak.time, Decimal::from(chunk * 2048) / dec!(11025));
             }
-        })
-        .collect()
+        }
+    }
 }
@@ -0,0 +1,167 @@
+use std::f64::consts::PI;
+
+/// Order of each Butterworth edge of [`BandpassFilter`] (24 dB per octave).
+const BUTTERWORTH_ORDER: usize = 4;
+
+/// A second-order IIR section (RBJ cookbook coefficients), run in transposed
+/// direct form II. State is kept in `f64`: at 11,025 Hz a 20 Hz pole sits very
+/// close to the unit circle and `f32` loses too much precision there.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub fn low_pass(sample_rate: u32, cutoff: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, cutoff, q);
+        Self::normalised(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(sample_rate: u32, cutoff: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, cutoff, q);
+        Self::normalised(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let input = f64::from(input);
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output as f32
+    }
+
+    fn prewarp(sample_rate: u32, cutoff: f32, q: f32) -> (f64, f64) {
+        let omega = 2.0 * PI * f64::from(cutoff) / f64::from(sample_rate);
+        (omega.cos(), omega.sin() / (2.0 * f64::from(q)))
+    }
+
+    fn normalised(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+}
+
+/// Butterworth bandpass: a high-pass cascade at `low_cutoff` followed by a
+/// low-pass cascade at `high_cutoff`, both designed for the actual sample rate.
+#[derive(Debug, Clone)]
+pub struct BandpassFilter {
+    sections: Vec<Biquad>,
+}
+
+impl BandpassFilter {
+    /// A `high_cutoff` at or above Nyquist leaves the top of the band open.
+    pub fn new(sample_rate: u32, low_cutoff: f32, high_cutoff: f32) -> Self {
+        let nyquist = sample_rate as f32 / 2.0;
+        let mut sections = Vec::with_capacity(BUTTERWORTH_ORDER);
+        for q in butterworth_q() {
+            sections.push(Biquad::high_pass(sample_rate, low_cutoff, q));
+        }
+        if high_cutoff < nyquist {
+            for q in butterworth_q() {
+                sections.push(Biquad::low_pass(sample_rate, high_cutoff, q));
+            }
+        }
+        Self { sections }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.sections
+            .iter_mut()
+            .fold(input, |sample, section| section.process(sample))
+    }
+}
+
+/// Quality factors of the second-order sections making up a Butterworth filter.
+fn butterworth_q() -> impl Iterator<Item = f32> {
+    (1..=BUTTERWORTH_ORDER / 2).map(|k| {
+        let angle = PI * (2 * k - 1) as f64 / (2 * BUTTERWORTH_ORDER) as f64;
+        (1.0 / (2.0 * angle.cos())) as f32
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gain in dB of a steady sine at `frequency` after the filter has settled.
+    fn gain_db(filter: &mut BandpassFilter, sample_rate: u32, frequency: f32) -> f32 {
+        let samples = (0..sample_rate as usize * 3)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                filter.process((2.0 * std::f32::consts::PI * frequency * t).sin())
+            })
+            .collect::<Vec<_>>();
+        let settled = &samples[sample_rate as usize * 2..];
+        let peak = settled.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn tones_inside_the_band_pass_unchanged() {
+        for (sample_rate, frequency) in [(11025, 100.0), (11025, 1000.0), (44100, 3000.0)] {
+            let mut filter = BandpassFilter::new(sample_rate, 20.0, 5000.0);
+            let gain = gain_db(&mut filter, sample_rate, frequency);
+            assert!(
+                gain.abs() < 0.5,
+                "{frequency} Hz at {sample_rate} Hz: {gain} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn tones_below_the_band_are_attenuated() {
+        for sample_rate in [11025, 44100] {
+            let mut filter = BandpassFilter::new(sample_rate, 20.0, 5000.0);
+            let gain = gain_db(&mut filter, sample_rate, 5.0);
+            assert!(gain < -40.0, "5 Hz at {sample_rate} Hz: {gain} dB");
+        }
+    }
+
+    #[test]
+    fn tones_above_the_band_are_attenuated() {
+        let mut filter = BandpassFilter::new(44100, 20.0, 5000.0);
+        let gain = gain_db(&mut filter, 44100, 20000.0);
+        assert!(gain < -40.0, "20 kHz at 44.1 kHz: {gain} dB");
+
+        let mut filter = BandpassFilter::new(11025, 300.0, 2000.0);
+        let gain = gain_db(&mut filter, 11025, 5000.0);
+        assert!(gain < -40.0, "5 kHz at 11,025 Hz: {gain} dB");
+    }
+
+    #[test]
+    fn cutoffs_are_at_minus_three_db() {
+        let mut filter = BandpassFilter::new(11025, 100.0, 2000.0);
+        let low = gain_db(&mut filter, 11025, 100.0);
+        let mut filter = BandpassFilter::new(11025, 100.0, 2000.0);
+        let high = gain_db(&mut filter, 11025, 2000.0);
+        assert!((low + 3.0).abs() < 0.5, "low edge: {low} dB");
+        assert!((high + 3.0).abs() < 0.5, "high edge: {high} dB");
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
 
-use super::constellation::ConstellationPoint;
-use rust_decimal::{Decimal, MathematicalOps};
+use super::{FingerprintConfig, constellation::ConstellationPoint};
+use rust_decimal::{Decimal, MathematicalOps, prelude::FromPrimitive};
 use rust_decimal_macros::dec;
 use tracing::info;
 
@@ -16,41 +16,16 @@ pub struct Fingerprint {
 }
 
 impl Fingerprint {
-    fn hash(anchor_freq: Decimal, target_freq: Decimal, delta_t: Decimal) -> i64 {
-        // Convert to integers for hashing
-        let a = quantize_frequency(anchor_freq).try_into().unwrap_or(0);
-        let b = quantize_frequency(target_freq).try_into().unwrap_or(0);
-        let dt = (delta_t * dec!(100)).try_into().unwrap_or(0);
-
-        // FNV-1a hash algorithm
-        const FNV_PRIME: u64 = 1099511628211;
-        const FNV_OFFSET: u64 = 14695981039346656037;
-
-        let mut hash = FNV_OFFSET;
-
-        // Hash all three components
-        hash ^= a;
-        hash = hash.wrapping_mul(FNV_PRIME);
-
-        hash ^= b;
-        hash = hash.wrapping_mul(FNV_PRIME);
-
-        hash ^= dt;
-        hash = hash.wrapping_mul(FNV_PRIME);
-
-        hash as i64
-    }
-}
-
-fn confidence(magnitude1: Decimal, magnitude2: Decimal) -> Decimal {
-    // Calculate confidence based on both magnitudes
-    (magnitude1 * magnitude2).sqrt().unwrap_or(Decimal::ZERO)
-}
-
-impl From<(&ConstellationPoint, &ConstellationPoint)> for Fingerprint {
-    fn from((anchor, target): (&ConstellationPoint, &ConstellationPoint)) -> Self {
+    /// The fingerprint of an anchor/target pair, hashed with `config.hash_scheme`.
+    pub fn new(
+        anchor: &ConstellationPoint,
+        target: &ConstellationPoint,
+        config: &FingerprintConfig,
+    ) -> Self {
         let delta_t = target.time - anchor.time;
-        let hash = Self::hash(anchor.frequency, target.frequency, delta_t);
+        let hash = config
+            .hash_scheme
+            .hash(anchor.frequency, target.frequency, delta_t, config);
         let confidence = confidence(anchor.magnitude, target.magnitude);
         // Calculate confidence based on both magnitudes
         Fingerprint {
@@ -64,6 +39,18 @@ impl From<(&ConstellationPoint, &ConstellationPoint)> for Fingerprint {
     }
 }
 
+fn confidence(magnitude1: Decimal, magnitude2: Decimal) -> Decimal {
+    // Calculate confidence based on both magnitudes
+    (magnitude1 * magnitude2).sqrt().unwrap_or(Decimal::ZERO)
+}
+
+/// Hashed as [`FingerprintConfig::legacy`] hashes, like [`generate_fingerprints`].
+impl From<(&ConstellationPoint, &ConstellationPoint)> for Fingerprint {
+    fn from((anchor, target): (&ConstellationPoint, &ConstellationPoint)) -> Self {
+        Fingerprint::new(anchor, target, &FingerprintConfig::legacy())
+    }
+}
+
 impl From<(i64, f64, i64, i64, i64, f64)> for Fingerprint {
     fn from(
         (hash, time_offset, confidence, anchor_freq, target_freq, delta_t): (
@@ -98,74 +85,94 @@ impl From<&Fingerprint> for (i64, f64, i64, i64, i64, f64) {
     }
 }
 
-// Add this function to generate fingerprints
+/// Pair up `points` as [`FingerprintConfig::legacy`] does, as this always
+/// has; see [`generate_fingerprints_with`] for any other configuration.
 pub fn generate_fingerprints(points: BTreeMap<usize, Vec<ConstellationPoint>>) -> Vec<Fingerprint> {
+    generate_fingerprints_with(points, &FingerprintConfig::legacy())
+}
+
+pub fn generate_fingerprints_with(
+    points: BTreeMap<usize, Vec<ConstellationPoint>>,
+    config: &FingerprintConfig,
+) -> Vec<Fingerprint> {
     let mut fingerprints = Vec::new();
-    let offsets = [1, 2, 3, 4, 5, 6, 8, 12];
 
     let chunk_indices: Vec<usize> = points.keys().cloned().collect();
     for current_chunk in chunk_indices {
-        // Get anchor points from current chunk
-        if !points.contains_key(&current_chunk) {
-            continue;
-        }
-        let anchor_points = &points[&current_chunk];
-        if anchor_points.is_empty() {
-            continue;
-        }
-        let mut sorted_anchors = anchor_points.clone();
-        sorted_anchors.sort_by(|a, b| b.magnitude.partial_cmp(&a.magnitude).unwrap());
-
-        // Take only the strongest 2 points from this chunk as anchors
-        let filtered_anchors = sorted_anchors.iter().take(3);
-
-        for anchor in filtered_anchors {
-            let mut pair_count = 0;
-            // Add more musically relevant offsets
-
-            for offset in &offsets {
-                // Apply weight to confidence score
-
-                let target_chunk = current_chunk + offset;
-                // Check if target chunk exists
-                if !points.contains_key(&target_chunk) {
-                    continue;
-                }
-
-                let target_points = &points[&target_chunk];
-                if target_points.is_empty() {
-                    continue;
-                }
-
-                // Find strongest peak in target chunk
-                let target = target_points
-                    .iter()
-                    .max_by(|a, b| a.magnitude.partial_cmp(&b.magnitude).unwrap())
-                    .unwrap();
-
-                if !is_harmonically_related(anchor.frequency, target.frequency) {
-                    continue;
-                }
-                let confidence = confidence(anchor.magnitude, target.magnitude);
-                if confidence < dec!(40) {
-                    continue;
-                }
-
-                let fingerprint = Fingerprint::from((anchor, target));
-                fingerprints.push(fingerprint);
-                pair_count += 1;
-
-                // Limit number of fingerprints per anchor
-                if pair_count >= 3 {
-                    break;
-                }
-            }
-        }
+        pair_anchors(&points, current_chunk, config, &mut fingerprints);
     }
     info!("Generated {} fingerprints", fingerprints.len());
     fingerprints
 }
 
+/// Append the fingerprints anchored in `current_chunk`. Targets come from the
+/// chunks `config.target_offsets` after it, which must already be in `points`.
+pub(crate) fn pair_anchors(
+    points: &BTreeMap<usize, Vec<ConstellationPoint>>,
+    current_chunk: usize,
+    config: &FingerprintConfig,
+    fingerprints: &mut Vec<Fingerprint>,
+) {
+    let min_pair_confidence = Decimal::from_f32(config.min_pair_confidence).unwrap_or_default();
+
+    // Get anchor points from current chunk
+    let Some(anchor_points) = points.get(&current_chunk) else {
+        return;
+    };
+    if anchor_points.is_empty() {
+        return;
+    }
+    let mut sorted_anchors = anchor_points.clone();
+    sorted_anchors.sort_by(|a, b| b.magnitude.partial_cmp(&a.magnitude).unwrap());
+
+    // Take only the strongest points from this chunk as anchors
+    let filtered_anchors = sorted_anchors.iter().take(config.anchors_per_frame);
+
+    for anchor in filtered_anchors {
+        let mut pair_count = 0;
+        // Add more musically relevant offsets
+
+        for offset in &config.target_offsets {
+            // Apply weight to confidence score
+
+            let target_chunk = current_chunk + offset;
+            // Check if target chunk exists
+            if !points.contains_key(&target_chunk) {
+                continue;
+            }
+
+            let target_points = &points[&target_chunk];
+            if target_points.is_empty() {
+                continue;
+            }
+
+            // Find strongest peak in target chunk
+            let target = target_points
+                .iter()
+                .max_by(|a, b| a.magnitude.partial_cmp(&b.magnitude).unwrap())
+                .unwrap();
+
+            if config.harmonic_pairs && !is_harmonically_related(anchor.frequency, target.frequency)
+            {
+                continue;
+            }
+            let confidence = confidence(anchor.magnitude, target.magnitude);
+            if confidence < min_pair_confidence {
+                continue;
+            }
+
+            let fingerprint = Fingerprint::new(anchor, target, config);
+            fingerprints.push(fingerprint);
+            pair_count += 1;
+
+            // Limit number of fingerprints per anchor
+            if pair_count >= config.pairs_per_anchor {
+                break;
+            }
+        }
+    }
+}
+
 // Helper function to check if two frequencies have a meaningful musical relationship
 fn is_harmonically_related(f1: Decimal, f2: Decimal) -> bool {
     // Common musical intervals (in frequency ratios)
@@ -194,21 +201,3 @@ fn is_harmonically_related(f1: Decimal, f2: Decimal) -> bool {
     // Also check if they're in similar frequency bands
     (f2 - f1).abs() < dec!(20)
 }
-
-//f32 -> u32
-// Adaptive frequency quantization
-fn quantize_frequency(freq: Decimal) -> Decimal {
-    if freq <= Decimal::ZERO {
-        // Handle edge case
-        Decimal::ZERO
-    } else if freq < dec!(300) {
-        // Fine resolution for bass frequencies (5Hz bins)
-        (freq / dec!(5)).round()
-    } else if freq < dec!(1000) {
-        // Medium resolution for mid-range (10Hz bins)
-        (freq / dec!(10)).round()
-    } else {
-        // Coarser resolution for high frequencies (20Hz bins)
-        (freq / dec!(20)).round()
-    }
-}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use rodio::{Decoder, Source};
+use std::{
+    cell::Cell,
+    io::{Read, Seek},
+    rc::Rc,
+    time::Duration,
+};
+
+use super::{
+    BandpassFilterMonoSource, Fingerprint, FingerprintConfig, StreamingFingerprinter,
+    constellation_points_with, generate_fingerprints_with,
+};
+
+/// Runs the full pipeline from encoded audio to catalogue fingerprints:
+/// decode, mono downmix + bandpass + downsample, constellation map, hashing.
+#[derive(Debug, Clone, Default)]
+pub struct Fingerprinter {
+    config: FingerprintConfig,
+}
+
+impl Fingerprinter {
+    pub fn new(config: FingerprintConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &FingerprintConfig {
+        &self.config
+    }
+
+    /// Decode `reader` without fingerprinting it, so callers can trim the source
+    /// (`skip_duration`/`take_duration`) or read its duration first.
+    pub fn decode<R>(reader: R) -> Result<Box<dyn Source<Item = i16>>>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        Ok(Box::new(Decoder::new(reader)?))
+    }
+
+    pub fn fingerprint<R>(&self, reader: R) -> Result<Vec<Fingerprint>>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        Ok(self.fingerprint_source(Self::decode(reader)?))
+    }
+
+    pub fn fingerprint_source(&self, source: Box<dyn Source<Item = i16>>) -> Vec<Fingerprint> {
+        let source = BandpassFilterMonoSource::with_cutoffs(
+            source,
+            self.config.sample_rate,
+            self.config.low_cutoff,
+            self.config.high_cutoff,
+        );
+        generate_fingerprints_with(
+            constellation_points_with(source, &self.config),
+            &self.config,
+        )
+    }
+
+    /// Like [`Fingerprinter::fingerprint_source`], also returning the length
+    /// of the audio in seconds. The length is measured as the audio is read:
+    /// decoders' own estimates are missing for some formats and off by
+    /// seconds for others.
+    pub fn fingerprint_with_duration(
+        &self,
+        source: Box<dyn Source<Item = i16>>,
+    ) -> (Vec<Fingerprint>, f64) {
+        let samples = Rc::new(Cell::new(0_u64));
+        let frame_rate = f64::from(source.sample_rate()) * f64::from(source.channels().max(1));
+        let fingerprints = self.fingerprint_source(Box::new(Counted {
+            source,
+            samples: samples.clone(),
+        }));
+        (fingerprints, samples.get() as f64 / frame_rate)
+    }
+
+    /// Fingerprint audio as it arrives, `channels` interleaved at `sample_rate` Hz.
+    pub fn streaming(&self, sample_rate: u32, channels: u16) -> StreamingFingerprinter {
+        StreamingFingerprinter::new(self.config.clone(), sample_rate, channels)
+    }
+}
+
+/// Counts the samples read through it.
+struct Counted {
+    source: Box<dyn Source<Item = i16>>,
+    samples: Rc<Cell<u64>>,
+}
+
+impl Iterator for Counted {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.source.next()?;
+        self.samples.set(self.samples.get() + 1);
+        Some(sample)
+    }
+}
+
+impl Source for Counted {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
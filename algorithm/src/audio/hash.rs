@@ -0,0 +1,437 @@
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use super::FingerprintConfig;
+
+/// How an anchor/target pair is reduced to the hash looked up in the catalogue.
+///
+/// Every stored fingerprint records the [`version`](HashScheme::version) of the
+/// scheme that produced it, so a catalogue can be rehashed from one scheme to
+/// another (see [`crate::model::migrate_hash_scheme`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashScheme {
+    /// FNV-1a over the adaptively quantised frequencies and delta-t in
+    /// hundredths of a second. Nothing can be recovered from the hash and
+    /// unrelated pairs may collide.
+    #[default]
+    Fnv1a,
+    /// Anchor bin, target bin and delta-t in frames bit-packed into a `u32`, as
+    /// in the original Shazam paper. See [`PackedHash`].
+    Packed,
+    /// The octave band of the anchor, the log-frequency distance from anchor
+    /// to target and delta-t in frames. A pitch shift moves both peaks by the
+    /// same ratio, so it leaves the hash unchanged unless the anchor crosses
+    /// into another octave band. See [`PitchInvariantHash`].
+    PitchInvariant,
+}
+
+impl HashScheme {
+    /// Identifier stored with each fingerprint.
+    pub const fn version(self) -> i64 {
+        match self {
+            HashScheme::Fnv1a => 1,
+            HashScheme::Packed => 2,
+            HashScheme::PitchInvariant => 3,
+        }
+    }
+
+    pub fn from_version(version: i64) -> Option<Self> {
+        match version {
+            1 => Some(HashScheme::Fnv1a),
+            2 => Some(HashScheme::Packed),
+            3 => Some(HashScheme::PitchInvariant),
+            _ => None,
+        }
+    }
+
+    /// Hash of a pair whose anchor is at `anchor_freq`, target at `target_freq`
+    /// and `delta_t` seconds after it.
+    pub fn hash(
+        self,
+        anchor_freq: Decimal,
+        target_freq: Decimal,
+        delta_t: Decimal,
+        config: &FingerprintConfig,
+    ) -> i64 {
+        match self {
+            HashScheme::Fnv1a => fnv1a(anchor_freq, target_freq, delta_t),
+            HashScheme::Packed => {
+                i64::from(PackedHash::new(anchor_freq, target_freq, delta_t, config).pack())
+            }
+            HashScheme::PitchInvariant => {
+                i64::from(PitchInvariantHash::new(anchor_freq, target_freq, delta_t, config).pack())
+            }
+        }
+    }
+}
+
+/// The fields of a [`HashScheme::Packed`] hash. From the most significant bit:
+/// 11 bits of anchor bin, 11 bits of target bin and 10 bits of delta-t in STFT
+/// frames. Values too large for their field saturate.
+///
+/// Bins are shifted right until the top bin of the band fits in 11 bits, so
+/// FFTs above 4096 points share each packed bin between neighbouring FFT bins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedHash {
+    pub anchor_bin: u32,
+    pub target_bin: u32,
+    pub delta_frames: u32,
+}
+
+impl PackedHash {
+    const BIN_BITS: u32 = 11;
+    const DELTA_BITS: u32 = 10;
+
+    /// Quantise a pair to the bin and frame grid of `config`.
+    pub fn new(
+        anchor_freq: Decimal,
+        target_freq: Decimal,
+        delta_t: Decimal,
+        config: &FingerprintConfig,
+    ) -> Self {
+        let sample_rate = Decimal::from(config.sample_rate);
+        let frequency_resolution = sample_rate / Decimal::from(config.fft_size);
+        let shift = bin_shift(config);
+        let bin = |freq: Decimal| to_u32((freq / frequency_resolution).round()) >> shift;
+
+        Self {
+            anchor_bin: bin(anchor_freq),
+            target_bin: bin(target_freq),
+            delta_frames: delta_frames(delta_t, config),
+        }
+    }
+
+    pub fn pack(self) -> u32 {
+        let bin_max = (1 << Self::BIN_BITS) - 1;
+        let delta_max = (1 << Self::DELTA_BITS) - 1;
+        (self.anchor_bin.min(bin_max) << (Self::BIN_BITS + Self::DELTA_BITS))
+            | (self.target_bin.min(bin_max) << Self::DELTA_BITS)
+            | self.delta_frames.min(delta_max)
+    }
+
+    pub fn unpack(hash: u32) -> Self {
+        let bin_mask = (1 << Self::BIN_BITS) - 1;
+        let delta_mask = (1 << Self::DELTA_BITS) - 1;
+        Self {
+            anchor_bin: (hash >> (Self::BIN_BITS + Self::DELTA_BITS)) & bin_mask,
+            target_bin: (hash >> Self::DELTA_BITS) & bin_mask,
+            delta_frames: hash & delta_mask,
+        }
+    }
+}
+
+/// The fields of a [`HashScheme::PitchInvariant`] hash. From the most
+/// significant bit: 4 bits of anchor octave band, 11 bits of log-frequency
+/// distance from anchor to target and 10 bits of delta-t in STFT frames.
+///
+/// Octave bands count up from `low_cutoff`. The distance is in steps of
+/// [`PitchInvariantHash::STEPS_PER_OCTAVE`], stored offset so that targets
+/// below the anchor stay positive. Values too large for their field saturate.
+/// A speed change also scales delta-t, so speed-changed copies only keep their
+/// hashes while the frame offsets round to the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PitchInvariantHash {
+    pub anchor_band: u32,
+    /// `log2(target / anchor)` in steps.
+    pub log_ratio: i32,
+    pub delta_frames: u32,
+}
+
+impl PitchInvariantHash {
+    /// Quarter semitones: coarse enough to absorb the ~1% bin quantisation of
+    /// low peaks, fine enough to tell a minor from a major third.
+    pub const STEPS_PER_OCTAVE: f64 = 48.0;
+    const BAND_BITS: u32 = 4;
+    const RATIO_BITS: u32 = 11;
+    const DELTA_BITS: u32 = 10;
+    const RATIO_OFFSET: i32 = 1 << (Self::RATIO_BITS - 1);
+
+    /// Quantise a pair for `config`.
+    pub fn new(
+        anchor_freq: Decimal,
+        target_freq: Decimal,
+        delta_t: Decimal,
+        config: &FingerprintConfig,
+    ) -> Self {
+        let [anchor, target] =
+            [anchor_freq, target_freq].map(|f| f.to_f64().unwrap_or(0.0).max(1.0));
+        let base = f64::from(config.low_cutoff).max(1.0);
+        Self {
+            anchor_band: (anchor / base).log2().floor().max(0.0) as u32,
+            log_ratio: ((target / anchor).log2() * Self::STEPS_PER_OCTAVE).round() as i32,
+            delta_frames: delta_frames(delta_t, config),
+        }
+    }
+
+    pub fn pack(self) -> u32 {
+        let band_max = (1 << Self::BAND_BITS) - 1;
+        let ratio_max = (1 << Self::RATIO_BITS) - 1;
+        let delta_max = (1 << Self::DELTA_BITS) - 1;
+        let ratio = (self.log_ratio + Self::RATIO_OFFSET).clamp(0, ratio_max) as u32;
+        (self.anchor_band.min(band_max) << (Self::RATIO_BITS + Self::DELTA_BITS))
+            | (ratio << Self::DELTA_BITS)
+            | self.delta_frames.min(delta_max)
+    }
+
+    pub fn unpack(hash: u32) -> Self {
+        let band_mask = (1 << Self::BAND_BITS) - 1;
+        let ratio_mask = (1 << Self::RATIO_BITS) - 1;
+        let delta_mask = (1 << Self::DELTA_BITS) - 1;
+        Self {
+            anchor_band: (hash >> (Self::RATIO_BITS + Self::DELTA_BITS)) & band_mask,
+            log_ratio: ((hash >> Self::DELTA_BITS) & ratio_mask) as i32 - Self::RATIO_OFFSET,
+            delta_frames: hash & delta_mask,
+        }
+    }
+}
+
+/// `delta_t` in STFT frames of `config`, to the nearest frame.
+fn delta_frames(delta_t: Decimal, config: &FingerprintConfig) -> u32 {
+    let frames = delta_t * Decimal::from(config.sample_rate) / Decimal::from(config.step_size());
+    to_u32(frames.round())
+}
+
+/// Right shift that brings the highest bin of the band under `2^BIN_BITS`.
+fn bin_shift(config: &FingerprintConfig) -> u32 {
+    let nyquist = config.sample_rate as f32 / 2.0;
+    let top_bin = (config.high_cutoff.min(nyquist) * config.fft_size as f32
+        / config.sample_rate as f32)
+        .ceil() as u32;
+    let mut shift = 0;
+    while top_bin >> shift >= 1 << PackedHash::BIN_BITS {
+        shift += 1;
+    }
+    shift
+}
+
+fn to_u32(value: Decimal) -> u32 {
+    value.to_u32().unwrap_or(0)
+}
+
+fn fnv1a(anchor_freq: Decimal, target_freq: Decimal, delta_t: Decimal) -> i64 {
+    // Convert to integers for hashing
+    let a = quantize_frequency(anchor_freq).try_into().unwrap_or(0);
+    let b = quantize_frequency(target_freq).try_into().unwrap_or(0);
+    let dt = (delta_t * dec!(100)).try_into().unwrap_or(0);
+
+    // FNV-1a hash algorithm
+    const FNV_PRIME: u64 = 1099511628211;
+    const FNV_OFFSET: u64 = 14695981039346656037;
+
+    let mut hash = FNV_OFFSET;
+
+    // Hash all three components
+    hash ^= a;
+    hash = hash.wrapping_mul(FNV_PRIME);
+
+    hash ^= b;
+    hash = hash.wrapping_mul(FNV_PRIME);
+
+    hash ^= dt;
+    hash = hash.wrapping_mul(FNV_PRIME);
+
+    hash as i64
+}
+
+//f32 -> u32
+// Adaptive frequency quantization
+fn quantize_frequency(freq: Decimal) -> Decimal {
+    if freq <= Decimal::ZERO {
+        // Handle edge case
+        Decimal::ZERO
+    } else if freq < dec!(300) {
+        // Fine resolution for bass frequencies (5Hz bins)
+        (freq / dec!(5)).round()
+    } else if freq < dec!(1000) {
+        // Medium resolution for mid-range (10Hz bins)
+        (freq / dec!(10)).round()
+    } else {
+        // Coarser resolution for high frequencies (20Hz bins)
+        (freq / dec!(20)).round()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::{ConstellationPoint, generate_fingerprints_with},
+        rng::XorShift,
+    };
+    use itertools::Itertools;
+    use rust_decimal::prelude::FromPrimitive;
+    use std::collections::{BTreeMap, HashSet};
+
+    #[test]
+    fn fnv1a_hashes_are_unchanged() {
+        // Catalogues built before hash schemes existed must still match
+        let hash = HashScheme::Fnv1a.hash(
+            dec!(441.43),
+            dec!(662.15),
+            dec!(0.743),
+            &FingerprintConfig::default(),
+        );
+        assert_eq!(hash, -1_881_651_320_642_845_005);
+    }
+
+    #[test]
+    fn packed_hashes_round_trip() {
+        let packed = PackedHash {
+            anchor_bin: 1857,
+            target_bin: 7,
+            delta_frames: 12,
+        };
+        assert_eq!(PackedHash::unpack(packed.pack()), packed);
+
+        let hash = HashScheme::Packed.hash(
+            dec!(1000),
+            dec!(1500),
+            dec!(2048) / dec!(11025) * dec!(3),
+            &FingerprintConfig::music(),
+        );
+        assert_eq!(
+            PackedHash::unpack(hash as u32),
+            PackedHash {
+                anchor_bin: 372,
+                target_bin: 557,
+                delta_frames: 3
+            }
+        );
+    }
+
+    #[test]
+    fn packed_fields_saturate_instead_of_overflowing() {
+        let packed = PackedHash {
+            anchor_bin: 5000,
+            target_bin: 3,
+            delta_frames: 2000,
+        };
+        assert_eq!(
+            PackedHash::unpack(packed.pack()),
+            PackedHash {
+                anchor_bin: 2047,
+                target_bin: 3,
+                delta_frames: 1023
+            }
+        );
+    }
+
+    #[test]
+    fn large_ffts_shift_bins_to_fit() {
+        let config = FingerprintConfig {
+            fft_size: 16384,
+            ..FingerprintConfig::music()
+        };
+        // 5 kHz is bin 7431 of a 16384-point FFT; two shifts bring it under 2048
+        assert_eq!(bin_shift(&config), 2);
+        assert_eq!(bin_shift(&FingerprintConfig::music()), 0);
+
+        let top = PackedHash::new(dec!(5000), dec!(5000), dec!(0), &config);
+        assert_eq!(top.anchor_bin, 7430 >> 2);
+    }
+
+    #[test]
+    fn schemes_are_identified_by_version() {
+        for scheme in [
+            HashScheme::Fnv1a,
+            HashScheme::Packed,
+            HashScheme::PitchInvariant,
+        ] {
+            assert_eq!(HashScheme::from_version(scheme.version()), Some(scheme));
+        }
+        assert_eq!(HashScheme::from_version(0), None);
+    }
+
+    #[test]
+    fn pitch_invariant_hashes_round_trip() {
+        for log_ratio in [-400, -1, 0, 23, 400] {
+            let hash = PitchInvariantHash {
+                anchor_band: 5,
+                log_ratio,
+                delta_frames: 8,
+            };
+            assert_eq!(PitchInvariantHash::unpack(hash.pack()), hash);
+        }
+    }
+
+    #[test]
+    fn pitch_invariant_hashes_ignore_a_shared_pitch_shift() {
+        let config = FingerprintConfig {
+            hash_scheme: HashScheme::PitchInvariant,
+            ..FingerprintConfig::music()
+        };
+        let semitone = 2_f64.powf(1.0 / 12.0);
+        let hash = |anchor: f64, target: f64| {
+            let [a, t] = [anchor, target].map(|f| Decimal::from_f64(f).unwrap());
+            HashScheme::PitchInvariant.hash(a, t, dec!(0.557), &config)
+        };
+
+        // A fifth above 440 Hz, and the same interval a semitone up
+        assert_eq!(hash(440.0, 660.0), hash(440.0 * semitone, 660.0 * semitone));
+        // A different interval from the same anchor
+        assert_ne!(hash(440.0, 660.0), hash(440.0, 440.0 * 1.25));
+        // Absolute-frequency schemes see unrelated pairs
+        let packed = |anchor: f64, target: f64| {
+            let [a, t] = [anchor, target].map(|f| Decimal::from_f64(f).unwrap());
+            HashScheme::Packed.hash(a, t, dec!(0.557), &config)
+        };
+        assert_ne!(
+            packed(440.0, 660.0),
+            packed(440.0 * semitone, 660.0 * semitone)
+        );
+    }
+
+    #[test]
+    fn pitch_shifted_constellations_share_their_hashes() {
+        let config = FingerprintConfig {
+            hash_scheme: HashScheme::PitchInvariant,
+            harmonic_pairs: false,
+            ..FingerprintConfig::music()
+        };
+        let mut random = XorShift::new(0x1234_5678);
+        let mut next = move || random.next_f64();
+        // Peaks kept inside one octave band (330-560 Hz above 20 Hz) so the
+        // shift cannot move an anchor into the next band
+        let frames = (0..40)
+            .map(|frame| {
+                let peaks = (0..4)
+                    .map(|_| (330.0 + next() * 230.0, 40.0 + next() * 60.0))
+                    .collect_vec();
+                (frame, peaks)
+            })
+            .collect_vec();
+        let constellation = |shift: f64| {
+            frames
+                .iter()
+                .map(|(frame, peaks)| {
+                    let points = peaks
+                        .iter()
+                        .map(|&(frequency, magnitude)| ConstellationPoint {
+                            time: Decimal::from(*frame) * dec!(2048) / dec!(11025),
+                            frequency: Decimal::from_f64(frequency * shift).unwrap(),
+                            magnitude: Decimal::from_f64(magnitude).unwrap(),
+                        })
+                        .collect();
+                    (*frame, points)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let hashes = |shift: f64| {
+            generate_fingerprints_with(constellation(shift), &config)
+                .into_iter()
+                .map(|f| f.hash)
+                .collect::<HashSet<_>>()
+        };
+
+        let original = hashes(1.0);
+        let shifted = hashes(2_f64.powf(1.0 / 12.0));
+
+        let shared = original.intersection(&shifted).count() as f64 / original.len() as f64;
+        assert!(
+            shared > 0.9,
+            "only {shared:.2} of hashes survived the shift"
+        );
+    }
+}
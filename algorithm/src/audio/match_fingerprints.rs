@@ -1,83 +1,621 @@
 use itertools::Itertools;
-use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
+use rust_decimal::prelude::ToPrimitive;
 use std::collections::HashMap;
 
-use super::Fingerprint;
+use super::{CatalogueStats, Fingerprint, significance};
 
 #[derive(Debug, Clone)]
 pub struct MatchResult {
     pub song_id: i64,
+    /// Share of the query fingerprints in the winning offset bins.
     pub confidence: f32,
+    /// Fingerprints in the winning offset bin and its neighbours.
     pub matched_count: usize,
     pub time_offset: f32, // How many seconds into the song the query starts
+    /// Playback speed of the query relative to the song: 1.05 means the query
+    /// plays 5% fast. Always 1 with [`MatchMode::Offset`].
+    pub speed: f32,
+    /// Counts of the bins from `neighbour_bins` below the winning bin to
+    /// `neighbour_bins` above it; the winning bin is in the middle.
+    pub bin_counts: Vec<usize>,
+    /// Probability that a song sharing only chance hashes with the query would
+    /// line up at least `matched_count` of them at some offset.
+    pub p_value: f64,
+    /// Expected number of songs in the catalogue matching at least this well by
+    /// chance: `p_value` times the number of songs. Comparable across query
+    /// lengths and catalogue sizes, unlike `confidence`.
+    pub false_positives: f64,
 }
 
-pub fn match_fingerprints(
-    query_fingerprints: &[Fingerprint],
-    potental_matches: HashMap<i64, Vec<Fingerprint>>,
-) -> Vec<MatchResult> {
-    let mut results = Vec::new();
+/// How the query's timeline is assumed to relate to the song's.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MatchMode {
+    /// The query plays at the song's speed: song time = offset + query time.
+    #[default]
+    Offset,
+    /// The query may be sped up or slowed down: song time = offset + speed ×
+    /// query time. Every speed from `min_speed` to `max_speed` in steps of
+    /// `speed_step` gets its own offset histogram (a Hough transform over offset
+    /// and speed). The best line is then refined by least squares over the
+    /// hits that voted for it, so stray hits do not pull the fit.
+    Stretch {
+        min_speed: f32,
+        max_speed: f32,
+        speed_step: f32,
+    },
+}
 
-    for (song_id, song_fingerprints) in potental_matches {
-        // Create a hash map to track all the query hashes for fast lookup
-        let query_hash_map: HashMap<i64, Vec<&Fingerprint>> = query_fingerprints
-            .iter()
-            .chunk_by(|fp| fp.hash)
-            .into_iter()
-            .map(|(hash, group)| (hash, group.collect()))
-            .collect();
+impl MatchMode {
+    /// ±10% in 0.5% steps, enough for broadcast speed-ups. At that step a
+    /// 25 s query drifts at most ~60 ms from the nearest candidate line, well
+    /// inside the default offset bins.
+    pub fn stretch() -> Self {
+        MatchMode::Stretch {
+            min_speed: 0.9,
+            max_speed: 1.1,
+            speed_step: 0.005,
+        }
+    }
+}
 
-        // Track time offsets - the key insight of the Shazam algorithm
-        let mut time_offsets = HashMap::new();
-        let mut best_offset_count = 0;
-        let mut best_offset = Decimal::ZERO;
+/// Scores candidate songs by how many of their fingerprints line up with the
+/// query at a single time offset.
+///
+/// Each pair of matching hashes votes for the offset between the song and the
+/// query, in bins `bin_width` seconds wide. A bin scores its own votes plus
+/// those of `neighbour_bins` bins either side, so a match whose offsets jitter
+/// across a bin boundary is not split in two.
+///
+/// A song is reported when its best offset is unlikely to be chance. Hits
+/// outside the winning bins show how many chance hits the song attracts; with
+/// `catalogue` set, the catalogue's hash reuse sets a floor under that, so a
+/// handful of aligned hits with no background is not taken on faith.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    /// Minimum number of fingerprints that must agree on the best offset.
+    pub min_matches: usize,
+    /// Largest [`MatchResult::false_positives`] reported.
+    pub max_false_positives: f64,
+    /// Minimum share of the query fingerprints that must agree on the best
+    /// offset. Still applied, but 0 by default: significance is judged by
+    /// `max_false_positives` now.
+    #[deprecated(note = "judge matches by `max_false_positives` instead")]
+    pub min_confidence: f32,
+    /// The catalogue being searched. Without it the candidate songs stand in for
+    /// the catalogue and a song's latest hit for its duration.
+    pub catalogue: Option<CatalogueStats>,
+    /// Width of the offset histogram bins, in seconds.
+    pub bin_width: f32,
+    /// Bins either side of a bin counted towards its score.
+    pub neighbour_bins: usize,
+    pub mode: MatchMode,
+}
 
-        // For each fingerprint in the song
-        for song_fp in &song_fingerprints {
-            // Find matching query fingerprints with the same hash
-            if let Some(matching_query_fps) = query_hash_map.get(&song_fp.hash) {
-                for query_fp in matching_query_fps {
-                    // Calculate time delta: how far into the song did our query start?
-                    let offset = song_fp.time_offset - query_fp.time_offset;
+impl Default for Matcher {
+    #[allow(deprecated)]
+    fn default() -> Self {
+        Self {
+            min_matches: 3,
+            max_false_positives: 0.01,
+            min_confidence: 0.0,
+            catalogue: None,
+            bin_width: 0.1,
+            neighbour_bins: 1,
+            mode: MatchMode::Offset,
+        }
+    }
+}
 
-                    // Round to nearest 0.1s to allow for small timing differences
+/// Votes for one offset bin.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bin {
+    count: usize,
+    /// Sum of the offsets voting for the bin, for their mean.
+    offset_sum: f32,
+}
 
-                    let bucket = (offset * dec!(10)) / dec!(10);
+impl Matcher {
+    pub fn identify(
+        &self,
+        query_fingerprints: &[Fingerprint],
+        potential_matches: HashMap<i64, Vec<Fingerprint>>,
+    ) -> Vec<MatchResult> {
+        let mut results = Vec::new();
 
-                    // Count fingerprints with this offset
-                    let count = time_offsets.entry(bucket).or_insert(0);
-                    *count += 1;
+        // Create a hash map to track all the query hashes for fast lookup
+        let query_hash_map: HashMap<i64, Vec<&Fingerprint>> =
+            query_fingerprints.iter().into_group_map_by(|fp| fp.hash);
+        let query_duration = query_fingerprints
+            .iter()
+            .filter_map(|fp| fp.time_offset.to_f32())
+            .minmax()
+            .into_option()
+            .map_or(0.0, |(first, last)| last - first);
+        let songs = self
+            .catalogue
+            .map_or(potential_matches.len(), |c| c.songs.max(1));
 
-                    // Track the best offset found
-                    if *count > best_offset_count {
-                        best_offset_count = *count;
-                        best_offset = bucket;
+        for (song_id, song_fingerprints) in potential_matches {
+            // (query time, song time) of every pair of matching hashes
+            let mut hits = Vec::new();
+
+            // For each fingerprint in the song
+            for song_fp in &song_fingerprints {
+                // Find matching query fingerprints with the same hash
+                if let Some(matching_query_fps) = query_hash_map.get(&song_fp.hash) {
+                    for query_fp in matching_query_fps {
+                        hits.push((
+                            query_fp.time_offset.to_f32().unwrap_or_default(),
+                            song_fp.time_offset.to_f32().unwrap_or_default(),
+                        ));
                     }
                 }
             }
+
+            results.extend(self.score(
+                song_id,
+                &hits,
+                query_fingerprints.len(),
+                query_duration,
+                songs,
+            ));
         }
 
+        // Most significant first; strong matches all have a p-value of ~0
+        results.sort_by(|a, b| {
+            a.p_value
+                .total_cmp(&b.p_value)
+                .then(b.matched_count.cmp(&a.matched_count))
+        });
+
+        results
+    }
+
+    /// The match of one song from its `hits` against a query of `query_len`
+    /// fingerprints spanning `query_duration` seconds, searched among `songs`
+    /// songs, if it is unlikely to be chance.
+    pub(crate) fn score(
+        &self,
+        song_id: i64,
+        hits: &[(f32, f32)],
+        query_len: usize,
+        query_duration: f32,
+        songs: usize,
+    ) -> Option<MatchResult> {
+        let result = match self.mode {
+            MatchMode::Offset => self.best_offset(song_id, hits, 1.0),
+            MatchMode::Stretch {
+                min_speed,
+                max_speed,
+                speed_step,
+            } => self.best_line(song_id, hits, min_speed, max_speed, speed_step),
+        }?;
+
         // Calculate confidence
-        let confidence = best_offset_count as f32 / query_fingerprints.len() as f32;
+        let confidence = result.matched_count as f32 / query_len as f32;
+        let p_value = self.p_value(&result, hits, query_len, query_duration);
+        let false_positives = p_value * songs as f64;
 
-        // Only consider songs with reasonable match count
-        if best_offset_count >= 3 && confidence > 0.05 {
-            results.push(MatchResult {
-                song_id,
+        // Only consider songs that are unlikely to be chance
+        #[allow(deprecated)]
+        let min_confidence = self.min_confidence;
+        (result.matched_count >= self.min_matches
+            && false_positives <= self.max_false_positives
+            && confidence >= min_confidence)
+            .then_some(MatchResult {
                 confidence,
-                matched_count: best_offset_count,
-                time_offset: best_offset.try_into().unwrap(),
-            });
+                p_value,
+                false_positives,
+                ..result
+            })
+    }
+
+    /// Significance of `result`, the best offset among a song's `hits` for a
+    /// query of `query_len` fingerprints spanning `query_duration` seconds.
+    fn p_value(
+        &self,
+        result: &MatchResult,
+        hits: &[(f32, f32)],
+        query_len: usize,
+        query_duration: f32,
+    ) -> f64 {
+        let bin_width = f64::from(self.bin_width);
+        let window = (2 * self.neighbour_bins + 1) as f64 * bin_width;
+        let latest_hit = hits
+            .iter()
+            .map(|&(_, song_time)| song_time)
+            .fold(0.0, f32::max);
+        let song_duration = self
+            .catalogue
+            .map_or(latest_hit, |c| c.mean_duration.max(latest_hit));
+        // The query may start anywhere from just before the song to its end
+        let span = (f64::from(song_duration + query_duration)).max(window);
+        let speeds = match self.mode {
+            MatchMode::Offset => 1.0,
+            MatchMode::Stretch {
+                min_speed,
+                max_speed,
+                speed_step,
+            } => f64::from(((max_speed - min_speed) / speed_step).round() + 1.0),
+        };
+        let windows = (span / bin_width).ceil() * speeds;
+
+        // Chance hits seen outside the winning bins, plus one so that a song
+        // with no background is not certain
+        let background =
+            (hits.len() - result.matched_count + 1) as f64 * window / (span - window).max(window);
+        let catalogue = self
+            .catalogue
+            .map_or(0.0, |c| c.expected_random_hits(query_len) * window / span);
+        significance::p_value(result.matched_count, background.max(catalogue), windows)
+    }
+
+    fn bin_index(&self, offset: f32) -> i64 {
+        (offset / self.bin_width).round() as i64
+    }
+
+    /// Offset histogram of `hits` for a query playing at `speed`.
+    fn histogram(&self, hits: &[(f32, f32)], speed: f32) -> HashMap<i64, Bin> {
+        let mut histogram: HashMap<i64, Bin> = HashMap::new();
+        for &(query_time, song_time) in hits {
+            // Calculate time delta: how far into the song did our query start?
+            let offset = song_time - speed * query_time;
+            let bin = histogram.entry(self.bin_index(offset)).or_default();
+            bin.count += 1;
+            bin.offset_sum += offset;
         }
+        histogram
     }
 
-    // Sort results by confidence (best matches first)
-    results.sort_by(|a, b| {
-        b.confidence
-            .partial_cmp(&a.confidence)
-            .unwrap_or(std::cmp::Ordering::Equal)
+    /// The bin whose neighbourhood holds the most votes at `speed`, with
+    /// `confidence` and significance left unset. Ties go to the bin with more votes of its own,
+    /// then the earlier offset, so the result does not depend on hash map order.
+    fn best_offset(&self, song_id: i64, hits: &[(f32, f32)], speed: f32) -> Option<MatchResult> {
+        let histogram = self.histogram(hits, speed);
+        let radius = self.neighbour_bins as i64;
+        let window = |centre: i64| (centre - radius..=centre + radius).map(|b| histogram.get(&b));
+
+        let (centre, score) = histogram
+            .iter()
+            .map(|(&centre, bin)| {
+                let score = window(centre).flatten().map(|b| b.count).sum::<usize>();
+                (centre, (score, bin.count))
+            })
+            .max_by(|(a, a_score), (b, b_score)| a_score.cmp(b_score).then(b.cmp(a)))?;
+
+        let votes = window(centre).flatten().copied().collect_vec();
+        let offset_sum = votes.iter().map(|b| b.offset_sum).sum::<f32>();
+        Some(MatchResult {
+            song_id,
+            confidence: 0.0,
+            matched_count: score.0,
+            time_offset: offset_sum / score.0 as f32,
+            speed,
+            bin_counts: window(centre).map(|b| b.map_or(0, |b| b.count)).collect(),
+            p_value: 1.0,
+            false_positives: 0.0,
+        })
+    }
+
+    /// The best [`best_offset`](Self::best_offset) over a grid of speeds, ties
+    /// going to the speed closest to 1, refined by a least-squares line through
+    /// the hits in its winning bins.
+    fn best_line(
+        &self,
+        song_id: i64,
+        hits: &[(f32, f32)],
+        min_speed: f32,
+        max_speed: f32,
+        speed_step: f32,
+    ) -> Option<MatchResult> {
+        let steps = ((max_speed - min_speed) / speed_step).round().max(0.0) as usize;
+        let best = (0..=steps)
+            .map(|step| min_speed + step as f32 * speed_step)
+            .filter_map(|speed| self.best_offset(song_id, hits, speed))
+            .max_by(|a, b| {
+                a.matched_count
+                    .cmp(&b.matched_count)
+                    .then((b.speed - 1.0).abs().total_cmp(&(a.speed - 1.0).abs()))
+            })?;
+
+        let centre = self.bin_index(best.time_offset);
+        let radius = self.neighbour_bins as i64;
+        let inliers = hits
+            .iter()
+            .filter(|&&(query_time, song_time)| {
+                let bin = self.bin_index(song_time - best.speed * query_time);
+                bin.abs_diff(centre) <= radius as u64
+            })
+            .copied()
+            .collect_vec();
+
+        Some(match least_squares(&inliers) {
+            Some((time_offset, speed)) => MatchResult {
+                time_offset,
+                speed,
+                ..best
+            },
+            None => best,
+        })
+    }
+}
+
+/// Intercept and slope of the least-squares line through `points`, or `None`
+/// if they share a single x.
+fn least_squares(points: &[(f32, f32)]) -> Option<(f32, f32)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|&(x, _)| f64::from(x)).sum::<f64>() / n;
+    let mean_y = points.iter().map(|&(_, y)| f64::from(y)).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(c, v), &(x, y)| {
+        let dx = f64::from(x) - mean_x;
+        (c + dx * (f64::from(y) - mean_y), v + dx * dx)
     });
+    if variance <= f64::EPSILON {
+        return None;
+    }
+    let slope = covariance / variance;
+    Some(((mean_y - slope * mean_x) as f32, slope as f32))
+}
+
+pub fn match_fingerprints(
+    query_fingerprints: &[Fingerprint],
+    potental_matches: HashMap<i64, Vec<Fingerprint>>,
+) -> Vec<MatchResult> {
+    Matcher::default().identify(query_fingerprints, potental_matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+    use rust_decimal::{Decimal, prelude::FromPrimitive};
+
+    fn fingerprint(hash: i64, time_offset: f32) -> Fingerprint {
+        Fingerprint {
+            hash,
+            time_offset: Decimal::from_f32(time_offset).unwrap(),
+            confidence: Decimal::ZERO,
+            anchor_freq: Decimal::ZERO,
+            target_freq: Decimal::ZERO,
+            delta_t: Decimal::ZERO,
+        }
+    }
 
-    results
+    /// Deterministic jitter in `-amplitude..amplitude`.
+    fn jitter(random: &mut XorShift, amplitude: f32) -> f32 {
+        (random.next_f32() * 2.0 - 1.0) * amplitude
+    }
+
+    /// A query of `count` fingerprints a quarter second apart, and the same
+    /// fingerprints in a song `offset` seconds later, each off by up to `noise`.
+    fn jittered_match(
+        count: usize,
+        offset: f32,
+        noise: f32,
+    ) -> (Vec<Fingerprint>, HashMap<i64, Vec<Fingerprint>>) {
+        let mut random = XorShift::new(0x9e37_79b9);
+        let query = (0..count)
+            .map(|i| fingerprint(i as i64, i as f32 * 0.25))
+            .collect_vec();
+        let song = query
+            .iter()
+            .map(|fp| {
+                let time = fp.time_offset.to_f32().unwrap() + offset + jitter(&mut random, noise);
+                fingerprint(fp.hash, time)
+            })
+            .collect();
+        (query, HashMap::from([(1, song)]))
+    }
+
+    /// A query of `count` fingerprints a quarter second apart, found `offset`
+    /// seconds into a song but played at `speed`.
+    fn stretched_match(
+        count: usize,
+        offset: f32,
+        speed: f32,
+    ) -> (Vec<Fingerprint>, HashMap<i64, Vec<Fingerprint>>) {
+        let query = (0..count)
+            .map(|i| fingerprint(i as i64, i as f32 * 0.25))
+            .collect_vec();
+        let song = query
+            .iter()
+            .map(|fp| fingerprint(fp.hash, offset + speed * fp.time_offset.to_f32().unwrap()))
+            .collect();
+        (query, HashMap::from([(1, song)]))
+    }
+
+    fn stretch_matcher() -> Matcher {
+        Matcher {
+            mode: MatchMode::stretch(),
+            ..Matcher::default()
+        }
+    }
+
+    #[test]
+    fn jittered_offsets_land_in_one_bin_and_its_neighbours() {
+        let (query, songs) = jittered_match(200, 42.0, 0.08);
+
+        let results = Matcher::default().identify(&query, songs);
+
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+        assert_eq!(result.matched_count, 200);
+        assert_eq!(result.bin_counts.len(), 3);
+        assert_eq!(result.bin_counts.iter().sum::<usize>(), 200);
+        assert!(
+            (result.time_offset - 42.0).abs() < 0.02,
+            "{}",
+            result.time_offset
+        );
+        assert!((result.confidence - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn neighbouring_bins_recover_matches_split_by_a_bin_boundary() {
+        // Offsets straddle the boundary between the 42.0 and 42.1 bins
+        let (query, songs) = jittered_match(200, 42.05, 0.03);
+
+        let isolated = Matcher {
+            neighbour_bins: 0,
+            ..Matcher::default()
+        }
+        .identify(&query, songs.clone());
+        let aggregated = Matcher::default().identify(&query, songs);
+
+        assert!(
+            isolated[0].matched_count < 150,
+            "{}",
+            isolated[0].matched_count
+        );
+        assert_eq!(aggregated[0].matched_count, 200);
+        assert!((aggregated[0].time_offset - 42.05).abs() < 0.01);
+    }
+
+    #[test]
+    fn bin_width_sets_the_tolerance() {
+        let (query, songs) = jittered_match(200, 10.0, 0.4);
+
+        let narrow = Matcher::default().identify(&query, songs.clone());
+        let wide = Matcher {
+            bin_width: 0.5,
+            ..Matcher::default()
+        }
+        .identify(&query, songs);
+
+        assert!(narrow[0].matched_count < 100, "{}", narrow[0].matched_count);
+        assert_eq!(wide[0].matched_count, 200);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn a_min_confidence_set_before_significance_scoring_is_still_applied() {
+        let (mut query, songs) = jittered_match(100, 30.0, 0.05);
+        // Three quarters of the query is audio the song doesn't share
+        query.extend((0..300).map(|i| fingerprint(1_000 + i, i as f32 * 0.1)));
+
+        let strict = Matcher {
+            min_confidence: 0.5,
+            ..Matcher::default()
+        };
+
+        assert_eq!(Matcher::default().identify(&query, songs.clone()).len(), 1);
+        assert!(strict.identify(&query, songs).is_empty());
+    }
+
+    #[test]
+    fn the_song_with_aligned_offsets_beats_scattered_hash_hits() {
+        let (query, mut songs) = jittered_match(100, 30.0, 0.05);
+        // Another song sharing every hash, but at unrelated offsets
+        let mut random = XorShift::new(7);
+        let scattered = query
+            .iter()
+            .map(|fp| fingerprint(fp.hash, 60.0 + jitter(&mut random, 50.0)))
+            .collect();
+        songs.insert(2, scattered);
+
+        let results = Matcher::default().identify(&query, songs);
+
+        assert_eq!(results[0].song_id, 1);
+        assert_eq!(results[0].matched_count, 100);
+        assert!(results.iter().skip(1).all(|r| r.matched_count < 10));
+    }
+
+    #[test]
+    fn stretch_mode_recovers_the_speed_of_a_sped_up_query() {
+        // 25 s of query played 4% fast drifts a full second against the song
+        let (query, songs) = stretched_match(100, 30.0, 1.04);
+
+        let fixed = Matcher::default().identify(&query, songs.clone());
+        let stretched = stretch_matcher().identify(&query, songs);
+
+        // A fixed offset only lines up the ~0.3 s of drift its three bins span
+        assert!(fixed.iter().all(|r| r.matched_count < 40));
+        let result = &stretched[0];
+        assert_eq!(result.matched_count, 100);
+        assert!((result.speed - 1.04).abs() < 0.001, "{}", result.speed);
+        assert!(
+            (result.time_offset - 30.0).abs() < 0.02,
+            "{}",
+            result.time_offset
+        );
+    }
+
+    #[test]
+    fn stretch_mode_reports_unit_speed_for_an_unaltered_query() {
+        let (query, songs) = jittered_match(100, 12.0, 0.03);
+
+        let result = &stretch_matcher().identify(&query, songs)[0];
+
+        assert_eq!(result.matched_count, 100);
+        assert!((result.speed - 1.0).abs() < 0.002, "{}", result.speed);
+        assert!((result.time_offset - 12.0).abs() < 0.03);
+    }
+
+    #[test]
+    fn stray_hits_do_not_pull_the_fitted_line() {
+        let (query, mut songs) = stretched_match(100, 30.0, 0.97);
+        // Hash hits elsewhere in the same song
+        let mut random = XorShift::new(11);
+        let stray = query
+            .iter()
+            .take(30)
+            .map(|fp| fingerprint(fp.hash, 100.0 + jitter(&mut random, 60.0)))
+            .collect_vec();
+        songs.get_mut(&1).unwrap().extend(stray);
+
+        let result = &stretch_matcher().identify(&query, songs)[0];
+
+        assert_eq!(result.matched_count, 100);
+        assert!((result.speed - 0.97).abs() < 0.001, "{}", result.speed);
+        assert!((result.time_offset - 30.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn chance_alignments_are_not_significant() {
+        // Hits scattered over the song, some of which will share a bin
+        let query = (0..300)
+            .map(|i| fingerprint(i, i as f32 * 0.1))
+            .collect_vec();
+        let mut random = XorShift::new(3);
+        let song = query
+            .iter()
+            .take(200)
+            .map(|fp| fingerprint(fp.hash, 100.0 + jitter(&mut random, 100.0)))
+            .collect_vec();
+
+        let results = Matcher::default().identify(&query, HashMap::from([(1, song)]));
+
+        assert!(results.is_empty(), "{results:?}");
+    }
+
+    #[test]
+    fn significance_accounts_for_catalogue_size_and_hash_reuse() {
+        let (query, songs) = jittered_match(40, 20.0, 0.05);
+        let identify = |songs_in_catalogue, fingerprints_per_hash| {
+            let matcher = Matcher {
+                catalogue: Some(CatalogueStats {
+                    songs: songs_in_catalogue,
+                    fingerprints: 1_000 * songs_in_catalogue,
+                    distinct_hashes: 1_000 * songs_in_catalogue / fingerprints_per_hash,
+                    mean_duration: 200.0,
+                }),
+                max_false_positives: f64::INFINITY,
+                ..Matcher::default()
+            };
+            matcher.identify(&query, songs.clone()).remove(0)
+        };
+
+        let small = identify(10, 2);
+        let large = identify(100_000, 2);
+        let reused = identify(10, 200);
+
+        // Every song is a chance to be fooled
+        assert_eq!(small.false_positives, small.p_value * 10.0);
+        assert_eq!(large.false_positives, large.p_value * 100_000.0);
+        // Spread over more songs, each hash is less likely to hit this one
+        assert!(large.p_value <= small.p_value);
+        // Hashes shared by many fingerprints make chance hits more likely
+        assert!(reused.p_value > small.p_value * 1000.0);
+        assert!(small.false_positives < 0.01);
+    }
 }
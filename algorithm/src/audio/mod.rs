@@ -1,9 +1,29 @@
+mod config;
 mod constellation;
+mod filter;
 mod fingerprint;
+mod fingerprinter;
+mod hash;
 mod match_fingerprints;
+mod peaks;
+mod resample;
 mod sample;
+mod significance;
+mod streaming;
+mod timeline;
+mod window;
 
-pub use constellation::constellation_points;
-pub use fingerprint::{Fingerprint, generate_fingerprints};
-pub use match_fingerprints::match_fingerprints;
+pub use config::FingerprintConfig;
+pub use constellation::{ConstellationPoint, constellation_points, constellation_points_with};
+pub use filter::{BandpassFilter, Biquad};
+pub use fingerprint::{Fingerprint, generate_fingerprints, generate_fingerprints_with};
+pub use fingerprinter::Fingerprinter;
+pub use hash::{HashScheme, PackedHash, PitchInvariantHash};
+pub use match_fingerprints::{MatchMode, MatchResult, Matcher, match_fingerprints};
+pub use peaks::{PeakInterpolation, PeakPicker};
+pub use resample::{Resample, Resampler};
 pub use sample::BandpassFilterMonoSource;
+pub use significance::CatalogueStats;
+pub use streaming::{StreamingFingerprinter, StreamingMatcher};
+pub use timeline::{TimelineMatcher, TimelineSegment};
+pub use window::{WindowFunction, coherent_gain};
@@ -0,0 +1,491 @@
+use itertools::Itertools;
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, ops::RangeInclusive};
+
+use super::ConstellationPoint;
+
+/// How spectral peaks are chosen for the constellation map.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PeakPicker {
+    /// Local maxima of a 5-bin window within a single frame; the strongest
+    /// `peaks_per_frame` of each frame are kept regardless of their neighbours
+    /// in time.
+    Frame { peaks_per_frame: usize },
+    /// Bins that are the maximum of a `(2 * time_radius + 1)` frames by
+    /// `(2 * frequency_radius + 1)` bins neighbourhood and exceed `threshold`
+    /// times that neighbourhood's mean magnitude. At most `peaks_per_frame`
+    /// are kept per frame. Frames are emitted `time_radius` frames late.
+    Neighbourhood {
+        time_radius: usize,
+        frequency_radius: usize,
+        threshold: f32,
+        peaks_per_frame: usize,
+    },
+}
+
+impl Default for PeakPicker {
+    fn default() -> Self {
+        PeakPicker::Frame { peaks_per_frame: 4 }
+    }
+}
+
+impl PeakPicker {
+    /// A neighbourhood picker spanning roughly ±0.56 s and ±27 Hz at the default
+    /// 11,025 Hz / 4096-point spectrogram.
+    pub fn neighbourhood() -> Self {
+        PeakPicker::Neighbourhood {
+            time_radius: 3,
+            frequency_radius: 10,
+            threshold: 2.0,
+            peaks_per_frame: 5,
+        }
+    }
+}
+
+/// Sub-bin refinement of peak positions by fitting a parabola through the log
+/// magnitudes either side of the peak, which is exact for a Gaussian-shaped
+/// main lobe and close for the usual windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeakInterpolation {
+    /// Peaks sit on the bin and frame grid. The frame picker keeps reporting
+    /// the bin two below the peak, as it always has, so catalogues built before
+    /// interpolation existed still match.
+    #[default]
+    None,
+    /// Interpolate frequency between neighbouring bins.
+    Frequency,
+    /// Interpolate frequency, and time between neighbouring frames. Peaks are
+    /// settled at least one frame late so the next frame is known.
+    TimeFrequency,
+}
+
+/// A magnitude spectrum held back until its neighbours in time are known.
+struct Frame {
+    index: usize,
+    magnitudes: Vec<f32>,
+    /// Each bin replaced by the maximum within `frequency_radius` bins of it.
+    /// Only filled in for the neighbourhood picker.
+    dilated: Vec<f32>,
+    /// Mean magnitude over the bins in the band.
+    mean: f32,
+}
+
+/// Turns successive magnitude spectra into constellation points with a [`PeakPicker`].
+pub(crate) struct PeakExtractor {
+    picker: PeakPicker,
+    interpolation: PeakInterpolation,
+    frequency_resolution: Decimal,
+    /// Samples between the starts of consecutive frames.
+    step_size: Decimal,
+    sample_rate: Decimal,
+    band: RangeInclusive<Decimal>,
+    frames: VecDeque<Frame>,
+}
+
+impl PeakExtractor {
+    pub fn new(
+        picker: PeakPicker,
+        interpolation: PeakInterpolation,
+        frequency_resolution: Decimal,
+        step_size: Decimal,
+        sample_rate: Decimal,
+        band: RangeInclusive<Decimal>,
+    ) -> Self {
+        Self {
+            picker,
+            interpolation,
+            frequency_resolution,
+            step_size,
+            sample_rate,
+            band,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Add the magnitude spectrum of frame `index`, returning every frame whose
+    /// peaks are now settled.
+    pub fn push(
+        &mut self,
+        index: usize,
+        magnitudes: &[f32],
+    ) -> Vec<(usize, Vec<ConstellationPoint>)> {
+        let bins = self.band_bins(magnitudes.len());
+        let dilated = match self.picker {
+            PeakPicker::Frame { .. } => Vec::new(),
+            PeakPicker::Neighbourhood {
+                frequency_radius, ..
+            } => dilate(magnitudes, frequency_radius),
+        };
+        self.frames.push_back(Frame {
+            index,
+            magnitudes: magnitudes.to_vec(),
+            dilated,
+            mean: magnitudes[bins.clone()].iter().sum::<f32>() / bins.len().max(1) as f32,
+        });
+
+        // The frame `lookahead` back now has all the neighbours it will get
+        let lookahead = self.lookahead();
+        if self.frames.len() > lookahead {
+            let centre = self.frames.len() - 1 - lookahead;
+            let settled = vec![self.peaks(centre)];
+            if self.frames.len() > 2 * lookahead {
+                self.frames.pop_front();
+            }
+            settled
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Settle the frames still waiting for neighbours that will never arrive.
+    pub fn finish(&mut self) -> Vec<(usize, Vec<ConstellationPoint>)> {
+        let first = self.frames.len().saturating_sub(self.lookahead());
+        let settled = (first..self.frames.len())
+            .map(|centre| self.peaks(centre))
+            .collect();
+        self.frames.clear();
+        settled
+    }
+
+    /// Frames that must follow a frame before its peaks can be settled.
+    fn lookahead(&self) -> usize {
+        let time_radius = match self.picker {
+            PeakPicker::Frame { .. } => 0,
+            PeakPicker::Neighbourhood { time_radius, .. } => time_radius,
+        };
+        if self.interpolation == PeakInterpolation::TimeFrequency {
+            time_radius.max(1)
+        } else {
+            time_radius
+        }
+    }
+
+    fn peaks(&self, centre: usize) -> (usize, Vec<ConstellationPoint>) {
+        let peaks = match self.picker {
+            PeakPicker::Frame { peaks_per_frame } => self.frame_peaks(centre, peaks_per_frame),
+            PeakPicker::Neighbourhood { .. } => self.neighbourhood_peaks(centre),
+        };
+        (self.frames[centre].index, peaks)
+    }
+
+    fn frame_peaks(&self, centre: usize, peaks_per_frame: usize) -> Vec<ConstellationPoint> {
+        let magnitudes = &self.frames[centre].magnitudes;
+        let max_magnitude = magnitudes.iter().copied().fold(0.0, f32::max);
+        // Without interpolation the peak is reported at the start of its
+        // 5-bin window, two bins low, to stay compatible with older catalogues
+        let reported_offset = match self.interpolation {
+            PeakInterpolation::None => 0,
+            _ => 2,
+        };
+
+        magnitudes
+            .windows(5)
+            .enumerate()
+            .filter_map(|(bin, window)| {
+                if window[0] < window[1]
+                    && window[1] < window[2]
+                    && window[2] > window[3]
+                    && window[3] > window[4]
+                {
+                    // (reported bin, magnitude)
+                    Some((bin + reported_offset, window[2]))
+                } else {
+                    None
+                }
+            })
+            .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+            .map(|(bin, magnitude)| self.point(centre, bin, magnitude, max_magnitude))
+            .filter(|point| self.band.contains(&point.frequency))
+            .take(peaks_per_frame)
+            .collect()
+    }
+
+    /// Peaks of `self.frames[centre]`, compared against up to `time_radius`
+    /// buffered frames either side of it.
+    fn neighbourhood_peaks(&self, centre: usize) -> Vec<ConstellationPoint> {
+        let PeakPicker::Neighbourhood {
+            time_radius,
+            threshold,
+            peaks_per_frame,
+            ..
+        } = self.picker
+        else {
+            unreachable!(
+                "neighbourhood peaks requested from a {:?} picker",
+                self.picker
+            );
+        };
+        let frame = &self.frames[centre];
+        let neighbours = &self
+            .frames
+            .range(
+                centre.saturating_sub(time_radius)
+                    ..(centre + time_radius + 1).min(self.frames.len()),
+            )
+            .collect_vec();
+        let floor =
+            threshold * neighbours.iter().map(|f| f.mean).sum::<f32>() / neighbours.len() as f32;
+        let max_magnitude = frame.magnitudes.iter().copied().fold(0.0, f32::max);
+
+        self.band_bins(frame.magnitudes.len())
+            .filter(|&bin| {
+                let magnitude = frame.magnitudes[bin];
+                magnitude > floor && neighbours.iter().all(|f| magnitude >= f.dilated[bin])
+            })
+            .map(|bin| (bin, frame.magnitudes[bin]))
+            .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+            .take(peaks_per_frame)
+            .map(|(bin, magnitude)| self.point(centre, bin, magnitude, max_magnitude))
+            .collect()
+    }
+
+    /// Bins whose centre frequency lies inside the band.
+    fn band_bins(&self, bins: usize) -> std::ops::Range<usize> {
+        let low = (self.band.start() / self.frequency_resolution).ceil();
+        let high = (self.band.end() / self.frequency_resolution).floor() + dec!(1);
+        let [low, high] = [low, high].map(|b| usize::try_from(b).unwrap_or(0).min(bins));
+        low..high
+    }
+
+    /// The point for a peak at `bin` of `self.frames[centre]`, refined off the
+    /// grid as far as `self.interpolation` allows. Peaks on the edge of the
+    /// spectrum, or in the first or last frame, stay on the grid along that axis.
+    fn point(
+        &self,
+        centre: usize,
+        bin: usize,
+        magnitude: f32,
+        max_magnitude: f32,
+    ) -> ConstellationPoint {
+        let frame = &self.frames[centre];
+        let mut bin_position = Decimal::from(bin);
+        let mut frame_position = Decimal::from(frame.index);
+
+        if self.interpolation != PeakInterpolation::None
+            && let (Some(&before), Some(&after)) = (
+                bin.checked_sub(1).and_then(|b| frame.magnitudes.get(b)),
+                frame.magnitudes.get(bin + 1),
+            )
+        {
+            bin_position += parabolic_offset(before, frame.magnitudes[bin], after);
+        }
+        if self.interpolation == PeakInterpolation::TimeFrequency
+            && let (Some(before), Some(after)) = (
+                centre.checked_sub(1).and_then(|c| self.frames.get(c)),
+                self.frames.get(centre + 1),
+            )
+        {
+            frame_position += parabolic_offset(
+                before.magnitudes[bin],
+                frame.magnitudes[bin],
+                after.magnitudes[bin],
+            );
+        }
+
+        let normalized_magnitude = magnitude / max_magnitude * 100.0;
+        ConstellationPoint {
+            time: frame_position * self.step_size / self.sample_rate,
+            frequency: bin_position * self.frequency_resolution,
+            magnitude: Decimal::from_f32(normalized_magnitude).unwrap_or_default(),
+        }
+    }
+}
+
+/// Position, between -0.5 and 0.5, of the vertex of the parabola through the
+/// log magnitudes at -1, 0 and +1. Zero if the middle is not a maximum.
+fn parabolic_offset(before: f32, peak: f32, after: f32) -> Decimal {
+    let [a, b, c] = [before, peak, after].map(|m| m.max(f32::MIN_POSITIVE).ln());
+    let curvature = a - 2.0 * b + c;
+    if curvature >= 0.0 {
+        return Decimal::ZERO;
+    }
+    let offset = (0.5 * (a - c) / curvature).clamp(-0.5, 0.5);
+    Decimal::from_f32(offset).unwrap_or_default()
+}
+
+/// Sliding maximum over `radius` bins either side of each bin.
+fn dilate(magnitudes: &[f32], radius: usize) -> Vec<f32> {
+    (0..magnitudes.len())
+        .map(|bin| {
+            let window = bin.saturating_sub(radius)..(bin + radius + 1).min(magnitudes.len());
+            magnitudes[window].iter().copied().fold(0.0, f32::max)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINS: usize = 200;
+
+    fn run(picker: PeakPicker, frames: &[Vec<f32>]) -> Vec<(usize, Vec<ConstellationPoint>)> {
+        run_interpolated(picker, PeakInterpolation::None, frames)
+    }
+
+    fn run_interpolated(
+        picker: PeakPicker,
+        interpolation: PeakInterpolation,
+        frames: &[Vec<f32>],
+    ) -> Vec<(usize, Vec<ConstellationPoint>)> {
+        let mut extractor = PeakExtractor::new(
+            picker,
+            interpolation,
+            dec!(10),
+            dec!(1),
+            dec!(2),
+            dec!(20)..=dec!(5000),
+        );
+        let mut settled = frames
+            .iter()
+            .enumerate()
+            .flat_map(|(index, magnitudes)| extractor.push(index, magnitudes))
+            .collect_vec();
+        settled.extend(extractor.finish());
+        settled
+    }
+
+    /// A flat noise floor with a ridge around `bin` whose height is `height`.
+    fn frame(bin: usize, height: f32) -> Vec<f32> {
+        (0..BINS)
+            .map(|b| {
+                let distance = b.abs_diff(bin) as f32;
+                1.0 + height / (1.0 + distance * distance)
+            })
+            .collect()
+    }
+
+    /// A Gaussian ridge centred between bins and frames, whose log magnitude is
+    /// exactly a parabola along both axes.
+    fn gaussian_frames(bin: f32, frame: f32) -> Vec<Vec<f32>> {
+        (0..9)
+            .map(|i| {
+                (0..BINS)
+                    .map(|b| {
+                        let [db, di] = [b as f32 - bin, i as f32 - frame];
+                        100.0 * (-db * db / 8.0 - di * di / 4.5).exp()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn strongest(
+        settled: &[(usize, Vec<ConstellationPoint>)],
+        index: usize,
+    ) -> &ConstellationPoint {
+        settled
+            .iter()
+            .find(|(i, _)| *i == index)
+            .and_then(|(_, peaks)| peaks.iter().max_by_key(|p| p.magnitude))
+            .unwrap()
+    }
+
+    #[test]
+    fn every_frame_is_settled_exactly_once_and_in_order() {
+        let frames = (0..10).map(|i| frame(50, i as f32)).collect_vec();
+
+        let settled = run(PeakPicker::neighbourhood(), &frames);
+
+        assert_eq!(
+            settled.iter().map(|(i, _)| *i).collect_vec(),
+            (0..10).collect_vec()
+        );
+    }
+
+    #[test]
+    fn neighbourhood_keeps_only_the_maximum_in_time() {
+        // A note swelling to its loudest in frame 4 then fading
+        let heights = [10.0, 20.0, 40.0, 80.0, 100.0, 80.0, 40.0, 20.0, 10.0];
+        let frames = heights.iter().map(|&h| frame(50, h)).collect_vec();
+
+        let settled = run(PeakPicker::neighbourhood(), &frames);
+        let per_frame = run(PeakPicker::default(), &frames);
+
+        let peaks = settled.iter().filter(|(_, p)| !p.is_empty()).collect_vec();
+        assert_eq!(peaks.len(), 1);
+        assert_eq!(peaks[0].0, 4);
+        assert_eq!(peaks[0].1[0].frequency, dec!(500));
+        assert_eq!(peaks[0].1[0].time, dec!(2));
+        // The single-frame picker reports the same note in every frame
+        assert!(per_frame.iter().all(|(_, p)| !p.is_empty()));
+    }
+
+    #[test]
+    fn neighbourhood_ignores_peaks_below_the_adaptive_threshold() {
+        // Ripples barely above the noise floor are local maxima but not significant
+        let frames = (0..9).map(|_| frame(50, 0.5)).collect_vec();
+
+        let settled = run(PeakPicker::neighbourhood(), &frames);
+
+        assert!(settled.iter().all(|(_, p)| p.is_empty()));
+    }
+
+    #[test]
+    fn neighbourhood_keeps_separate_peaks_outside_the_radius() {
+        let mut magnitudes = frame(30, 100.0);
+        for (m, n) in magnitudes.iter_mut().zip(frame(120, 60.0)) {
+            *m += n - 1.0;
+        }
+        let frames = vec![magnitudes];
+
+        let settled = run(PeakPicker::neighbourhood(), &frames);
+
+        let frequencies = settled[0].1.iter().map(|p| p.frequency).collect_vec();
+        assert_eq!(frequencies, vec![dec!(300), dec!(1200)]);
+    }
+
+    #[test]
+    fn frequency_interpolation_finds_the_peak_between_bins() {
+        let frames = gaussian_frames(50.3, 4.0);
+
+        for picker in [PeakPicker::default(), PeakPicker::neighbourhood()] {
+            let settled = run_interpolated(picker, PeakInterpolation::Frequency, &frames);
+            let peak = strongest(&settled, 4);
+            assert!(
+                (peak.frequency - dec!(503)).abs() < dec!(0.01),
+                "{picker:?}: {}",
+                peak.frequency
+            );
+            assert_eq!(peak.time, dec!(2));
+        }
+    }
+
+    #[test]
+    fn without_interpolation_peaks_stay_on_the_legacy_grid() {
+        let frames = gaussian_frames(50.3, 4.0);
+
+        let per_frame = run(PeakPicker::default(), &frames);
+        let neighbourhood = run(PeakPicker::neighbourhood(), &frames);
+
+        // The frame picker has always reported the start of its 5-bin window
+        assert_eq!(strongest(&per_frame, 4).frequency, dec!(480));
+        assert_eq!(strongest(&neighbourhood, 4).frequency, dec!(500));
+    }
+
+    #[test]
+    fn time_interpolation_finds_the_peak_between_frames() {
+        let frames = gaussian_frames(50.3, 4.25);
+
+        for picker in [PeakPicker::default(), PeakPicker::neighbourhood()] {
+            let settled = run_interpolated(picker, PeakInterpolation::TimeFrequency, &frames);
+
+            assert_eq!(
+                settled.iter().map(|(i, _)| *i).collect_vec(),
+                (0..9).collect_vec(),
+                "{picker:?}"
+            );
+            let peak = strongest(&settled, 4);
+            assert!(
+                (peak.time - dec!(2.125)).abs() < dec!(0.001),
+                "{picker:?}: {}",
+                peak.time
+            );
+            assert!((peak.frequency - dec!(503)).abs() < dec!(0.01));
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// Zero crossings of the sinc kernel kept on each side of its centre, measured
+/// at the lower of the two sample rates. More crossings give a steeper anti-alias
+/// transition at the cost of more taps per output sample.
+const ZERO_CROSSINGS: usize = 32;
+/// Cutoff as a fraction of the lower Nyquist frequency, leaving room for the
+/// transition band so aliases fold back above the band we fingerprint.
+const ROLLOFF: f64 = 0.95;
+
+/// Band-limited sample-rate converter using a polyphase windowed-sinc filter.
+///
+/// The rate ratio is reduced to `up / down`, so 48 kHz -> 11,025 Hz becomes
+/// 147 / 640 and every output rate is exact rather than an integer decimation of
+/// the input. The filter's group delay is compensated: output sample `n` is
+/// centred on input time `n / to`, so the same audio at different source rates
+/// lines up sample for sample.
+pub struct Resampler {
+    up: usize,
+    down: usize,
+    /// Taps per polyphase branch.
+    taps: usize,
+    /// Branch-major filter table: `coefficients[phase * taps + k]`.
+    coefficients: Vec<f32>,
+    /// Filter centre, in upsampled samples.
+    delay: u64,
+    /// The last `taps` input samples, newest at the back.
+    history: VecDeque<f32>,
+    /// Input samples pushed so far, including the zero padding added by `flush`.
+    pushed: u64,
+    /// Real input samples pushed so far.
+    inputs: u64,
+    outputs: u64,
+}
+
+impl Resampler {
+    pub fn new(from_sample_rate: u32, to_sample_rate: u32) -> Self {
+        let divisor = gcd(from_sample_rate as usize, to_sample_rate as usize);
+        let up = to_sample_rate as usize / divisor;
+        let down = from_sample_rate as usize / divisor;
+
+        let (taps, coefficients, delay) = if up == down {
+            // Same rate: a single unit tap passes samples straight through.
+            (1, vec![1.0], 0)
+        } else {
+            let ratio = up.max(down);
+            let cutoff = 0.5 / ratio as f64 * ROLLOFF;
+            let half_length = ZERO_CROSSINGS * ratio;
+            let length = 2 * half_length + 1;
+            let taps = length.div_ceil(up);
+
+            let mut prototype = vec![0.0; taps * up];
+            for (i, coefficient) in prototype.iter_mut().enumerate().take(length) {
+                let x = i as f64 - half_length as f64;
+                *coefficient = sinc(2.0 * cutoff * x) * blackman(i, length);
+            }
+
+            // Split into one branch per output phase, each normalised to unity gain at DC.
+            let mut coefficients = vec![0.0; taps * up];
+            for phase in 0..up {
+                let branch = (0..taps)
+                    .map(|k| prototype[phase + k * up])
+                    .collect::<Vec<_>>();
+                let gain: f64 = branch.iter().sum();
+                for (k, coefficient) in branch.into_iter().enumerate() {
+                    coefficients[phase * taps + k] = (coefficient / gain) as f32;
+                }
+            }
+            (taps, coefficients, half_length as u64)
+        };
+
+        Self {
+            up,
+            down,
+            taps,
+            coefficients,
+            delay,
+            history: VecDeque::from(vec![0.0; taps]),
+            pushed: 0,
+            inputs: 0,
+            outputs: 0,
+        }
+    }
+
+    /// Feed one input sample, appending any output samples it completes.
+    pub fn push(&mut self, sample: f32, output: &mut impl Extend<f32>) {
+        self.inputs += 1;
+        self.push_padded(sample, output);
+    }
+
+    /// Pad the input with silence until every output sample covering the real
+    /// input has been produced, i.e. exactly `ceil(inputs * to / from)` in total.
+    pub fn flush(&mut self, output: &mut impl Extend<f32>) {
+        let total = (self.inputs * self.up as u64).div_ceil(self.down as u64);
+        while self.outputs < total {
+            self.push_padded(0.0, output);
+        }
+    }
+
+    fn push_padded(&mut self, sample: f32, output: &mut impl Extend<f32>) {
+        self.history.pop_front();
+        self.history.push_back(sample);
+        self.pushed += 1;
+
+        // The newest input completes every output whose filter window ends on it.
+        loop {
+            let position = self.outputs * self.down as u64 + self.delay;
+            let newest = position / self.up as u64;
+            if newest + 1 != self.pushed {
+                break;
+            }
+            let phase = (position % self.up as u64) as usize;
+            let branch = &self.coefficients[phase * self.taps..(phase + 1) * self.taps];
+            let value = branch
+                .iter()
+                .zip(self.history.iter().rev())
+                .map(|(coefficient, sample)| coefficient * sample)
+                .sum();
+            output.extend(Some(value));
+            self.outputs += 1;
+        }
+    }
+}
+
+/// Iterator adaptor resampling a stream of samples with a [`Resampler`].
+pub struct Resample<I> {
+    input: I,
+    resampler: Resampler,
+    pending: VecDeque<f32>,
+    flushed: bool,
+}
+
+impl<I: Iterator<Item = f32>> Resample<I> {
+    pub fn new(input: I, from_sample_rate: u32, to_sample_rate: u32) -> Self {
+        Self {
+            input,
+            resampler: Resampler::new(from_sample_rate, to_sample_rate),
+            pending: VecDeque::new(),
+            flushed: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = f32>> Iterator for Resample<I> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            match self.input.next() {
+                Some(sample) => self.resampler.push(sample, &mut self.pending),
+                None if !self.flushed => {
+                    self.resampler.flush(&mut self.pending);
+                    self.flushed = true;
+                }
+                None => return None,
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn blackman(i: usize, length: usize) -> f64 {
+    let phase = 2.0 * PI * i as f64 / (length - 1) as f64;
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f32, sample_rate: u32, seconds: f32) -> impl Iterator<Item = f32> {
+        (0..(sample_rate as f32 * seconds) as usize).map(move |i| {
+            (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin()
+        })
+    }
+
+    /// Amplitude of `frequency` in `samples`, by correlating against a reference tone.
+    fn amplitude(samples: &[f32], frequency: f32, sample_rate: u32) -> f32 {
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (i, s)| {
+                let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32;
+                (re + s * phase.cos(), im + s * phase.sin())
+            });
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f32
+    }
+
+    #[test]
+    fn produces_exactly_the_target_rate() {
+        for from in [44100, 48000, 96000, 22050] {
+            let output = Resample::new(tone(440.0, from, 3.0), from, 11025).count();
+            assert_eq!(output, 3 * 11025, "converting from {from} Hz");
+        }
+    }
+
+    #[test]
+    fn in_band_tones_keep_their_level() {
+        for from in [44100, 48000, 96000, 22050] {
+            let output = Resample::new(tone(1000.0, from, 1.0), from, 11025).collect::<Vec<_>>();
+            let level = amplitude(&output[1000..10000], 1000.0, 11025);
+            assert!((level - 1.0).abs() < 0.01, "{from} Hz level was {level}");
+        }
+    }
+
+    #[test]
+    fn tones_above_the_target_nyquist_are_removed() {
+        for from in [44100, 48000, 96000] {
+            let output = Resample::new(tone(8000.0, from, 1.0), from, 11025).collect::<Vec<_>>();
+            // Without an anti-alias filter 8 kHz would fold down to 3,025 Hz.
+            let alias = amplitude(&output[1000..10000], 11025.0 - 8000.0, 11025);
+            assert!(alias < 0.001, "{from} Hz alias level was {alias}");
+        }
+    }
+
+    #[test]
+    fn output_is_aligned_with_the_input_in_time() {
+        for from in [44100, 48000, 96000, 22050] {
+            let impulse = (0..from).map(|i| if i == from / 2 { 1.0 } else { 0.0 });
+            let output = Resample::new(impulse, from, 11025).collect::<Vec<_>>();
+            let peak = (0..output.len())
+                .max_by(|&a, &b| output[a].total_cmp(&output[b]))
+                .unwrap();
+            assert!(peak.abs_diff(11025 / 2) <= 1, "{from} Hz peak at {peak}");
+        }
+    }
+}
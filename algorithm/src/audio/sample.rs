@@ -5,21 +5,20 @@ use std::{
     time::Duration,
 };
 
+use super::{
+    filter::BandpassFilter,
+    resample::{Resample, Resampler},
+};
+
+/// Default passband kept for fingerprinting, in Hz.
+pub const DEFAULT_LOW_CUTOFF: f32 = 20.0;
+pub const DEFAULT_HIGH_CUTOFF: f32 = 5000.0;
+
 pub struct BandpassFilterMonoSource {
-    source: Box<dyn Source<Item = i16>>,
+    samples: Resample<Downmix>,
     target_sample_rate: u32,
-    _original_sample_rate: u32,
-    downsample_ratio: usize,
-    channels: u16,
-    _sample_counter: usize,
-    // Filter states
-    x1: f32,
-    _x2: f32, // Previous input values
-    y1: f32,
-    y2: f32, // Previous output values
-    // Filter coefficients (calculated for bandpass between 20Hz-5kHz)
-    hp_coef: f32, // High-pass filter coefficient (for 20Hz cutoff)
-    lp_coef: f32, // Low-pass filter coefficient (for 5kHz cutoff)
+    total_duration: Option<Duration>,
+    filter: BandpassFilter,
 }
 
 impl BandpassFilterMonoSource {
@@ -27,56 +26,42 @@ impl BandpassFilterMonoSource {
     where
         D: Read + Seek + Send + Sync + 'static,
     {
-        let source = Box::new(Decoder::new(data).unwrap());
+        let source = Box::new(Decoder::new(data)?);
         // Apply bandpass filtering + downsampling to 11,025 Hz
         Ok(BandpassFilterMonoSource::new(source, 11025))
     }
 
     pub fn new(source: Box<dyn Source<Item = i16>>, target_sample_rate: u32) -> Self {
-        let original_sample_rate = source.sample_rate();
-        let channels = source.channels();
-        // TODO: Fix as this will currently sample longer than the original source because it ignores the remainder of the division and should be sampling less often than it should.
-        let downsample_ratio = (original_sample_rate / target_sample_rate) as usize;
-
-        // Calculate filter coefficients based on RC filter design
-        // High-pass filter (cutoff ~20Hz)
-        let hp_coef = 0.98; // Simplified coefficient for 20Hz at 44.1kHz
-
-        // Low-pass filter (cutoff ~5kHz)
-        let lp_coef = 0.2; // Simplified coefficient for 5kHz at 44.1kHz
-
-        BandpassFilterMonoSource {
+        Self::with_cutoffs(
             source,
             target_sample_rate,
-            _original_sample_rate: original_sample_rate,
-            downsample_ratio,
-            channels,
-            _sample_counter: 0,
-            x1: 0.0,
-            _x2: 0.0,
-            y1: 0.0,
-            y2: 0.0,
-            hp_coef,
-            lp_coef,
-        }
+            DEFAULT_LOW_CUTOFF,
+            DEFAULT_HIGH_CUTOFF,
+        )
     }
 
-    // Apply bandpass filtering to a sample
-    fn filter(&mut self, sample: i16) -> i16 {
-        // Convert to float for filtering
-        let input = sample as f32;
-
-        // High-pass filter (removes frequencies below ~20Hz)
-        let hp = self.hp_coef * (self.y1 + input - self.x1);
-        self.x1 = input;
-        self.y1 = hp;
+    /// Like [`BandpassFilterMonoSource::new`], keeping only `low_cutoff..high_cutoff` Hz.
+    pub fn with_cutoffs(
+        source: Box<dyn Source<Item = i16>>,
+        target_sample_rate: u32,
+        low_cutoff: f32,
+        high_cutoff: f32,
+    ) -> Self {
+        let original_sample_rate = source.sample_rate();
+        let total_duration = source.total_duration();
+        // Downmix to mono first so the resampler only runs once per frame
+        let samples = Resample::new(Downmix { source }, original_sample_rate, target_sample_rate);
 
-        // Low-pass filter (removes frequencies above ~5kHz)
-        let lp = self.lp_coef * hp + (1.0 - self.lp_coef) * self.y2;
-        self.y2 = lp;
+        // Filter after resampling so the coefficients match the rate the
+        // samples are actually at
+        let filter = BandpassFilter::new(target_sample_rate, low_cutoff, high_cutoff);
 
-        // Convert back to i16
-        lp as i16
+        BandpassFilterMonoSource {
+            samples,
+            target_sample_rate,
+            total_duration,
+            filter,
+        }
     }
 }
 
@@ -84,50 +69,20 @@ impl Iterator for BandpassFilterMonoSource {
     type Item = i16;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Skip directly to the next sample we need
-        if self.channels == 1 {
-            // Fast path for mono sources
-            // Skip samples we don't need due to downsampling
-            for _ in 0..self.downsample_ratio - 1 {
-                // Skip samples without filtering them
-                self.source.next()?;
-            }
-            // Process only the sample we'll actually use
-            let sample = self.source.next()?;
-            Some(self.filter(sample))
-        } else {
-            // Path for stereo or multi-channel sources
-            // Skip samples we don't need
-            for _ in 0..self.downsample_ratio - 1 {
-                // Skip all channels for each sample position
-                for _ in 0..self.channels {
-                    self.source.next()?;
-                }
-            }
-
-            // Process only the sample we'll use
-            let mut sum = 0i32;
-            for _ in 0..self.channels {
-                sum += self.source.next()? as i32;
-            }
-            let averaged = (sum / self.channels as i32) as i16;
-
-            // Filter and return the downsampled, mono sample
-            Some(self.filter(averaged))
-        }
+        let sample = self.samples.next()?;
+        // Convert back to i16 (saturating)
+        Some(self.filter.process(sample) as i16)
     }
 }
 
 impl Source for BandpassFilterMonoSource {
     fn current_frame_len(&self) -> Option<usize> {
-        self.source
-            .current_frame_len()
-            .map(|len| len / self.downsample_ratio / self.channels as usize)
+        // Resampling fixes the output format, so there are no frame boundaries to report
+        None
     }
 
     fn channels(&self) -> u16 {
         1 // Mono output
-        //self.source.channels()
     }
 
     fn sample_rate(&self) -> u32 {
@@ -135,8 +90,94 @@ impl Source for BandpassFilterMonoSource {
     }
 
     fn total_duration(&self) -> Option<Duration> {
-        // Calculate the new duration based on the downsample ratio
+        // Resampling changes the sample count, not the length in time
+        self.total_duration
+    }
+}
+
+/// [`BandpassFilterMonoSource`] for audio that arrives in pieces: interleaved
+/// samples are pushed as they come and filtered mono samples appended to the
+/// output, identical to what the source would yield for the same audio.
+pub(crate) struct BandpassFilterMonoStream {
+    channels: u16,
+    /// Samples of a frame split across two pushes.
+    partial_frame: Vec<i16>,
+    resampler: Resampler,
+    filter: BandpassFilter,
+    resampled: Vec<f32>,
+}
+
+impl BandpassFilterMonoStream {
+    pub fn new(
+        channels: u16,
+        sample_rate: u32,
+        target_sample_rate: u32,
+        low_cutoff: f32,
+        high_cutoff: f32,
+    ) -> Self {
+        Self {
+            channels: channels.max(1),
+            partial_frame: Vec::new(),
+            resampler: Resampler::new(sample_rate, target_sample_rate),
+            filter: BandpassFilter::new(target_sample_rate, low_cutoff, high_cutoff),
+            resampled: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[i16], output: &mut impl Extend<i16>) {
+        for &sample in samples {
+            self.partial_frame.push(sample);
+            if self.partial_frame.len() < self.channels as usize {
+                continue;
+            }
+            // Downmix exactly as `Downmix` does
+            let mono = if self.channels == 1 {
+                f32::from(sample)
+            } else {
+                let sum: i32 = self.partial_frame.iter().map(|&s| s as i32).sum();
+                sum as f32 / self.channels as f32
+            };
+            self.partial_frame.clear();
+            self.resampler.push(mono, &mut self.resampled);
+        }
+        self.drain(output);
+    }
+
+    /// End of the audio: drain the resampler. An incomplete trailing frame is
+    /// dropped, as the source drops it.
+    pub fn flush(&mut self, output: &mut impl Extend<i16>) {
+        self.resampler.flush(&mut self.resampled);
+        self.drain(output);
+    }
+
+    fn drain(&mut self, output: &mut impl Extend<i16>) {
+        let filter = &mut self.filter;
+        output.extend(
+            self.resampled
+                .drain(..)
+                .map(|sample| filter.process(sample) as i16),
+        );
+    }
+}
+
+/// Averages each frame of the decoded source down to a single mono sample.
+struct Downmix {
+    source: Box<dyn Source<Item = i16>>,
+}
+
+impl Iterator for Downmix {
+    type Item = f32;
 
-        self.source.total_duration()
+    fn next(&mut self) -> Option<Self::Item> {
+        let channels = self.source.channels().max(1);
+        if channels == 1 {
+            // Fast path for mono sources
+            return self.source.next().map(f32::from);
+        }
+        let mut sum = 0i32;
+        for _ in 0..channels {
+            sum += self.source.next()? as i32;
+        }
+        Some(sum as f32 / channels as f32)
     }
 }
@@ -0,0 +1,115 @@
+//! How likely a match is to be chance: hash hits from unrelated audio land at
+//! uniformly random offsets, so the number falling into any one offset window
+//! is roughly Poisson distributed.
+
+/// Size of the catalogue queries are matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CatalogueStats {
+    pub songs: usize,
+    pub fingerprints: usize,
+    pub distinct_hashes: usize,
+    /// Mean song length, in seconds.
+    pub mean_duration: f32,
+}
+
+impl CatalogueStats {
+    /// Hash hits one song is expected to share with a query of
+    /// `query_fingerprints` fingerprints it has nothing to do with, if hashes
+    /// are used evenly across the catalogue.
+    pub fn expected_random_hits(&self, query_fingerprints: usize) -> f64 {
+        if self.songs == 0 || self.distinct_hashes == 0 {
+            return 0.0;
+        }
+        query_fingerprints as f64 * self.fingerprints as f64
+            / (self.distinct_hashes as f64 * self.songs as f64)
+    }
+}
+
+/// Probability that chance alone puts at least `count` hits into one of
+/// `windows` offset windows, when each window expects `lambda` of them.
+pub(crate) fn p_value(count: usize, lambda: f64, windows: f64) -> f64 {
+    let tail = poisson_tail(lambda, count);
+    if tail >= 1.0 {
+        return 1.0;
+    }
+    // 1 - (1 - tail)^windows, without losing small tails to rounding
+    -(windows.max(1.0) * (-tail).ln_1p()).exp_m1()
+}
+
+/// `P(X >= k)` for `X ~ Poisson(lambda)`.
+pub(crate) fn poisson_tail(lambda: f64, k: usize) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+    if lambda <= 0.0 {
+        return 0.0;
+    }
+    let ln_factorial = (2..=k).map(|i| (i as f64).ln()).sum::<f64>();
+    if k as f64 <= lambda {
+        // Most of the mass is at or above k: subtract the terms below it
+        let mut term = (-lambda).exp();
+        let mut below = 0.0;
+        for i in 0..k {
+            below += term;
+            term *= lambda / (i + 1) as f64;
+        }
+        return (1.0 - below).max(0.0);
+    }
+    // Sum upwards from P(X = k) until the terms no longer matter
+    let mut term = (-lambda + k as f64 * lambda.ln() - ln_factorial).exp();
+    let mut tail = 0.0;
+    let mut i = k;
+    while term > tail * 1e-16 && term > 0.0 {
+        tail += term;
+        i += 1;
+        term *= lambda / i as f64;
+    }
+    tail.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poisson_tail_matches_closed_forms() {
+        let e = std::f64::consts::E;
+        // 1 - e^-1 (1 + 1 + 1/2)
+        assert!((poisson_tail(1.0, 3) - (1.0 - 2.5 / e)).abs() < 1e-12);
+        // e^-10 (1 + 10 + 50 + 166.67 + 416.67) below 5
+        let below = (1.0 + 10.0 + 50.0 + 1000.0 / 6.0 + 10000.0 / 24.0) * (-10.0_f64).exp();
+        assert!((poisson_tail(10.0, 5) - (1.0 - below)).abs() < 1e-12);
+        assert_eq!(poisson_tail(0.0, 1), 0.0);
+        assert_eq!(poisson_tail(3.0, 0), 1.0);
+    }
+
+    #[test]
+    fn tiny_tails_do_not_underflow_to_zero_early() {
+        let tail = poisson_tail(0.01, 8);
+        // Dominated by the first term, 0.01^8 / 8!
+        let first = 0.01_f64.powi(8) / 40320.0 * (-0.01_f64).exp();
+        assert!((tail / first - 1.0).abs() < 2e-3, "{tail}");
+        assert!((p_value(8, 0.01, 1000.0) / (1000.0 * first) - 1.0).abs() < 2e-3);
+    }
+
+    #[test]
+    fn more_windows_and_more_background_mean_less_significance() {
+        let base = p_value(6, 0.05, 1000.0);
+        assert!(p_value(6, 0.05, 10_000.0) > base);
+        assert!(p_value(6, 0.5, 1000.0) > base);
+        assert!(p_value(12, 0.05, 1000.0) < base);
+    }
+
+    #[test]
+    fn random_hits_scale_with_the_query_and_hash_reuse() {
+        let stats = CatalogueStats {
+            songs: 1000,
+            fingerprints: 2_000_000,
+            distinct_hashes: 1_000_000,
+            mean_duration: 200.0,
+        };
+        // Two fingerprints per hash, shared between 1000 songs
+        assert!((stats.expected_random_hits(500) - 1.0).abs() < 1e-12);
+        assert_eq!(CatalogueStats::default().expected_random_hits(500), 0.0);
+    }
+}
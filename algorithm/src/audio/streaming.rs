@@ -0,0 +1,333 @@
+//! Identification while audio is still arriving, answering as soon as the
+//! evidence is strong enough rather than after a fixed-length recording.
+
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    ConstellationPoint, Fingerprint, FingerprintConfig, MatchResult, Matcher,
+    constellation::ConstellationBuilder, fingerprint::pair_anchors,
+    sample::BandpassFilterMonoStream,
+};
+
+/// Fingerprints audio pushed in pieces of any size, producing the same
+/// fingerprints, in the same order, as [`super::Fingerprinter::fingerprint_source`]
+/// does for the whole recording.
+///
+/// Anchors are paired once every chunk their targets may come from has
+/// settled, so fingerprints trail the audio by the largest target offset plus
+/// the peak picker's lookahead.
+pub struct StreamingFingerprinter {
+    config: FingerprintConfig,
+    front_end: BandpassFilterMonoStream,
+    constellation: ConstellationBuilder,
+    /// Settled chunks from the next anchor onwards.
+    points: BTreeMap<usize, Vec<ConstellationPoint>>,
+    next_anchor: usize,
+    samples: Vec<i16>,
+    settled: Vec<(usize, Vec<ConstellationPoint>)>,
+}
+
+impl StreamingFingerprinter {
+    /// Audio will be pushed as interleaved `channels`-channel samples at
+    /// `sample_rate` Hz.
+    pub fn new(config: FingerprintConfig, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            front_end: BandpassFilterMonoStream::new(
+                channels,
+                sample_rate,
+                config.sample_rate,
+                config.low_cutoff,
+                config.high_cutoff,
+            ),
+            constellation: ConstellationBuilder::new(&config, config.sample_rate),
+            config,
+            points: BTreeMap::new(),
+            next_anchor: 0,
+            samples: Vec::new(),
+            settled: Vec::new(),
+        }
+    }
+
+    /// Add the next interleaved `samples`, returning the fingerprints they complete.
+    pub fn push(&mut self, samples: &[i16]) -> Vec<Fingerprint> {
+        self.front_end.push(samples, &mut self.samples);
+        self.settle()
+    }
+
+    /// End of the audio: the remaining fingerprints.
+    pub fn finish(&mut self) -> Vec<Fingerprint> {
+        self.front_end.flush(&mut self.samples);
+        let mut fingerprints = self.settle();
+        self.constellation.finish(&mut self.settled);
+        self.store_settled();
+        while let Some((&anchor, _)) = self.points.range(self.next_anchor..).next() {
+            pair_anchors(&self.points, anchor, &self.config, &mut fingerprints);
+            self.next_anchor = anchor + 1;
+        }
+        self.points.clear();
+        fingerprints
+    }
+
+    fn settle(&mut self) -> Vec<Fingerprint> {
+        for sample in self.samples.drain(..) {
+            self.constellation.push(sample, &mut self.settled);
+        }
+        self.store_settled();
+
+        let mut fingerprints = Vec::new();
+        let Some(&last) = self.points.keys().next_back() else {
+            return fingerprints;
+        };
+        let reach = self
+            .config
+            .target_offsets
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0);
+        // Chunks settle in order, so every target of these anchors is known
+        while self.next_anchor + reach <= last {
+            pair_anchors(
+                &self.points,
+                self.next_anchor,
+                &self.config,
+                &mut fingerprints,
+            );
+            self.next_anchor += 1;
+        }
+        self.points = self.points.split_off(&self.next_anchor);
+        fingerprints
+    }
+
+    fn store_settled(&mut self) {
+        for (index, peaks) in self.settled.drain(..) {
+            self.points.entry(index).or_default().extend(peaks);
+        }
+    }
+}
+
+/// Matches a query that grows as audio arrives, keeping every song's hash hits
+/// between pushes so each one only pays for the new fingerprints.
+///
+/// Feed it the fingerprints from a [`StreamingFingerprinter`] together with
+/// the catalogue fingerprints sharing their hashes; it returns a result as
+/// soon as one song is significant under the [`Matcher`]'s thresholds, which
+/// for a clean recording takes a few seconds of audio.
+#[derive(Debug, Clone)]
+pub struct StreamingMatcher {
+    matcher: Matcher,
+    query_len: usize,
+    /// Times of the first and latest query fingerprints, in seconds.
+    query_span: Option<(f32, f32)>,
+    /// (query time, song time) of every pair of matching hashes, by song.
+    hits: HashMap<i64, Vec<(f32, f32)>>,
+}
+
+impl StreamingMatcher {
+    pub fn new(matcher: Matcher) -> Self {
+        Self {
+            matcher,
+            query_len: 0,
+            query_span: None,
+            hits: HashMap::new(),
+        }
+    }
+
+    /// Add the next `query` fingerprints and the catalogue fingerprints
+    /// sharing a hash with them, by song, as returned by
+    /// [`crate::model::find_similar_fingerprints`]. Returns the most
+    /// significant song once one passes the thresholds.
+    pub fn push(
+        &mut self,
+        query: &[Fingerprint],
+        candidates: HashMap<i64, Vec<Fingerprint>>,
+    ) -> Option<MatchResult> {
+        let mut query_times: HashMap<i64, Vec<f32>> = HashMap::new();
+        for fp in query {
+            let time = fp.time_offset.to_f32().unwrap_or_default();
+            query_times.entry(fp.hash).or_default().push(time);
+            self.query_span = Some(match self.query_span {
+                Some((first, last)) => (first.min(time), last.max(time)),
+                None => (time, time),
+            });
+        }
+        self.query_len += query.len();
+
+        for (song_id, song_fingerprints) in &candidates {
+            let hits = self.hits.entry(*song_id).or_default();
+            for song_fp in song_fingerprints {
+                if let Some(times) = query_times.get(&song_fp.hash) {
+                    let song_time = song_fp.time_offset.to_f32().unwrap_or_default();
+                    hits.extend(times.iter().map(|&query_time| (query_time, song_time)));
+                }
+            }
+        }
+
+        // Only songs with new hits can have become significant: a longer
+        // query makes the others' alignments less remarkable, not more
+        let query_duration = self.query_span.map_or(0.0, |(first, last)| last - first);
+        let songs = self
+            .matcher
+            .catalogue
+            .map_or(self.hits.len(), |c| c.songs.max(1));
+        candidates
+            .keys()
+            .filter_map(|song_id| {
+                self.matcher.score(
+                    *song_id,
+                    &self.hits[song_id],
+                    self.query_len,
+                    query_duration,
+                    songs,
+                )
+            })
+            .min_by(|a, b| {
+                a.p_value
+                    .total_cmp(&b.p_value)
+                    .then(b.matched_count.cmp(&a.matched_count))
+                    .then(a.song_id.cmp(&b.song_id))
+            })
+    }
+
+    /// Seconds of query fingerprinted so far.
+    pub fn heard(&self) -> f32 {
+        self.query_span.map_or(0.0, |(_, last)| last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::{CatalogueStats, Fingerprinter},
+        testing::music,
+    };
+    use itertools::Itertools;
+    use rodio::buffer::SamplesBuffer;
+
+    /// A matcher searching a catalogue of 10,000 songs like `song`.
+    fn catalogue_matcher(song: &[Fingerprint]) -> Matcher {
+        Matcher {
+            catalogue: Some(CatalogueStats {
+                songs: 10_000,
+                fingerprints: 10_000 * song.len(),
+                distinct_hashes: 1 << 20,
+                mean_duration: 40.0,
+            }),
+            ..Matcher::default()
+        }
+    }
+
+    fn fingerprint_all(
+        config: &FingerprintConfig,
+        samples: &[i16],
+        rate: u32,
+        channels: u16,
+    ) -> Vec<Fingerprint> {
+        let source = SamplesBuffer::new(channels, rate, samples.to_vec());
+        Fingerprinter::new(config.clone()).fingerprint_source(Box::new(source))
+    }
+
+    #[test]
+    fn streamed_fingerprints_match_the_whole_recording() {
+        let config = FingerprintConfig::music();
+        let samples = music(1, 6.0, 44_100, 2);
+        let expected = fingerprint_all(&config, &samples, 44_100, 2);
+
+        let mut streaming = StreamingFingerprinter::new(config, 44_100, 2);
+        // Odd piece sizes split frames across pushes
+        let mut fingerprints = Vec::new();
+        for piece in samples.chunks(997) {
+            fingerprints.extend(streaming.push(piece));
+        }
+        fingerprints.extend(streaming.finish());
+
+        let key = |fp: &Fingerprint| (fp.hash, fp.time_offset, fp.confidence);
+        assert!(!expected.is_empty());
+        assert_eq!(
+            fingerprints.iter().map(key).collect_vec(),
+            expected.iter().map(key).collect_vec()
+        );
+    }
+
+    #[test]
+    fn a_catalogued_song_is_identified_within_a_few_seconds() {
+        let config = FingerprintConfig::music();
+        let rate = config.sample_rate;
+        let song = music(7, 40.0, rate, 1);
+        let other = music(8, 40.0, rate, 1);
+        let catalogue: HashMap<i64, Vec<Fingerprint>> = HashMap::from([
+            (1, fingerprint_all(&config, &song, rate, 1)),
+            (2, fingerprint_all(&config, &other, rate, 1)),
+        ]);
+        let lookup = |query: &[Fingerprint]| -> HashMap<i64, Vec<Fingerprint>> {
+            let hashes: std::collections::HashSet<i64> = query.iter().map(|fp| fp.hash).collect();
+            catalogue
+                .iter()
+                .map(|(&id, fps)| {
+                    (
+                        id,
+                        fps.iter()
+                            .filter(|fp| hashes.contains(&fp.hash))
+                            .cloned()
+                            .collect_vec(),
+                    )
+                })
+                .filter(|(_, fps)| !fps.is_empty())
+                .collect()
+        };
+
+        // Start listening 12 s into the song
+        let start = 12 * rate as usize;
+        let mut fingerprinter = StreamingFingerprinter::new(config, rate, 1);
+        let mut matcher = StreamingMatcher::new(catalogue_matcher(&catalogue[&1]));
+        let mut result = None;
+        for piece in song[start..].chunks(rate as usize / 4) {
+            let query = fingerprinter.push(piece);
+            if let Some(found) = matcher.push(&query, lookup(&query)) {
+                result = Some(found);
+                break;
+            }
+        }
+
+        let result = result.expect("no match before the audio ran out");
+        assert_eq!(result.song_id, 1);
+        assert!(
+            matcher.heard() <= 5.0,
+            "answered after {} s",
+            matcher.heard()
+        );
+        assert!(
+            (result.time_offset - 12.0).abs() < 0.3,
+            "{}",
+            result.time_offset
+        );
+    }
+
+    #[test]
+    fn audio_outside_the_catalogue_gets_no_answer() {
+        let config = FingerprintConfig::music();
+        let rate = config.sample_rate;
+        let catalogue = fingerprint_all(&config, &music(7, 40.0, rate, 1), rate, 1);
+        let unknown = music(9, 20.0, rate, 1);
+
+        let mut fingerprinter = StreamingFingerprinter::new(config, rate, 1);
+        let mut matcher = StreamingMatcher::new(catalogue_matcher(&catalogue));
+        for piece in unknown.chunks(rate as usize / 4) {
+            let query = fingerprinter.push(piece);
+            let hashes: std::collections::HashSet<i64> = query.iter().map(|fp| fp.hash).collect();
+            let shared = catalogue
+                .iter()
+                .filter(|fp| hashes.contains(&fp.hash))
+                .cloned()
+                .collect_vec();
+            let candidates = if shared.is_empty() {
+                HashMap::new()
+            } else {
+                HashMap::from([(1, shared)])
+            };
+            assert!(matcher.push(&query, candidates).is_none());
+        }
+    }
+}
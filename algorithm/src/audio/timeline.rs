@@ -0,0 +1,321 @@
+use anyhow::{Result, ensure};
+use itertools::Itertools;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::HashMap;
+
+use super::{Fingerprint, MatchResult, Matcher};
+
+/// A stretch of a recording identified as one song.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineSegment {
+    /// Start of the segment in the recording, in seconds.
+    pub start: f32,
+    /// End of the segment in the recording, in seconds.
+    pub end: f32,
+    pub song_id: i64,
+    /// Position in the song heard at `start`, in seconds.
+    pub offset_in_song: f32,
+    /// Playback speed of the recording relative to the song, as in
+    /// [`MatchResult::speed`].
+    pub speed: f32,
+    /// Mean confidence of the windows merged into the segment.
+    pub confidence: f32,
+}
+
+/// Cuts a long recording (a DJ mix, an hour of radio) into the songs it plays.
+///
+/// A `window`-second query slides over the recording `hop` seconds at a time
+/// and is matched on its own. Consecutive windows won by the same song at the
+/// same alignment are merged into one segment, bridging up to `max_gap`
+/// seconds of unmatched windows, and overlapping segments are split halfway.
+#[derive(Debug, Clone)]
+pub struct TimelineMatcher {
+    pub matcher: Matcher,
+    /// Length of each query window, in seconds.
+    pub window: f32,
+    /// Distance between the starts of consecutive windows, in seconds.
+    pub hop: f32,
+    /// Longest run of unmatched recording a segment may span, in seconds.
+    pub max_gap: f32,
+    /// How far apart two windows' song alignments may be and still belong to
+    /// the same segment, in seconds.
+    pub offset_tolerance: f32,
+}
+
+impl Default for TimelineMatcher {
+    fn default() -> Self {
+        Self {
+            matcher: Matcher::default(),
+            window: 10.0,
+            hop: 5.0,
+            max_gap: 10.0,
+            offset_tolerance: 1.0,
+        }
+    }
+}
+
+/// The best match of one window, aligned to the recording.
+struct WindowMatch {
+    start: f32,
+    end: f32,
+    /// The matcher's result with `time_offset` measured from the start of the
+    /// recording, so it stays the same while one song keeps playing.
+    result: MatchResult,
+}
+
+impl TimelineMatcher {
+    /// Segments of `recording`, in order. `candidates` are the catalogue
+    /// fingerprints sharing a hash with any of the recording, by song, as
+    /// returned by [`crate::model::find_similar_fingerprints`] for the whole
+    /// recording. `window` and `hop` must be positive.
+    pub fn segments(
+        &self,
+        recording: &[Fingerprint],
+        candidates: &HashMap<i64, Vec<Fingerprint>>,
+    ) -> Result<Vec<TimelineSegment>> {
+        ensure!(
+            self.window.is_finite() && self.window > 0.0,
+            "a timeline window must last a positive number of seconds, not {}",
+            self.window
+        );
+        ensure!(
+            self.hop.is_finite() && self.hop > 0.0,
+            "timeline windows must be a positive number of seconds apart, not {}",
+            self.hop
+        );
+        let by_hash: HashMap<i64, Vec<(i64, &Fingerprint)>> = candidates
+            .iter()
+            .flat_map(|(&song_id, fps)| fps.iter().map(move |fp| (song_id, fp)))
+            .into_group_map_by(|(_, fp)| fp.hash);
+        let timed = recording
+            .iter()
+            .map(|fp| (fp.time_offset.to_f32().unwrap_or_default(), fp))
+            .sorted_by(|(a, _), (b, _)| a.total_cmp(b))
+            .collect_vec();
+        let Some(&(duration, _)) = timed.last() else {
+            return Ok(Vec::new());
+        };
+
+        let windows = (0..)
+            .map(|i| i as f32 * self.hop)
+            .take_while(|&start| start == 0.0 || start < duration)
+            .filter_map(|start| {
+                let end = start + self.window;
+                // `timed` is sorted, so the window's fingerprints are a slice of it
+                let first = timed.partition_point(|(time, _)| *time < start);
+                let last = timed.partition_point(|(time, _)| *time < end);
+                let query = timed[first..last]
+                    .iter()
+                    .map(|(_, fp)| (*fp).clone())
+                    .collect_vec();
+                let potential_matches: HashMap<i64, Vec<Fingerprint>> = query
+                    .iter()
+                    .map(|fp| fp.hash)
+                    .unique()
+                    .filter_map(|hash| by_hash.get(&hash))
+                    .flatten()
+                    .map(|&(song_id, fp)| (song_id, fp.clone()))
+                    .into_group_map();
+                let result = self
+                    .matcher
+                    .identify(&query, potential_matches)
+                    .into_iter()
+                    .next()?;
+                Some(WindowMatch { start, end, result })
+            });
+
+        Ok(self.merge(windows))
+    }
+
+    fn merge(&self, windows: impl Iterator<Item = WindowMatch>) -> Vec<TimelineSegment> {
+        let mut segments: Vec<(TimelineSegment, usize)> = Vec::new();
+        // Alignment of the song in the last segment with the recording
+        let mut alignment = 0.0;
+
+        for window in windows {
+            let result = &window.result;
+            if let Some((segment, merged)) = segments.last_mut()
+                && segment.song_id == result.song_id
+                && (alignment - result.time_offset).abs() <= self.offset_tolerance
+                && window.start <= segment.end + self.max_gap
+            {
+                segment.end = window.end;
+                segment.confidence += result.confidence;
+                *merged += 1;
+                continue;
+            }
+            alignment = result.time_offset;
+            segments.push((
+                TimelineSegment {
+                    start: window.start,
+                    end: window.end,
+                    song_id: result.song_id,
+                    offset_in_song: result.time_offset + result.speed * window.start,
+                    speed: result.speed,
+                    confidence: result.confidence,
+                },
+                1,
+            ));
+        }
+
+        let mut segments = segments
+            .into_iter()
+            .map(|(segment, merged)| TimelineSegment {
+                confidence: segment.confidence / merged as f32,
+                ..segment
+            })
+            .collect_vec();
+        // Windows straddling a change of song let neighbouring segments overlap
+        for i in 1..segments.len() {
+            let (previous, next) = (segments[i - 1].end, segments[i].start);
+            if previous > next {
+                let boundary = (previous + next) / 2.0;
+                let moved = boundary - next;
+                segments[i - 1].end = boundary;
+                let segment = &mut segments[i];
+                segment.start = boundary;
+                // Song time passes `speed` times as fast as recording time
+                segment.offset_in_song += segment.speed * moved;
+            }
+        }
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::{Decimal, prelude::FromPrimitive};
+
+    fn fingerprint(hash: i64, time_offset: f32) -> Fingerprint {
+        Fingerprint {
+            hash,
+            time_offset: Decimal::from_f32(time_offset).unwrap(),
+            confidence: Decimal::ZERO,
+            anchor_freq: Decimal::ZERO,
+            target_freq: Decimal::ZERO,
+            delta_t: Decimal::ZERO,
+        }
+    }
+
+    /// A recording built from `(song_id, offset_in_song, seconds)` pieces
+    /// played back to back, with four fingerprints a second, and the songs'
+    /// catalogue entries. Hashes are unique to a song and position; a song id
+    /// of 0 is audio outside the catalogue.
+    fn recording(pieces: &[(i64, f32, f32)]) -> (Vec<Fingerprint>, HashMap<i64, Vec<Fingerprint>>) {
+        let mut recording = Vec::new();
+        let mut catalogue: HashMap<i64, Vec<Fingerprint>> = HashMap::new();
+        let mut start = 0.0;
+        for &(song_id, offset, seconds) in pieces {
+            for i in 0..(seconds * 4.0) as usize {
+                let in_song = offset + i as f32 * 0.25;
+                let hash = song_id * 1_000_000 + (in_song * 4.0) as i64;
+                recording.push(fingerprint(hash, start + i as f32 * 0.25));
+                if song_id != 0 {
+                    catalogue
+                        .entry(song_id)
+                        .or_default()
+                        .push(fingerprint(hash, in_song));
+                }
+            }
+            start += seconds;
+        }
+        (recording, catalogue)
+    }
+
+    #[test]
+    fn back_to_back_songs_become_separate_segments() {
+        let (recording, catalogue) = recording(&[(1, 30.0, 60.0), (2, 10.0, 60.0)]);
+
+        let segments = TimelineMatcher::default()
+            .segments(&recording, &catalogue)
+            .unwrap();
+
+        assert_eq!(segments.len(), 2);
+        let [first, second] = [&segments[0], &segments[1]];
+        assert_eq!((first.song_id, second.song_id), (1, 2));
+        assert_eq!(first.start, 0.0);
+        assert!((first.offset_in_song - 30.0).abs() < 0.1);
+        // The boundary lands within a hop of the real change at 60 s
+        assert!((second.start - 60.0).abs() <= 5.0, "{}", second.start);
+        assert_eq!(first.end, second.start);
+        assert!(
+            (second.offset_in_song - (10.0 + second.start - 60.0)).abs() < 0.1,
+            "{}",
+            second.offset_in_song
+        );
+        assert!(second.end >= 120.0);
+    }
+
+    #[test]
+    fn audio_outside_the_catalogue_is_left_out() {
+        let (recording, catalogue) = recording(&[(1, 0.0, 40.0), (0, 0.0, 60.0), (2, 0.0, 40.0)]);
+
+        let segments = TimelineMatcher::default()
+            .segments(&recording, &catalogue)
+            .unwrap();
+
+        assert_eq!(segments.iter().map(|s| s.song_id).collect_vec(), vec![1, 2]);
+        assert!(segments[0].end <= 50.0);
+        assert!(segments[1].start >= 90.0);
+    }
+
+    #[test]
+    fn split_segments_advance_through_the_song_at_its_speed() {
+        let window = |start: f32, song_id: i64, time_offset: f32, speed: f32| WindowMatch {
+            start,
+            end: start + 10.0,
+            result: MatchResult {
+                song_id,
+                confidence: 0.5,
+                matched_count: 40,
+                time_offset,
+                speed,
+                bin_counts: Vec::new(),
+                p_value: 1e-12,
+                false_positives: 1e-8,
+            },
+        };
+        // The second song, sped up 5%, starts in a window overlapping the first
+        let windows = [
+            window(0.0, 1, 0.0, 1.0),
+            window(5.0, 1, 0.0, 1.0),
+            window(10.0, 2, 20.0, 1.05),
+        ];
+
+        let segments = TimelineMatcher::default().merge(windows.into_iter());
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].start, 12.5);
+        assert_eq!(segments[1].speed, 1.05);
+        assert!((segments[1].offset_in_song - (20.0 + 1.05 * 12.5)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_jump_within_a_song_starts_a_new_segment() {
+        // The same song cut from 20 s straight to 100 s, as in an edit
+        let (recording, catalogue) = recording(&[(1, 0.0, 40.0), (1, 100.0, 40.0)]);
+
+        let segments = TimelineMatcher::default()
+            .segments(&recording, &catalogue)
+            .unwrap();
+
+        assert_eq!(segments.len(), 2);
+        assert!(segments.iter().all(|s| s.song_id == 1));
+        assert!((segments[1].offset_in_song - (100.0 + segments[1].start - 40.0)).abs() < 0.1);
+    }
+
+    #[test]
+    fn windows_that_never_advance_are_refused() {
+        let (recording, catalogue) = recording(&[(1, 0.0, 20.0)]);
+
+        for (window, hop) in [(10.0, 0.0), (10.0, -5.0), (10.0, f32::NAN), (0.0, 5.0)] {
+            let timeline = TimelineMatcher {
+                window,
+                hop,
+                ..TimelineMatcher::default()
+            };
+            assert!(timeline.segments(&recording, &catalogue).is_err());
+        }
+    }
+}
@@ -0,0 +1,453 @@
+//! Accuracy evaluation: identify a set of clips whose songs are known, and
+//! measure how often the answer is right, how often an answer is given for
+//! audio that isn't catalogued, and how long answers take.
+//!
+//! Clips come from a labelled list, or are cut at random from audio files,
+//! labelled with the songs they hold when those files are the catalogue's own.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    Fingerprinter, MatchResult, Matcher, SongInfo,
+    identify::identify_file,
+    model::FingerprintStore,
+    rng::XorShift,
+    source::{container_duration, song_from_file},
+};
+
+/// The largest [`MatchResult::false_positives`] accepted at each threshold,
+/// unless others are given: from accepting any match down to very strict.
+pub const DEFAULT_THRESHOLDS: &[f64] = &[1.0, 0.1, 0.01, 1e-3, 1e-6];
+
+/// A clip of an audio file to identify, and the song it holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalQuery {
+    pub path: PathBuf,
+    pub start: Duration,
+    /// To the end of the file when `None`.
+    pub duration: Option<Duration>,
+    /// `None` for audio that isn't in the catalogue.
+    pub expected: Option<SongInfo>,
+}
+
+/// A row of a labels file.
+#[derive(Debug, Deserialize)]
+struct Label {
+    query: PathBuf,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+/// Read a CSV file of labelled clips with the header `query,title,artist`,
+/// where `query` is an audio file relative to the labels file and a clip with
+/// no title isn't in the catalogue.
+pub fn load_labels(path: &Path) -> Result<Vec<EvalQuery>> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut reader =
+        csv::Reader::from_path(path).with_context(|| format!("reading {}", path.display()))?;
+    reader
+        .deserialize::<Label>()
+        .map(|label| {
+            let label = label.with_context(|| format!("reading {}", path.display()))?;
+            let expected = label
+                .title
+                .map(|title| SongInfo::new(title, label.artist.unwrap_or_default()));
+            Ok(EvalQuery {
+                path: dir.join(label.query),
+                start: Duration::ZERO,
+                duration: None,
+                expected,
+            })
+        })
+        .collect()
+}
+
+/// How [`cut_clips`] cuts clips.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipOptions {
+    pub clips_per_file: usize,
+    pub seconds: f64,
+    /// The same seed cuts the same clips.
+    pub seed: u64,
+}
+
+impl Default for ClipOptions {
+    fn default() -> Self {
+        Self {
+            clips_per_file: 3,
+            seconds: 10.0,
+            seed: 1,
+        }
+    }
+}
+
+/// Cut clips at random from each of `paths`, labelled with the song each
+/// file holds (see [`song_from_file`]) when `catalogued`, or as out of the
+/// catalogue otherwise. Files no longer than a clip are used whole; files
+/// that can't be read are logged and passed over.
+pub fn cut_clips(
+    paths: &[PathBuf],
+    catalogued: bool,
+    options: ClipOptions,
+) -> Result<Vec<EvalQuery>> {
+    let mut random = XorShift::new(options.seed);
+    let mut queries = Vec::new();
+    for path in paths {
+        let length = match file_duration(path) {
+            Ok(length) => length,
+            Err(e) => {
+                warn!("Skipping {}: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        let expected = catalogued.then(|| song_from_file(path));
+        if length <= options.seconds {
+            queries.push(EvalQuery {
+                path: path.clone(),
+                start: Duration::ZERO,
+                duration: None,
+                expected,
+            });
+            continue;
+        }
+        for _ in 0..options.clips_per_file {
+            queries.push(EvalQuery {
+                path: path.clone(),
+                start: Duration::from_secs_f64(random.next_f64() * (length - options.seconds)),
+                duration: Some(Duration::from_secs_f64(options.seconds)),
+                expected: expected.clone(),
+            });
+        }
+    }
+    Ok(queries)
+}
+
+/// What identifying one query gave.
+#[derive(Debug, Clone)]
+pub struct EvalOutcome {
+    pub query: EvalQuery,
+    /// The best match and the song it is, if anything matched.
+    pub best: Option<(MatchResult, SongInfo)>,
+    pub latency: Duration,
+    pub error: Option<String>,
+}
+
+impl EvalOutcome {
+    /// Whether the best match is the expected song, at any significance.
+    fn is_correct(&self) -> bool {
+        match (&self.query.expected, &self.best) {
+            (Some(expected), Some((_, song))) => {
+                expected.title == song.title && expected.artist == song.artist
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a match would be reported with at most `threshold` false
+    /// positives expected.
+    fn is_accepted(&self, threshold: f64) -> bool {
+        self.best
+            .as_ref()
+            .is_some_and(|(result, _)| result.false_positives <= threshold)
+    }
+}
+
+/// Identify each of `queries`. `matcher` should not gate matches on their
+/// significance (its `max_false_positives` infinite) so [`EvalSummary`] can
+/// apply each threshold itself. A query that fails is recorded, not fatal.
+#[instrument(skip_all, fields(queries = queries.len()))]
+pub async fn evaluate(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    matcher: &Matcher,
+    queries: Vec<EvalQuery>,
+) -> Result<Vec<EvalOutcome>> {
+    let mut outcomes = Vec::with_capacity(queries.len());
+    for query in queries {
+        let started = Instant::now();
+        let identified = identify_file(
+            store,
+            fingerprinter,
+            matcher,
+            &query.path,
+            query.start,
+            query.duration,
+        )
+        .await;
+        let latency = started.elapsed();
+
+        let named = match identified {
+            Ok(results) => best_named(store, results).await,
+            Err(e) => Err(e),
+        };
+        let (best, error) = match named {
+            Ok(best) => (best, None),
+            Err(e) => {
+                warn!("Failed to identify {}: {:#}", query.path.display(), e);
+                (None, Some(format!("{e:#}")))
+            }
+        };
+        outcomes.push(EvalOutcome {
+            query,
+            best,
+            latency,
+            error,
+        });
+    }
+    info!("Evaluated {} queries", outcomes.len());
+    Ok(outcomes)
+}
+
+/// The best of `results`, if any, and the song it is.
+async fn best_named(
+    store: &impl FingerprintStore,
+    results: Vec<MatchResult>,
+) -> Result<Option<(MatchResult, SongInfo)>> {
+    let Some(result) = results.into_iter().next() else {
+        return Ok(None);
+    };
+    let songs = store.song_info(&[result.song_id]).await?;
+    let song = songs
+        .get(&result.song_id)
+        .map(|(title, artist, _)| SongInfo::new(title, artist))
+        .with_context(|| format!("song {} isn't catalogued", result.song_id))?;
+    Ok(Some((result, song)))
+}
+
+/// Precision and recall of the catalogue's songs, and how often audio from
+/// outside it is matched, when matches need at most `max_false_positives`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThresholdMetrics {
+    pub max_false_positives: f64,
+    /// Share of accepted matches that are the right song; `None` when none
+    /// are accepted.
+    pub precision: Option<f64>,
+    /// Share of catalogued clips matched to the right song.
+    pub recall: Option<f64>,
+    /// Share of out-of-catalogue clips matched to any song.
+    pub false_positive_rate: Option<f64>,
+}
+
+/// Nearest-rank percentiles of the time to identify a query, over the queries
+/// that could be identified; ones that failed early would flatter them.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Latency {
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// The accuracy of an evaluation run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EvalSummary {
+    pub queries: usize,
+    pub in_catalogue: usize,
+    pub out_of_catalogue: usize,
+    /// Queries that couldn't be identified at all, such as unreadable files.
+    pub errors: usize,
+    /// Share of catalogued clips whose best match is the right song, however
+    /// significant.
+    pub top1_accuracy: Option<f64>,
+    pub thresholds: Vec<ThresholdMetrics>,
+    pub latency: Latency,
+}
+
+impl EvalSummary {
+    pub fn new(outcomes: &[EvalOutcome], thresholds: &[f64]) -> Self {
+        let (catalogued, uncatalogued): (Vec<_>, Vec<_>) = outcomes
+            .iter()
+            .partition(|outcome| outcome.query.expected.is_some());
+        let share = |count: usize, of: usize| (of > 0).then(|| count as f64 / of as f64);
+
+        let thresholds = thresholds
+            .iter()
+            .map(|&threshold| {
+                let accepted = outcomes.iter().filter(|o| o.is_accepted(threshold));
+                let (right, wrong) = accepted.fold((0, 0), |(right, wrong), outcome| {
+                    if outcome.is_correct() {
+                        (right + 1, wrong)
+                    } else {
+                        (right, wrong + 1)
+                    }
+                });
+                let matched_uncatalogued = uncatalogued
+                    .iter()
+                    .filter(|o| o.is_accepted(threshold))
+                    .count();
+                ThresholdMetrics {
+                    max_false_positives: threshold,
+                    precision: share(right, right + wrong),
+                    recall: share(right, catalogued.len()),
+                    false_positive_rate: share(matched_uncatalogued, uncatalogued.len()),
+                }
+            })
+            .collect();
+
+        let latencies = outcomes
+            .iter()
+            .filter(|outcome| outcome.error.is_none())
+            .map(|outcome| outcome.latency.as_secs_f64() * 1000.0)
+            .sorted_by(f64::total_cmp)
+            .collect_vec();
+        let percentile = |p: f64| {
+            let rank = ((p * latencies.len() as f64).ceil() as usize).max(1);
+            latencies.get(rank - 1).copied().unwrap_or_default()
+        };
+
+        Self {
+            queries: outcomes.len(),
+            in_catalogue: catalogued.len(),
+            out_of_catalogue: uncatalogued.len(),
+            errors: outcomes.iter().filter(|o| o.error.is_some()).count(),
+            top1_accuracy: share(
+                catalogued.iter().filter(|o| o.is_correct()).count(),
+                catalogued.len(),
+            ),
+            thresholds,
+            latency: Latency {
+                p50_ms: percentile(0.5),
+                p90_ms: percentile(0.9),
+                p99_ms: percentile(0.99),
+                max_ms: latencies.last().copied().unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Length of the audio file at `path` in seconds, as its container records
+/// it, or by decoding it when the container doesn't say.
+fn file_duration(path: &Path) -> Result<f64> {
+    if let Some(length) = container_duration(path)? {
+        return Ok(length);
+    }
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let audio = Fingerprinter::decode(BufReader::new(file))?;
+    let frame_rate = audio.sample_rate() as f64 * audio.channels() as f64;
+    Ok(audio.count() as f64 / frame_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(expected: Option<&str>, best: Option<(&str, f64)>, latency_ms: u64) -> EvalOutcome {
+        EvalOutcome {
+            query: EvalQuery {
+                path: PathBuf::from("clip.wav"),
+                start: Duration::ZERO,
+                duration: None,
+                expected: expected.map(|title| SongInfo::new(title, "Artist")),
+            },
+            best: best.map(|(title, false_positives)| {
+                let result = MatchResult {
+                    song_id: 1,
+                    confidence: 0.5,
+                    matched_count: 20,
+                    time_offset: 0.0,
+                    speed: 1.0,
+                    bin_counts: Vec::new(),
+                    p_value: false_positives / 100.0,
+                    false_positives,
+                };
+                (result, SongInfo::new(title, "Artist"))
+            }),
+            latency: Duration::from_millis(latency_ms),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn summaries_count_right_and_wrong_answers_at_each_threshold() {
+        let outcomes = vec![
+            outcome(Some("One"), Some(("One", 1e-9)), 10),
+            outcome(Some("Two"), Some(("Two", 0.05)), 20),
+            outcome(Some("Three"), Some(("One", 0.5)), 30),
+            outcome(Some("Four"), None, 40),
+            outcome(None, Some(("One", 0.005)), 50),
+            outcome(None, None, 60),
+        ];
+
+        let summary = EvalSummary::new(&outcomes, &[1.0, 0.01]);
+
+        assert_eq!(
+            (
+                summary.queries,
+                summary.in_catalogue,
+                summary.out_of_catalogue
+            ),
+            (6, 4, 2)
+        );
+        assert_eq!(summary.top1_accuracy, Some(0.5));
+        assert_eq!(
+            summary.thresholds[0],
+            ThresholdMetrics {
+                max_false_positives: 1.0,
+                precision: Some(0.5),
+                recall: Some(0.5),
+                false_positive_rate: Some(0.5),
+            }
+        );
+        assert_eq!(
+            summary.thresholds[1],
+            ThresholdMetrics {
+                max_false_positives: 0.01,
+                precision: Some(0.5),
+                recall: Some(0.25),
+                false_positive_rate: Some(0.5),
+            }
+        );
+        assert_eq!(
+            summary.latency,
+            Latency {
+                p50_ms: 30.0,
+                p90_ms: 60.0,
+                p99_ms: 60.0,
+                max_ms: 60.0,
+            }
+        );
+    }
+
+    #[test]
+    fn an_empty_run_has_no_rates() {
+        let summary = EvalSummary::new(&[], DEFAULT_THRESHOLDS);
+        assert_eq!(summary.top1_accuracy, None);
+        assert!(summary.thresholds.iter().all(|t| t.precision.is_none()
+            && t.recall.is_none()
+            && t.false_positive_rate.is_none()));
+        assert_eq!(summary.latency, Latency::default());
+    }
+
+    #[test]
+    fn failed_queries_dont_count_towards_latency() {
+        let failed = EvalOutcome {
+            error: Some("unreadable".to_string()),
+            ..outcome(Some("Two"), None, 1)
+        };
+        let outcomes = vec![outcome(Some("One"), Some(("One", 1e-9)), 40), failed];
+
+        let summary = EvalSummary::new(&outcomes, DEFAULT_THRESHOLDS);
+
+        assert_eq!(summary.errors, 1);
+        assert_eq!(
+            summary.latency,
+            Latency {
+                p50_ms: 40.0,
+                p90_ms: 40.0,
+                p99_ms: 40.0,
+                max_ms: 40.0,
+            }
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Identification end to end: fingerprint audio, look it up in the catalogue
+//! and match it.
+
+use std::{fs::File, io::BufReader, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use rodio::Source;
+use tokio::sync::mpsc;
+use tracing::{debug, info, instrument};
+
+use crate::{
+    audio::{
+        Fingerprinter, MatchResult, Matcher, StreamingMatcher, TimelineMatcher, TimelineSegment,
+    },
+    model::FingerprintStore,
+};
+
+/// Identify the song playing in `source`, a clip of a single song. Results
+/// are ordered best first, as [`Matcher::identify`] gives them.
+#[instrument(skip_all)]
+pub async fn identify_clip(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    matcher: &Matcher,
+    source: Box<dyn Source<Item = i16>>,
+) -> Result<Vec<MatchResult>> {
+    let fingerprints = fingerprinter.fingerprint_source(source);
+    let candidates = store
+        .find_similar(fingerprinter.config().hash_scheme, &fingerprints)
+        .await?;
+    info!(
+        "Matching {} fingerprints against {} candidate songs",
+        fingerprints.len(),
+        candidates.len()
+    );
+    Ok(matcher.identify(&fingerprints, candidates))
+}
+
+/// Identify the song playing in the audio file at `path`, listening from
+/// `start` for `duration`, or to the end of the file.
+pub async fn identify_file(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    matcher: &Matcher,
+    path: &Path,
+    start: Duration,
+    duration: Option<Duration>,
+) -> Result<Vec<MatchResult>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let audio = Fingerprinter::decode(BufReader::new(file))?.skip_duration(start);
+    let audio: Box<dyn Source<Item = i16>> = match duration {
+        Some(duration) => Box::new(audio.take_duration(duration)),
+        None => Box::new(audio),
+    };
+    identify_clip(store, fingerprinter, matcher, audio).await
+}
+
+/// Identify every catalogued song played in `source`, a recording of any
+/// length. The whole recording is fingerprinted and looked up once, then cut
+/// into segments by `timeline`.
+#[instrument(skip_all)]
+pub async fn identify_timeline(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    timeline: &TimelineMatcher,
+    source: Box<dyn Source<Item = i16>>,
+) -> Result<Vec<TimelineSegment>> {
+    let fingerprints = fingerprinter.fingerprint_source(source);
+    let candidates = store
+        .find_similar(fingerprinter.config().hash_scheme, &fingerprints)
+        .await?;
+    let segments = timeline.segments(&fingerprints, &candidates)?;
+    info!(
+        "Found {} segments from {} candidate songs",
+        segments.len(),
+        candidates.len()
+    );
+    Ok(segments)
+}
+
+/// Identify audio as it is recorded, returning as soon as one song is
+/// significant. `audio` delivers interleaved `channels`-channel samples at
+/// `sample_rate` Hz in pieces of any size; closing it ends the attempt, and
+/// `None` means nothing matched by then. Each piece is fingerprinted on the
+/// blocking pool, off the runtime's worker threads.
+#[instrument(skip_all)]
+pub async fn identify_live(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    matcher: Matcher,
+    sample_rate: u32,
+    channels: u16,
+    audio: &mut mpsc::Receiver<Vec<i16>>,
+) -> Result<Option<MatchResult>> {
+    let scheme = fingerprinter.config().hash_scheme;
+    let mut streaming = fingerprinter.streaming(sample_rate, channels);
+    let mut matcher = StreamingMatcher::new(matcher);
+
+    loop {
+        let samples = audio.recv().await;
+        let finished = samples.is_none();
+        let fingerprints;
+        (streaming, fingerprints) = tokio::task::spawn_blocking(move || {
+            let fingerprints = match samples {
+                Some(samples) => streaming.push(&samples),
+                None => streaming.finish(),
+            };
+            (streaming, fingerprints)
+        })
+        .await?;
+        if !fingerprints.is_empty() {
+            let candidates = store.find_similar(scheme, &fingerprints).await?;
+            if let Some(result) = matcher.push(&fingerprints, candidates) {
+                info!(
+                    "Identified song {} after {:.1}s of audio",
+                    result.song_id,
+                    matcher.heard()
+                );
+                return Ok(Some(result));
+            }
+        }
+        if finished {
+            debug!("No match in {:.1}s of audio", matcher.heard());
+            return Ok(None);
+        }
+    }
+}
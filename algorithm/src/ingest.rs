@@ -0,0 +1,309 @@
+//! Ingestion end to end: fetch each track of a source, fingerprint it and add
+//! it to the catalogue.
+//!
+//! Tracks are fetched and fingerprinted several at a time, decoding on
+//! blocking threads so every core is busy, while the songs they produce are
+//! written to the store in batches.
+
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use futures::{StreamExt, stream};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    audio::{Fingerprinter, HashScheme},
+    model::{FingerprintStore, NewSong},
+    source::{AudioSource, Track},
+};
+
+/// How [`ingest_with`] spreads the work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IngestOptions {
+    /// Tracks fetched and fingerprinted at once.
+    pub workers: usize,
+    /// Songs written to the store per transaction.
+    pub batch_size: usize,
+}
+
+impl Default for IngestOptions {
+    /// A worker per core, and batches of 16 songs.
+    fn default() -> Self {
+        Self {
+            workers: std::thread::available_parallelism().map_or(4, NonZeroUsize::get),
+            batch_size: 16,
+        }
+    }
+}
+
+/// What became of each track of a source.
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    /// Tracks added to the catalogue, with their new song ids.
+    pub ingested: Vec<(Track, i64)>,
+    /// Tracks skipped because the catalogue already had them, with their ids.
+    pub skipped: Vec<(Track, i64)>,
+    /// Tracks that couldn't be fetched, decoded or stored, and why.
+    pub failed: Vec<(Track, String)>,
+    /// Files the source passed over because they aren't audio.
+    pub ignored: Vec<String>,
+    /// Directories the source couldn't read, and why.
+    pub unreadable: Vec<(String, String)>,
+}
+
+impl IngestReport {
+    /// The catalogue ids of every track that was ingested or already there.
+    pub fn song_ids(&self) -> Vec<i64> {
+        self.ingested
+            .iter()
+            .chain(&self.skipped)
+            .map(|(_, song_id)| *song_id)
+            .collect()
+    }
+}
+
+/// What ingesting a single track did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ingested {
+    Added(i64),
+    AlreadyCatalogued(i64),
+}
+
+/// A track, ready to be written to the store or already there.
+enum Prepared {
+    Fingerprinted(NewSong),
+    AlreadyCatalogued(i64),
+}
+
+/// Catalogue every track of `source` not already in `store`, with the default
+/// [`IngestOptions`].
+pub async fn ingest(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    source: &impl AudioSource,
+) -> Result<IngestReport> {
+    ingest_with(store, fingerprinter, source, IngestOptions::default()).await
+}
+
+/// Catalogue every track of `source` not already in `store`. A track that
+/// fails doesn't stop the rest; failures are collected in the report, which
+/// lists tracks in the order the source gave them, along with whatever the
+/// source passed over listing them. Only listing the source's tracks failing
+/// outright is an error.
+///
+/// When a batch can't be written, its songs are retried one at a time so a
+/// single bad song only fails itself.
+#[instrument(skip_all, fields(workers = options.workers, batch_size = options.batch_size))]
+pub async fn ingest_with(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    source: &impl AudioSource,
+    options: IngestOptions,
+) -> Result<IngestReport> {
+    let listing = source.tracks().await?;
+    let tracks = listing.tracks;
+    let scheme = fingerprinter.config().hash_scheme;
+    let mut progress = Progress::new(tracks.len());
+    let mut outcomes: Vec<(usize, Track, Result<Ingested, String>)> = Vec::new();
+    let mut batch: Vec<(usize, Track, NewSong)> = Vec::new();
+    // Songs added by this run, so a second copy of one is reported as skipped
+    let mut added = HashSet::new();
+
+    let mut prepared = stream::iter(tracks.into_iter().enumerate())
+        .map(|(index, mut track)| async move {
+            let prepared = prepare(store, fingerprinter, source, &mut track).await;
+            (index, track, prepared)
+        })
+        .buffer_unordered(options.workers.max(1));
+
+    while let Some((index, track, prepared)) = prepared.next().await {
+        match prepared {
+            Ok(Prepared::Fingerprinted(song)) => batch.push((index, track, song)),
+            Ok(Prepared::AlreadyCatalogued(song_id)) => {
+                outcomes.push((index, track, Ok(Ingested::AlreadyCatalogued(song_id))));
+            }
+            Err(e) => outcomes.push((index, track, Err(format!("{e:#}")))),
+        }
+        if batch.len() >= options.batch_size.max(1) {
+            write_batch(store, scheme, &mut batch, &mut added, &mut outcomes).await;
+        }
+        progress.update(outcomes.len() + batch.len());
+    }
+    write_batch(store, scheme, &mut batch, &mut added, &mut outcomes).await;
+
+    outcomes.sort_by_key(|(index, ..)| *index);
+    let mut report = IngestReport {
+        ignored: listing.ignored,
+        unreadable: listing.unreadable,
+        ..IngestReport::default()
+    };
+    for (_, track, outcome) in outcomes {
+        match outcome {
+            Ok(Ingested::Added(song_id)) => report.ingested.push((track, song_id)),
+            Ok(Ingested::AlreadyCatalogued(song_id)) => report.skipped.push((track, song_id)),
+            Err(e) => report.failed.push((track, e)),
+        }
+    }
+
+    for (track, error) in &report.failed {
+        warn!("Failed to ingest {}: {}", track.location, error);
+    }
+    info!(
+        "Ingested {} songs, skipped {} already catalogued, {} failed, {} files ignored and {} unreadable, in {:.1?}",
+        report.ingested.len(),
+        report.skipped.len(),
+        report.failed.len(),
+        report.ignored.len(),
+        report.unreadable.len(),
+        progress.started.elapsed()
+    );
+    Ok(report)
+}
+
+/// Catalogue one track of `source`, unless `store` already has it.
+#[instrument(skip(store, fingerprinter, source))]
+pub async fn ingest_track(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    source: &impl AudioSource,
+    track: &Track,
+) -> Result<Ingested> {
+    let mut track = track.clone();
+    match prepare(store, fingerprinter, source, &mut track).await? {
+        Prepared::AlreadyCatalogued(song_id) => Ok(Ingested::AlreadyCatalogued(song_id)),
+        Prepared::Fingerprinted(new) => Ok(Ingested::Added(
+            store
+                .insert_song(
+                    &new.song,
+                    new.duration,
+                    fingerprinter.config().hash_scheme,
+                    &new.fingerprints,
+                )
+                .await?,
+        )),
+    }
+}
+
+/// Fetch and fingerprint `track`, unless `store` already has it, first naming
+/// it as the source really has it (see [`AudioSource::song`]). Decoding and
+/// fingerprinting run on a blocking thread.
+async fn prepare(
+    store: &impl FingerprintStore,
+    fingerprinter: &Fingerprinter,
+    source: &impl AudioSource,
+    track: &mut Track,
+) -> Result<Prepared> {
+    track.song = source.song(track).await?;
+    if let Some(song_id) = store.song_exists(&track.song).await? {
+        info!("Song already exists in the database with ID {}", song_id);
+        return Ok(Prepared::AlreadyCatalogued(song_id));
+    }
+
+    let encoded = source.open(track).await?;
+    let fingerprinter = fingerprinter.clone();
+    let (fingerprints, duration) = tokio::task::spawn_blocking(move || {
+        let audio = Fingerprinter::decode(encoded)?;
+        anyhow::Ok(fingerprinter.fingerprint_with_duration(audio))
+    })
+    .await??;
+    debug!(
+        "Fingerprinted {} with {} fingerprints",
+        track.song,
+        fingerprints.len()
+    );
+
+    Ok(Prepared::Fingerprinted(NewSong {
+        song: track.song.clone(),
+        duration,
+        fingerprints,
+    }))
+}
+
+/// Write `batch` to `store` in one go, falling back to one song at a time if
+/// that fails, and record what became of each track.
+async fn write_batch(
+    store: &impl FingerprintStore,
+    scheme: HashScheme,
+    batch: &mut Vec<(usize, Track, NewSong)>,
+    added: &mut HashSet<i64>,
+    outcomes: &mut Vec<(usize, Track, Result<Ingested, String>)>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let (tracks, songs): (Vec<_>, Vec<_>) = batch
+        .drain(..)
+        .map(|(index, track, song)| ((index, track), song))
+        .unzip();
+    let song_ids = match store.insert_songs(&songs, scheme).await {
+        Ok(song_ids) => song_ids.into_iter().map(Ok).collect(),
+        Err(e) => {
+            warn!(
+                "Writing a batch of {} songs failed, retrying one at a time: {}",
+                songs.len(),
+                e
+            );
+            let mut song_ids = Vec::with_capacity(songs.len());
+            for new in &songs {
+                song_ids.push(
+                    store
+                        .insert_song(&new.song, new.duration, scheme, &new.fingerprints)
+                        .await,
+                );
+            }
+            song_ids
+        }
+    };
+
+    for ((index, track), song_id) in tracks.into_iter().zip(song_ids) {
+        let outcome = match song_id {
+            Ok(song_id) if added.insert(song_id) => Ok(Ingested::Added(song_id)),
+            Ok(song_id) => Ok(Ingested::AlreadyCatalogued(song_id)),
+            Err(e) => Err(format!("storing the song: {e}")),
+        };
+        outcomes.push((index, track, outcome));
+    }
+}
+
+/// Logs how far through the tracks ingestion is, at most every few seconds.
+struct Progress {
+    total: usize,
+    started: Instant,
+    last_logged: Instant,
+}
+
+impl Progress {
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    fn new(total: usize) -> Self {
+        info!("Ingesting {} tracks", total);
+        let now = Instant::now();
+        Self {
+            total,
+            started: now,
+            last_logged: now,
+        }
+    }
+
+    fn update(&mut self, done: usize) {
+        if self.last_logged.elapsed() < Self::INTERVAL && done < self.total {
+            return;
+        }
+        self.last_logged = Instant::now();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = done as f64 / elapsed.max(f64::EPSILON);
+        let remaining = (self.total - done) as f64 / rate.max(f64::EPSILON);
+        info!(
+            "{}/{} tracks ({:.1}%), {:.1} tracks/s, about {:.0?} left",
+            done,
+            self.total,
+            100.0 * done as f64 / self.total.max(1) as f64,
+            rate,
+            Duration::from_secs_f64(remaining.min(1e9))
+        );
+    }
+}
@@ -0,0 +1,16 @@
+pub mod audio;
+pub mod eval;
+pub mod identify;
+pub mod ingest;
+pub mod model;
+pub mod report;
+pub(crate) mod rng;
+pub mod source;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod youtube;
+
+pub use audio::{
+    ConstellationPoint, Fingerprint, FingerprintConfig, Fingerprinter, MatchResult, Matcher,
+};
+pub use model::SongInfo;
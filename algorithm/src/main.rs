@@ -1,135 +1,652 @@
-use anyhow::Result;
-use audio::{constellation_points, generate_fingerprints, match_fingerprints};
+use anyhow::{Context, Result, bail, ensure};
+use audio_identifier::{
+    FingerprintConfig, Fingerprinter, MatchResult, Matcher, SongInfo,
+    eval::{ClipOptions, DEFAULT_THRESHOLDS, EvalSummary, cut_clips, evaluate, load_labels},
+    identify::identify_file,
+    ingest::{IngestOptions, IngestReport, ingest_with},
+    model::{
+        FingerprintStore, HashIndex, SqliteStore, catalogue_config, catalogue_stats, delete_song,
+        export_catalogue, import_catalogue, list_songs, load_config, read_catalogue_config,
+        setup_database, song_record,
+    },
+    report::{IdentifyRecord, ReportFormat, ReportWriter},
+    source::{Directory, Http, LocalFile, Track, YouTube, audio_files, song_from_path},
+};
+use clap::{Parser, Subcommand};
 use itertools::Itertools;
-use rodio::{Decoder, Source};
+use serde_json::json;
 use sqlx::SqlitePool;
 use std::{
-    fmt::Display,
     fs::File,
-    io::{BufReader, Write},
-    time::Duration,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
-use tracing::{info, instrument};
-
-mod audio;
-mod model;
-mod youtube;
+use tracing::warn;
+
+/// Fingerprint songs into a catalogue and identify recordings against it.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// The catalogue: a SQLite database file, created if it doesn't exist, or
+    /// a `sqlite:` URL.
+    #[arg(long, global = true, default_value = "data/fingerprints.db")]
+    db: String,
+
+    /// Print results as JSON, for scripts, instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
 
-use audio::BandpassFilterMonoSource;
-use model::{
-    find_similar_fingerprints, get_song_info, setup_database, song_exists, store_song_fingerprints,
-};
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Fingerprint songs into the catalogue. Each source is an audio file, a
+    /// directory searched for them recursively, an HTTP(S) URL or a YouTube
+    /// search as "Title - Artist".
+    Ingest {
+        #[arg(required = true)]
+        sources: Vec<String>,
+        /// Tracks fingerprinted at once; one per core by default.
+        #[arg(long)]
+        workers: Option<usize>,
+        /// Songs written to the catalogue per transaction.
+        #[arg(long, default_value_t = IngestOptions::default().batch_size)]
+        batch_size: usize,
+    },
+    /// Identify the song playing in each of some audio files.
+    Identify {
+        /// Audio files, or directories searched for them recursively.
+        #[arg(required = true)]
+        queries: Vec<PathBuf>,
+        /// Seconds into the file to start listening.
+        #[arg(long, default_value_t = 0.0)]
+        start: f64,
+        /// Seconds to listen for; the rest of the file by default.
+        #[arg(long)]
+        duration: Option<f64>,
+        /// Also write the best match for each query to this file, as JSON
+        /// Lines (.jsonl) or CSV (.csv).
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// The report's format, when its extension doesn't say: jsonl or csv.
+        #[arg(long)]
+        format: Option<ReportFormat>,
+        /// Look hashes up in this hash index, built with `index`, instead of
+        /// the catalogue.
+        #[arg(long)]
+        index: Option<PathBuf>,
+    },
+    /// List every catalogued song.
+    List,
+    /// Show one catalogued song.
+    Show { id: i64 },
+    /// Remove a song and its fingerprints from the catalogue.
+    Delete { id: i64 },
+    /// Show the size of the catalogue and the configuration it was built with.
+    Stats,
+    /// Write the whole catalogue to an archive file.
+    Export { file: PathBuf },
+    /// Merge a catalogue archive into this catalogue.
+    Import { file: PathBuf },
+    /// Build a memory-mapped index of the catalogue's hashes, for `identify
+    /// --index` and `eval --index`. It has to be rebuilt once the catalogue
+    /// changes.
+    Index { file: PathBuf },
+    /// Measure identification accuracy and latency on clips whose songs are
+    /// known.
+    Eval {
+        /// A CSV of labelled clips with the header `query,title,artist`, paths
+        /// relative to it; a clip with no title isn't catalogued.
+        #[arg(long)]
+        labels: Option<PathBuf>,
+        /// Cut clips at random from the audio files under this directory, the
+        /// ones the catalogue was built from.
+        #[arg(long)]
+        cut: Option<PathBuf>,
+        /// Cut clips at random from the audio files under this directory, none
+        /// of which are catalogued.
+        #[arg(long)]
+        negatives: Option<PathBuf>,
+        /// Clips cut from each file.
+        #[arg(long, default_value_t = ClipOptions::default().clips_per_file)]
+        clips_per_file: usize,
+        /// Length of each cut clip, in seconds.
+        #[arg(long, default_value_t = ClipOptions::default().seconds)]
+        clip_seconds: f64,
+        /// Seed for where clips are cut; the same seed cuts the same clips.
+        #[arg(long, default_value_t = ClipOptions::default().seed)]
+        seed: u64,
+        /// Largest expected false-positive counts to report precision and
+        /// recall at, comma separated.
+        #[arg(long, value_delimiter = ',', default_values_t = DEFAULT_THRESHOLDS.to_vec())]
+        thresholds: Vec<f64>,
+        /// Look hashes up in this hash index, built with `index`, instead of
+        /// the catalogue.
+        #[arg(long)]
+        index: Option<PathBuf>,
+    },
+}
 
-#[derive(Debug)]
-struct SongInfo {
-    title: String,
-    artist: String,
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+    // Logs go to stderr so they never mix with results
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+    let pool = setup_database(&database_url(&cli.db)?).await?;
+    let result = run(&cli, &pool).await;
+    pool.close().await;
+    result
 }
 
-impl SongInfo {
-    fn new(title: impl Into<String>, artist: impl Into<String>) -> Self {
-        Self {
-            title: title.into(),
-            artist: artist.into(),
+async fn run(cli: &Cli, pool: &SqlitePool) -> Result<()> {
+    match &cli.command {
+        Command::Ingest {
+            sources,
+            workers,
+            batch_size,
+        } => {
+            let fingerprinter =
+                Fingerprinter::new(catalogue_config(pool, FingerprintConfig::default()).await?);
+            let store = SqliteStore::new(pool.clone());
+            let mut options = IngestOptions {
+                batch_size: *batch_size,
+                ..IngestOptions::default()
+            };
+            if let Some(workers) = workers {
+                options.workers = *workers;
+            }
+
+            let mut report = IngestReport::default();
+            for source in sources {
+                let ingested = if Path::new(source).is_dir() {
+                    ingest_with(&store, &fingerprinter, &Directory::new(source), options).await?
+                } else if Path::new(source).is_file() {
+                    ingest_with(&store, &fingerprinter, &LocalFile::new(source), options).await?
+                } else if source.starts_with("http://") || source.starts_with("https://") {
+                    let url = url::Url::parse(source)?;
+                    let name = url
+                        .path_segments()
+                        .and_then(|mut segments| segments.next_back())
+                        .unwrap_or_default();
+                    let song = song_from_path(Path::new(name));
+                    ingest_with(&store, &fingerprinter, &Http::new(source, song), options).await?
+                } else {
+                    let song = match source.split_once(" - ") {
+                        Some((title, artist)) => SongInfo::new(title.trim(), artist.trim()),
+                        None => SongInfo::new(source.trim(), "Unknown"),
+                    };
+                    ingest_with(&store, &fingerprinter, &YouTube::new(vec![song]), options).await?
+                };
+                report.ingested.extend(ingested.ingested);
+                report.skipped.extend(ingested.skipped);
+                report.failed.extend(ingested.failed);
+                report.ignored.extend(ingested.ignored);
+                report.unreadable.extend(ingested.unreadable);
+            }
+            print_ingest_report(&report, cli.json)?;
+            if !report.failed.is_empty() && report.ingested.is_empty() && report.skipped.is_empty()
+            {
+                bail!("nothing could be ingested");
+            }
+        }
+        Command::Identify {
+            queries,
+            start,
+            duration,
+            report,
+            format,
+            index,
+        } => {
+            let fingerprinter = Fingerprinter::new(
+                read_catalogue_config(pool, FingerprintConfig::default()).await?,
+            );
+            let store = open_store(pool, &fingerprinter, index.as_deref()).await?;
+            let matcher = Matcher {
+                catalogue: Some(store.stats(fingerprinter.config().hash_scheme).await?),
+                ..Matcher::default()
+            };
+            let (start, duration) = (seconds(*start)?, duration.map(seconds).transpose()?);
+
+            let mut report = match report {
+                Some(path) => {
+                    let format = format
+                        .or_else(|| ReportFormat::from_path(path))
+                        .context("give the report a .jsonl or .csv extension, or a --format")?;
+                    let out = File::create(path)
+                        .with_context(|| format!("creating {}", path.display()))?;
+                    Some(ReportWriter::new(format, BufWriter::new(out)))
+                }
+                None => None,
+            };
+
+            // Several queries, or a directory of them, are reported query by query
+            let batch = queries.len() > 1 || queries.iter().any(|query| query.is_dir());
+            let mut files = Vec::new();
+            for query in queries {
+                if query.is_dir() {
+                    files.extend(audio_files(query)?);
+                } else {
+                    files.push(query.clone());
+                }
+            }
+
+            let mut identified = Vec::new();
+            let mut failed = 0;
+            for file in &files {
+                let query = file.display().to_string();
+                let started = Instant::now();
+                let outcome =
+                    identify_file(&store, &fingerprinter, &matcher, file, start, duration).await;
+                let (record, results) = match outcome {
+                    Ok(results) => {
+                        let songs = store
+                            .song_info(&results.iter().map(|r| r.song_id).collect_vec())
+                            .await?;
+                        // A song deleted since it was matched is left out
+                        let named = results
+                            .into_iter()
+                            .filter_map(|result| {
+                                let Some((title, artist, _)) = songs.get(&result.song_id) else {
+                                    warn!("Song {} is no longer catalogued", result.song_id);
+                                    return None;
+                                };
+                                Some((result, title.clone(), artist.clone()))
+                            })
+                            .collect_vec();
+                        let best = named.first().map(|(result, title, artist)| {
+                            (result, title.as_str(), artist.as_str())
+                        });
+                        (IdentifyRecord::new(&query, best, started.elapsed()), named)
+                    }
+                    Err(e) => {
+                        warn!("Failed to identify {}: {:#}", query, e);
+                        failed += 1;
+                        (
+                            IdentifyRecord::failed(&query, e, started.elapsed()),
+                            Vec::new(),
+                        )
+                    }
+                };
+                if let Some(report) = &mut report {
+                    report.write(&record)?;
+                }
+                identified.push((record, results));
+            }
+            if let Some(report) = &mut report {
+                report.flush()?;
+            }
+
+            let results_json = |results: &[(MatchResult, String, String)]| {
+                results
+                    .iter()
+                    .map(|(result, title, artist)| {
+                        json!({
+                            "song_id": result.song_id,
+                            "title": title,
+                            "artist": artist,
+                            "confidence": result.confidence,
+                            "time_offset": result.time_offset,
+                            "speed": result.speed,
+                            "matched_count": result.matched_count,
+                            "p_value": result.p_value,
+                            "false_positives": result.false_positives,
+                        })
+                    })
+                    .collect_vec()
+            };
+            if cli.json && batch {
+                let queries = identified
+                    .iter()
+                    .map(|(record, results)| {
+                        json!({
+                            "query": record.query,
+                            "runtime_ms": record.runtime_ms,
+                            "error": record.error,
+                            "results": results_json(results),
+                        })
+                    })
+                    .collect_vec();
+                println!("{}", serde_json::to_string_pretty(&queries)?);
+            } else if cli.json {
+                // A single query prints just its results, as it always has
+                if let Some((record, results)) = identified.first()
+                    && record.error.is_none()
+                {
+                    println!("{}", serde_json::to_string_pretty(&results_json(results))?);
+                }
+            } else {
+                for (record, results) in &identified {
+                    if batch {
+                        println!("{}:", record.query);
+                    }
+                    if let Some(error) = &record.error {
+                        println!("Failed: {error}");
+                    } else if results.is_empty() {
+                        println!("No match");
+                    }
+                    for (rank, (result, title, artist)) in results.iter().enumerate() {
+                        println!(
+                            "{}. {} - {} (id {}) at {:.2}s, confidence {:.2}, {} matches, p = {:.2e}",
+                            rank + 1,
+                            title,
+                            artist,
+                            result.song_id,
+                            result.time_offset,
+                            result.confidence,
+                            result.matched_count,
+                            result.p_value
+                        );
+                    }
+                }
+            }
+            if let [(record, _)] = identified.as_slice()
+                && let Some(error) = &record.error
+                && !batch
+            {
+                bail!("couldn't identify {}: {}", record.query, error);
+            }
+            if failed > 0 {
+                bail!("{failed} of {} queries couldn't be identified", files.len());
+            }
+        }
+        Command::List => {
+            let songs = list_songs(pool).await?;
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&songs)?);
+            } else {
+                for record in &songs {
+                    println!("{:>6}  {}  {:.1}s", record.id, record.song, record.duration);
+                }
+            }
+        }
+        Command::Show { id } => {
+            let record = song_record(pool, *id)
+                .await?
+                .with_context(|| format!("no song with id {id}"))?;
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&record)?);
+            } else {
+                println!("id:           {}", record.id);
+                println!("title:        {}", record.song.title);
+                println!("artist:       {}", record.song.artist);
+                println!(
+                    "album:        {}",
+                    record.song.album.as_deref().unwrap_or("-")
+                );
+                println!(
+                    "isrc:         {}",
+                    record.song.isrc.as_deref().unwrap_or("-")
+                );
+                println!("duration:     {:.1}s", record.duration);
+                println!("fingerprints: {}", record.fingerprints);
+            }
+        }
+        Command::Delete { id } => {
+            if !delete_song(pool, *id).await? {
+                bail!("no song with id {id}");
+            }
+            if cli.json {
+                println!("{}", json!({ "deleted": id }));
+            } else {
+                println!("Deleted song {id}");
+            }
+        }
+        Command::Stats => {
+            let config = load_config(pool).await?;
+            let scheme = config
+                .as_ref()
+                .map_or(FingerprintConfig::default().hash_scheme, |config| {
+                    config.hash_scheme
+                });
+            let stats = catalogue_stats(pool, scheme).await?;
+            if cli.json {
+                let stats = json!({
+                    "songs": stats.songs,
+                    "fingerprints": stats.fingerprints,
+                    "distinct_hashes": stats.distinct_hashes,
+                    "mean_duration": stats.mean_duration,
+                    "config": config,
+                });
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                println!("songs:           {}", stats.songs);
+                println!("fingerprints:    {}", stats.fingerprints);
+                println!("distinct hashes: {}", stats.distinct_hashes);
+                println!("mean duration:   {:.1}s", stats.mean_duration);
+                match config {
+                    Some(config) => {
+                        println!("config:          {}", serde_json::to_string(&config)?)
+                    }
+                    None => println!("config:          none recorded yet"),
+                }
+            }
+        }
+        Command::Export { file } => {
+            let out = File::create(file).with_context(|| format!("creating {}", file.display()))?;
+            let summary = export_catalogue(pool, BufWriter::new(out)).await?;
+            if cli.json {
+                println!(
+                    "{}",
+                    json!({ "songs": summary.songs, "fingerprints": summary.fingerprints })
+                );
+            } else {
+                println!(
+                    "Exported {} songs with {} fingerprints to {}",
+                    summary.songs,
+                    summary.fingerprints,
+                    file.display()
+                );
+            }
+        }
+        Command::Import { file } => {
+            let input = File::open(file).with_context(|| format!("opening {}", file.display()))?;
+            let summary = import_catalogue(pool, BufReader::new(input)).await?;
+            if cli.json {
+                println!(
+                    "{}",
+                    json!({
+                        "songs": summary.songs,
+                        "duplicates": summary.duplicates,
+                        "fingerprints": summary.fingerprints,
+                    })
+                );
+            } else {
+                println!(
+                    "Imported {} songs with {} fingerprints, skipped {} already catalogued",
+                    summary.songs, summary.fingerprints, summary.duplicates
+                );
+            }
+        }
+        Command::Index { file } => {
+            let config = read_catalogue_config(pool, FingerprintConfig::default()).await?;
+            let index = HashIndex::build(pool, config.hash_scheme, file).await?;
+            if cli.json {
+                println!(
+                    "{}",
+                    json!({ "hashes": index.len(), "generation": index.generation() })
+                );
+            } else {
+                println!("Indexed {} hashes to {}", index.len(), file.display());
+            }
+        }
+        Command::Eval {
+            labels,
+            cut,
+            negatives,
+            clips_per_file,
+            clip_seconds,
+            seed,
+            thresholds,
+            index,
+        } => {
+            let options = ClipOptions {
+                clips_per_file: *clips_per_file,
+                seconds: *clip_seconds,
+                seed: *seed,
+            };
+            let mut queries = Vec::new();
+            if let Some(labels) = labels {
+                queries.extend(load_labels(labels)?);
+            }
+            for (dir, catalogued) in [(cut, true), (negatives, false)] {
+                if let Some(dir) = dir {
+                    queries.extend(cut_clips(&audio_files(dir)?, catalogued, options)?);
+                }
+            }
+            if queries.is_empty() {
+                bail!("nothing to evaluate: give --labels, --cut or --negatives");
+            }
+
+            let fingerprinter = Fingerprinter::new(
+                read_catalogue_config(pool, FingerprintConfig::default()).await?,
+            );
+            let store = open_store(pool, &fingerprinter, index.as_deref()).await?;
+            // Every match is kept so each threshold can be applied afterwards
+            let matcher = Matcher {
+                catalogue: Some(store.stats(fingerprinter.config().hash_scheme).await?),
+                max_false_positives: f64::INFINITY,
+                ..Matcher::default()
+            };
+            let outcomes = evaluate(&store, &fingerprinter, &matcher, queries).await?;
+            let summary = EvalSummary::new(&outcomes, thresholds);
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            } else {
+                print_eval_summary(&summary);
+            }
         }
     }
+    Ok(())
 }
 
-impl Display for SongInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} - {}", self.title, self.artist)
+fn print_eval_summary(summary: &EvalSummary) {
+    let percent = |share: Option<f64>| match share {
+        Some(share) => format!("{:.1}%", 100.0 * share),
+        None => "-".to_string(),
+    };
+    println!(
+        "queries:        {} ({} catalogued, {} not, {} failed)",
+        summary.queries, summary.in_catalogue, summary.out_of_catalogue, summary.errors
+    );
+    println!("top-1 accuracy: {}", percent(summary.top1_accuracy));
+    println!();
+    println!(
+        "{:>19}  {:>9}  {:>7}  {:>19}",
+        "max false positives", "precision", "recall", "false positive rate"
+    );
+    for row in &summary.thresholds {
+        println!(
+            "{:>19}  {:>9}  {:>7}  {:>19}",
+            row.max_false_positives,
+            percent(row.precision),
+            percent(row.recall),
+            percent(row.false_positive_rate)
+        );
     }
+    println!();
+    println!(
+        "latency:        p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+        summary.latency.p50_ms,
+        summary.latency.p90_ms,
+        summary.latency.p99_ms,
+        summary.latency.max_ms
+    );
 }
 
-#[instrument(skip(pool))]
-async fn get_song(pool: &SqlitePool, song: &SongInfo) -> Result<i64> {
-    if let Some(song_id) = song_exists(pool, song).await? {
-        info!("Song already exists in the database with ID {}", song_id);
-        return Ok(song_id);
+fn print_ingest_report(report: &IngestReport, as_json: bool) -> Result<()> {
+    if as_json {
+        let song = |(track, song_id): &(Track, i64)| json!({ "song_id": song_id, "location": track.location, "song": track.song });
+        let report = json!({
+            "ingested": report.ingested.iter().map(song).collect_vec(),
+            "skipped": report.skipped.iter().map(song).collect_vec(),
+            "failed": report
+                .failed
+                .iter()
+                .map(|(track, error)| json!({ "location": track.location, "error": error }))
+                .collect_vec(),
+            "ignored": report.ignored,
+            "unreadable": report
+                .unreadable
+                .iter()
+                .map(|(location, error)| json!({ "location": location, "error": error }))
+                .collect_vec(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
     }
 
-    let buffer = youtube::get_audio_from_youtube(&song.to_string())
-        .await?
-        .into_inner()
-        .into_inner();
-    info!("Downloaded audio for {}", song);
-
-    let file = std::fs::File::create(format!("data/{}.aac", song))?;
-    info!("Writing audio to file: {:?}", file);
-    let mut writer = std::io::BufWriter::new(file);
-
-    writer.write_all(buffer.as_slice())?;
-    writer.flush()?;
-
-    let source = BufReader::new(File::open(format!("data/{}.aac", song))?);
-
-    let source = BandpassFilterMonoSource::downsample(source)?;
-    let duration = source.total_duration().unwrap().as_secs_f32();
-
-    let constellation_points = constellation_points(source);
-
-    let fingerprints = generate_fingerprints(constellation_points);
-
-    Ok(store_song_fingerprints(pool, song, duration, &fingerprints).await?)
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    dotenvy::dotenv().ok();
-    tracing_subscriber::fmt::init();
-
-    let pool = setup_database().await?;
-
-    let songs = vec![
-        SongInfo::new("Back to friends", "sombr"),
-        SongInfo::new("Waxwing", "Sorry"),
-        SongInfo::new("Lemon to a Knife Fight", "The Wombats"),
-        SongInfo::new("The Less I Know The Better", "Tame Impala"),
-        SongInfo::new("Dog Dribble", "Getdown Services"),
-        SongInfo::new("The fish needs a bike", "Snapped Ankles"),
-    ];
-
-    for song in &songs {
-        get_song(&pool, song).await?;
+    for (track, song_id) in &report.ingested {
+        println!("Added    {:>6}  {}", song_id, track.song);
     }
-
-    info!("Loading audio...");
-
-    let song = SongInfo::new("Waxwing", "Sorry");
-
-    let buffer = youtube::get_audio_from_youtube(&song.to_string()).await?;
-    let source = Box::new(
-        Decoder::new(buffer)?
-            .skip_duration(Duration::from_secs(25))
-            .take_duration(Duration::from_secs(25)),
-    );
-
-    let source = BandpassFilterMonoSource::new(source, 11025);
-
-    let constellation_points = constellation_points(source);
-
-    let fingerprints = generate_fingerprints(constellation_points);
-
-    let potential_matches = find_similar_fingerprints(&pool, &fingerprints).await?;
-
-    info!("Found {} potential matches", potential_matches.len());
-
-    info!("Matching fingerprints... {:?}", potential_matches.keys());
-
-    let results = match_fingerprints(&fingerprints, potential_matches);
-
-    let song_infos = get_song_info(&pool, &results.iter().map(|r| r.song_id).collect_vec()).await?;
-    for result in &results {
-        let song_info = song_infos.get(&result.song_id).unwrap();
-        info!(
-            "Matched song {} by {} with confidence {:.2} at time offset {:.2} with {} matches",
-            song_info.0, song_info.1, result.confidence, result.time_offset, result.matched_count
+    for (track, song_id) in &report.skipped {
+        println!(
+            "Skipped  {:>6}  {} (already catalogued)",
+            song_id, track.song
         );
     }
+    for (track, error) in &report.failed {
+        println!("Failed           {}: {}", track.location, error);
+    }
+    for (location, error) in &report.unreadable {
+        println!("Unreadable       {}: {}", location, error);
+    }
+    println!(
+        "{} added, {} already catalogued, {} failed, {} files ignored",
+        report.ingested.len(),
+        report.skipped.len(),
+        report.failed.len(),
+        report.ignored.len()
+    );
+    Ok(())
+}
 
-    dbg!(results);
+/// The catalogue, looking hashes up in the hash index at `index` if one is
+/// given.
+async fn open_store(
+    pool: &SqlitePool,
+    fingerprinter: &Fingerprinter,
+    index: Option<&Path>,
+) -> Result<SqliteStore> {
+    let store = SqliteStore::new(pool.clone());
+    let Some(path) = index else {
+        return Ok(store);
+    };
+    let index = HashIndex::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let scheme = fingerprinter.config().hash_scheme;
+    ensure!(
+        index.scheme() == scheme,
+        "{} indexes {:?} hashes, but the catalogue uses {:?}; rebuild it with `index`",
+        path.display(),
+        index.scheme(),
+        scheme
+    );
+    store
+        .with_index(index)
+        .await
+        .with_context(|| format!("{}: rebuild it with `index`", path.display()))
+}
 
-    pool.close().await;
+/// The catalogue URL for `--db`, creating the directory a database file is
+/// to go in.
+fn database_url(db: &str) -> Result<String> {
+    if db.starts_with("sqlite:") {
+        return Ok(db.to_string());
+    }
+    if let Some(parent) = Path::new(db).parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("creating {}", parent.display()))?;
+    }
+    Ok(format!("sqlite:{db}"))
+}
 
-    Ok(())
+fn seconds(value: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(value).with_context(|| format!("{value} isn't a valid time"))
 }
@@ -0,0 +1,302 @@
+//! A portable copy of a catalogue, for moving one between machines or merging
+//! catalogues built separately.
+//!
+//! All integers are little-endian and strings are a `u32` byte length followed
+//! by UTF-8:
+//!
+//! ```text
+//! magic "AIDA" | version u32 | config (JSON string) | songs u64
+//! per song:        title | artist | album (u8 flag, string) | isrc (u8 flag, string)
+//!                  | duration f64 | fingerprints u64
+//! per fingerprint: hash scheme u8 | hash i64 | time offset f64 | confidence i64
+//!                  | anchor frequency i64 | target frequency i64 | delta time f64
+//! crc32 of everything before it, u32
+//! ```
+//!
+//! Version 1 archives, written before songs had an ISRC, are still read.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{Context, Result, bail, ensure};
+use sqlx::{Row, SqlitePool};
+use tracing::{info, instrument};
+
+use super::{SongInfo, insert_fingerprints, insert_song, load_config, store_config};
+use crate::audio::{Fingerprint, FingerprintConfig, HashScheme};
+
+const MAGIC: &[u8; 4] = b"AIDA";
+const VERSION: u32 = 2;
+
+/// What an export wrote or an import read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ArchiveSummary {
+    /// Songs added to the catalogue, or written to the archive.
+    pub songs: usize,
+    /// Songs skipped on import because the catalogue already had them.
+    pub duplicates: usize,
+    /// Fingerprints added to the catalogue, or written to the archive.
+    pub fingerprints: usize,
+}
+
+/// Write the whole catalogue, with the configuration it was built with, to `out`.
+#[instrument(skip_all)]
+pub async fn export_catalogue(pool: &SqlitePool, out: impl Write) -> Result<ArchiveSummary> {
+    let config = load_config(pool)
+        .await?
+        .context("the catalogue has no recorded configuration")?;
+    let songs = sqlx::query(
+        "SELECT id, title, artist, album, isrc, CAST(duration AS REAL) AS duration FROM songs ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut out = Checksummed::new(out);
+    out.write_all(MAGIC)?;
+    write_u32(&mut out, VERSION)?;
+    write_str(&mut out, &serde_json::to_string(&config)?)?;
+    write_u64(&mut out, songs.len() as u64)?;
+
+    let mut summary = ArchiveSummary::default();
+    for song in &songs {
+        let fingerprints = sqlx::query(
+            "SELECT hash_scheme, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time FROM fingerprints WHERE song_id = ? ORDER BY id",
+        )
+        .bind(song.get::<i64, _>("id"))
+        .fetch_all(pool)
+        .await?;
+
+        write_str(&mut out, song.get("title"))?;
+        write_str(&mut out, song.get("artist"))?;
+        write_optional_str(&mut out, song.get("album"))?;
+        write_optional_str(&mut out, song.get("isrc"))?;
+        write_f64(&mut out, song.get("duration"))?;
+        write_u64(&mut out, fingerprints.len() as u64)?;
+        for row in &fingerprints {
+            out.write_all(&[row.get::<i64, _>("hash_scheme") as u8])?;
+            write_i64(&mut out, row.get("hash"))?;
+            write_f64(&mut out, row.get("time_offset"))?;
+            write_i64(&mut out, row.get("confidence"))?;
+            write_i64(&mut out, row.get("anchor_frequency"))?;
+            write_i64(&mut out, row.get("target_frequency"))?;
+            write_f64(&mut out, row.get("delta_time"))?;
+        }
+        summary.songs += 1;
+        summary.fingerprints += fingerprints.len();
+    }
+
+    let checksum = out.hasher.clone().finalize();
+    out.inner.write_all(&checksum.to_le_bytes())?;
+    out.inner.flush()?;
+    info!(
+        "Exported {} songs with {} fingerprints",
+        summary.songs, summary.fingerprints
+    );
+    Ok(summary)
+}
+
+/// Merge the catalogue archived in `input` into this one, skipping songs it
+/// already has. A catalogue with no configuration yet adopts the archive's;
+/// otherwise the two must have been built with the same configuration, as
+/// fingerprints made with different settings never match.
+///
+/// Everything is added in one transaction, which is only committed once the
+/// checksum has been verified.
+#[instrument(skip_all)]
+pub async fn import_catalogue(pool: &SqlitePool, input: impl Read) -> Result<ArchiveSummary> {
+    let mut input = Checksummed::new(input);
+    let mut magic = [0; 4];
+    input
+        .read_exact(&mut magic)
+        .context("the archive is empty or truncated")?;
+    ensure!(&magic == MAGIC, "not a catalogue archive");
+    let version = read_u32(&mut input)?;
+    ensure!(
+        (1..=VERSION).contains(&version),
+        "unsupported catalogue archive version {version}"
+    );
+    let config: FingerprintConfig = serde_json::from_str(&read_string(&mut input)?)
+        .context("the archive's configuration is invalid")?;
+
+    let mut tx = pool.begin().await?;
+    match load_config(&mut *tx).await? {
+        Some(existing) if existing != config => {
+            bail!("the archive was built with a different fingerprint configuration")
+        }
+        Some(_) => {}
+        None => store_config(&mut *tx, &config).await?,
+    }
+
+    let mut summary = ArchiveSummary::default();
+    let songs = read_u64(&mut input)?;
+    for _ in 0..songs {
+        let mut song = SongInfo::new(read_string(&mut input)?, read_string(&mut input)?);
+        song.album = read_optional_string(&mut input)?;
+        if version >= 2 {
+            song.isrc = read_optional_string(&mut input)?;
+        }
+        let duration = read_f64(&mut input)?;
+        let count = read_u64(&mut input)?;
+        // Runs of fingerprints sharing a hash scheme
+        let mut fingerprints: Vec<(HashScheme, Vec<Fingerprint>)> = Vec::new();
+        for _ in 0..count {
+            let version = read_u8(&mut input)?;
+            let scheme = HashScheme::from_version(version.into())
+                .with_context(|| format!("unknown hash scheme {version}"))?;
+            let fingerprint: Fingerprint = (
+                read_i64(&mut input)?,
+                read_f64(&mut input)?,
+                read_i64(&mut input)?,
+                read_i64(&mut input)?,
+                read_i64(&mut input)?,
+                read_f64(&mut input)?,
+            )
+                .into();
+            match fingerprints.last_mut() {
+                Some((last, run)) if *last == scheme => run.push(fingerprint),
+                _ => fingerprints.push((scheme, vec![fingerprint])),
+            }
+        }
+
+        let Some(song_id) = insert_song(&mut tx, &song, duration).await? else {
+            summary.duplicates += 1;
+            continue;
+        };
+        for (scheme, run) in &fingerprints {
+            insert_fingerprints(&mut tx, song_id, *scheme, run).await?;
+        }
+        summary.songs += 1;
+        summary.fingerprints += count as usize;
+    }
+
+    let expected = input.hasher.clone().finalize();
+    let mut checksum = [0; 4];
+    input
+        .inner
+        .read_exact(&mut checksum)
+        .context("the archive is truncated")?;
+    ensure!(
+        u32::from_le_bytes(checksum) == expected,
+        "the archive is corrupt: checksum mismatch"
+    );
+    tx.commit().await?;
+    info!(
+        "Imported {} songs with {} fingerprints, skipped {} already catalogued",
+        summary.songs, summary.fingerprints, summary.duplicates
+    );
+    Ok(summary)
+}
+
+/// Keeps a running CRC-32 of everything read or written through it.
+struct Checksummed<T> {
+    inner: T,
+    hasher: crc32fast::Hasher,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn write_u32(out: &mut impl Write, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(out: &mut impl Write, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_i64(out: &mut impl Write, value: i64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_f64(out: &mut impl Write, value: f64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_str(out: &mut impl Write, value: &str) -> io::Result<()> {
+    let len = u32::try_from(value.len())
+        .map_err(|_| io::Error::other("a string is too long for the archive format"))?;
+    write_u32(out, len)?;
+    out.write_all(value.as_bytes())
+}
+
+fn write_optional_str(out: &mut impl Write, value: Option<&str>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            out.write_all(&[1])?;
+            write_str(out, value)
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input
+        .read_exact(&mut bytes)
+        .context("the archive is truncated")?;
+    Ok(bytes)
+}
+
+fn read_u8(input: &mut impl Read) -> Result<u8> {
+    Ok(read_array::<1>(input)?[0])
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_array(input)?))
+}
+
+fn read_u64(input: &mut impl Read) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_array(input)?))
+}
+
+fn read_i64(input: &mut impl Read) -> Result<i64> {
+    Ok(i64::from_le_bytes(read_array(input)?))
+}
+
+fn read_f64(input: &mut impl Read) -> Result<f64> {
+    Ok(f64::from_le_bytes(read_array(input)?))
+}
+
+fn read_string(input: &mut impl Read) -> Result<String> {
+    let len = read_u32(input)? as usize;
+    let mut bytes = Vec::new();
+    input
+        .by_ref()
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .context("the archive is truncated")?;
+    ensure!(bytes.len() == len, "the archive is truncated");
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn read_optional_string(input: &mut impl Read) -> Result<Option<String>> {
+    Ok(match read_u8(input)? {
+        0 => None,
+        _ => Some(read_string(input)?),
+    })
+}
@@ -0,0 +1,350 @@
+//! A read-only inverted index from hash to the songs and positions it occurs
+//! at, laid out so it can be memory-mapped and searched in place.
+//!
+//! All integers are little-endian:
+//!
+//! ```text
+//! header   magic "AIDX" | version u32 | hash scheme i64 | generation i64 | hashes u64 | postings u64
+//! hashes   (hash i64, first posting u32, postings u32) per hash, sorted by hash
+//! postings (song id u32, time offset f32) per posting, sorted by song then time
+//! ```
+//!
+//! The generation is the catalogue's (see [`super::catalogue_generation`]) when
+//! the index was built, so an index the catalogue has moved on from is known.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use itertools::Itertools;
+use memmap2::Mmap;
+use sqlx::{Row, SqlitePool};
+use tracing::{info, instrument};
+
+use super::catalogue_generation;
+use crate::audio::{Fingerprint, HashScheme};
+
+const MAGIC: &[u8; 4] = b"AIDX";
+const VERSION: u32 = 2;
+const HEADER_LEN: usize = 40;
+const HASH_ENTRY_LEN: usize = 16;
+const POSTING_LEN: usize = 8;
+
+/// One occurrence of a hash in the catalogue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posting {
+    pub song_id: u32,
+    /// Seconds into the song.
+    pub time_offset: f32,
+}
+
+/// A memory-mapped hash index, built with [`HashIndex::build`] or
+/// [`HashIndex::write`]. Lookups binary search the mapped file, so opening it
+/// costs nothing up front and only the pages touched are read.
+#[derive(Debug)]
+pub struct HashIndex {
+    map: Mmap,
+    scheme: HashScheme,
+    generation: i64,
+    hashes: usize,
+    postings: usize,
+}
+
+impl HashIndex {
+    /// Write an index of the catalogue's fingerprints hashed with `scheme`
+    /// to `path`, then open it.
+    #[instrument(skip(pool))]
+    pub async fn build(pool: &SqlitePool, scheme: HashScheme, path: &Path) -> Result<Self> {
+        // One transaction, so the generation is that of the fingerprints read
+        let mut tx = pool.begin().await?;
+        let generation = catalogue_generation(&mut *tx).await?;
+        let mut rows = sqlx::query(
+            "SELECT hash, song_id, time_offset FROM fingerprints WHERE hash_scheme = ?",
+        )
+        .bind(scheme.version())
+        .fetch(&mut *tx);
+        let mut postings = Vec::new();
+        while let Some(row) = rows.try_next().await? {
+            let song_id: i64 = row.get("song_id");
+            let song_id = u32::try_from(song_id)
+                .with_context(|| format!("song id {song_id} does not fit in the index"))?;
+            postings.push((
+                row.get::<i64, _>("hash"),
+                Posting {
+                    song_id,
+                    time_offset: row.get::<f64, _>("time_offset") as f32,
+                },
+            ));
+        }
+        drop(rows);
+        tx.commit().await?;
+        info!("Indexing {} fingerprints", postings.len());
+        Self::write(path, scheme, generation, postings)?;
+        Ok(Self::open(path)?)
+    }
+
+    /// Write an index of `postings`, given as `(hash, posting)` in any order,
+    /// taken from the catalogue at `generation`.
+    pub fn write(
+        path: &Path,
+        scheme: HashScheme,
+        generation: i64,
+        mut postings: Vec<(i64, Posting)>,
+    ) -> io::Result<()> {
+        postings.sort_by(|(a, x), (b, y)| {
+            a.cmp(b)
+                .then(x.song_id.cmp(&y.song_id))
+                .then(x.time_offset.total_cmp(&y.time_offset))
+        });
+        let runs = postings
+            .iter()
+            .chunk_by(|(hash, _)| *hash)
+            .into_iter()
+            .map(|(hash, run)| (hash, run.count()))
+            .collect_vec();
+
+        // Written alongside and renamed over the old index, which may be mapped
+        let partial = path.with_extension("partial");
+        let mut out = BufWriter::new(File::create(&partial)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&scheme.version().to_le_bytes())?;
+        out.write_all(&generation.to_le_bytes())?;
+        out.write_all(&(runs.len() as u64).to_le_bytes())?;
+        out.write_all(&(postings.len() as u64).to_le_bytes())?;
+
+        let mut first = 0_usize;
+        for (hash, count) in &runs {
+            let (start, len) = (u32::try_from(first), u32::try_from(*count));
+            let (Ok(start), Ok(len)) = (start, len) else {
+                return Err(io::Error::other("too many postings for the index format"));
+            };
+            out.write_all(&hash.to_le_bytes())?;
+            out.write_all(&start.to_le_bytes())?;
+            out.write_all(&len.to_le_bytes())?;
+            first += count;
+        }
+        for (_, posting) in &postings {
+            out.write_all(&posting.song_id.to_le_bytes())?;
+            out.write_all(&posting.time_offset.to_le_bytes())?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&partial, path)
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the index is only ever replaced whole, never modified in place
+        let map = unsafe { Mmap::map(&file)? };
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        if map.len() < HEADER_LEN || &map[..4] != MAGIC {
+            return Err(invalid("not a hash index"));
+        }
+        if read_u32(&map, 4) != VERSION {
+            return Err(invalid("unsupported hash index version"));
+        }
+        let scheme = HashScheme::from_version(read_i64(&map, 8))
+            .ok_or_else(|| invalid("unknown hash scheme"))?;
+        let generation = read_i64(&map, 16);
+        let (hashes, postings) = (read_u64(&map, 24), read_u64(&map, 32));
+        // Counts from a corrupt header can be anything, so size the file checked
+        let len = usize::try_from(hashes)
+            .ok()
+            .zip(usize::try_from(postings).ok())
+            .and_then(|(hashes, postings)| {
+                HEADER_LEN
+                    .checked_add(hashes.checked_mul(HASH_ENTRY_LEN)?)?
+                    .checked_add(postings.checked_mul(POSTING_LEN)?)
+            });
+        if len != Some(map.len()) {
+            return Err(invalid("hash index is truncated"));
+        }
+        Ok(Self {
+            map,
+            scheme,
+            generation,
+            hashes: hashes as usize,
+            postings: postings as usize,
+        })
+    }
+
+    /// The scheme the indexed fingerprints were hashed with.
+    pub fn scheme(&self) -> HashScheme {
+        self.scheme
+    }
+
+    /// The catalogue generation the index was built from.
+    pub fn generation(&self) -> i64 {
+        self.generation
+    }
+
+    /// Number of distinct hashes indexed.
+    pub fn len(&self) -> usize {
+        self.hashes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes == 0
+    }
+
+    /// Every occurrence of `hash`, by song then time. Entries are checked as
+    /// they are looked up rather than all at once when the index is opened, so
+    /// a corrupt one is an error here.
+    pub fn postings(&self, hash: i64) -> io::Result<impl ExactSizeIterator<Item = Posting> + '_> {
+        let (start, len) = self.find(hash).unwrap_or((0, 0));
+        if start.checked_add(len).is_none_or(|end| end > self.postings) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("hash index entry for {hash} is out of range"),
+            ));
+        }
+        let base = HEADER_LEN + self.hashes * HASH_ENTRY_LEN;
+        Ok((start..start + len).map(move |i| {
+            let at = base + i * POSTING_LEN;
+            Posting {
+                song_id: read_u32(&self.map, at),
+                time_offset: f32::from_bits(read_u32(&self.map, at + 4)),
+            }
+        }))
+    }
+
+    /// The same as [`super::find_similar_fingerprints`] for a catalogue hashed
+    /// with [`HashIndex::scheme`], with only the hash and time offset of each
+    /// catalogue fingerprint filled in, which is all matching needs.
+    pub fn find_similar(
+        &self,
+        fingerprints: &[Fingerprint],
+    ) -> io::Result<HashMap<i64, Vec<Fingerprint>>> {
+        let mut result_map: HashMap<i64, Vec<Fingerprint>> = HashMap::new();
+        for hash in fingerprints.iter().map(|fp| fp.hash).unique() {
+            for posting in self.postings(hash)? {
+                result_map
+                    .entry(posting.song_id.into())
+                    .or_default()
+                    .push((hash, f64::from(posting.time_offset), 0, 0, 0, 0.0).into());
+            }
+        }
+        Ok(result_map)
+    }
+
+    /// `(first posting, postings)` of `hash`.
+    fn find(&self, hash: i64) -> Option<(usize, usize)> {
+        let (mut low, mut high) = (0, self.hashes);
+        while low < high {
+            let mid = (low + high) / 2;
+            let at = HEADER_LEN + mid * HASH_ENTRY_LEN;
+            match read_i64(&self.map, at).cmp(&hash) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => {
+                    return Some((
+                        read_u32(&self.map, at + 8) as usize,
+                        read_u32(&self.map, at + 12) as usize,
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn read_i64(bytes: &[u8], at: usize) -> i64 {
+    i64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posting(song_id: u32, time_offset: f32) -> Posting {
+        Posting {
+            song_id,
+            time_offset,
+        }
+    }
+
+    #[test]
+    fn postings_come_back_sorted_by_song_and_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index");
+        HashIndex::write(
+            &path,
+            HashScheme::Packed,
+            7,
+            vec![
+                (42, posting(2, 1.5)),
+                (-7, posting(1, 0.25)),
+                (42, posting(1, 9.0)),
+                (42, posting(1, 3.0)),
+                (i64::MAX, posting(3, 2.0)),
+            ],
+        )
+        .unwrap();
+
+        let index = HashIndex::open(&path).unwrap();
+
+        assert_eq!(index.scheme(), HashScheme::Packed);
+        assert_eq!(index.generation(), 7);
+        assert_eq!(index.len(), 3);
+        assert_eq!(
+            index.postings(42).unwrap().collect_vec(),
+            vec![posting(1, 3.0), posting(1, 9.0), posting(2, 1.5)]
+        );
+        assert_eq!(
+            index.postings(-7).unwrap().collect_vec(),
+            vec![posting(1, 0.25)]
+        );
+        assert_eq!(index.postings(i64::MAX).unwrap().len(), 1);
+        assert_eq!(index.postings(0).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index");
+        HashIndex::write(&path, HashScheme::Fnv1a, 1, vec![(1, posting(1, 0.0))]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(HashIndex::open(&path).is_err());
+        std::fs::write(&path, b"not an index at all, just some text").unwrap();
+        assert!(HashIndex::open(&path).is_err());
+
+        // Counts so large that sizing the file overflows
+        let mut huge = bytes.clone();
+        huge[24..32].copy_from_slice(&(u64::MAX / 8).to_le_bytes());
+        std::fs::write(&path, &huge).unwrap();
+        assert!(HashIndex::open(&path).is_err());
+    }
+
+    #[test]
+    fn entries_pointing_past_the_postings_are_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index");
+        HashIndex::write(&path, HashScheme::Packed, 1, vec![(1, posting(1, 0.0))]).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        // The one entry's posting count
+        bytes[HEADER_LEN + 12..HEADER_LEN + 16].copy_from_slice(&2u32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        let index = HashIndex::open(&path).unwrap();
+
+        assert!(index.postings(1).is_err());
+        assert_eq!(index.postings(2).unwrap().len(), 0);
+    }
+}
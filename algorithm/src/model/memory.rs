@@ -0,0 +1,157 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::RwLock,
+};
+
+use super::{FingerprintStore, SongInfo};
+use crate::audio::{CatalogueStats, Fingerprint, HashScheme};
+
+/// A catalogue held in memory, for tests and short-lived tools. Nothing is
+/// persisted.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    catalogue: RwLock<Catalogue>,
+}
+
+#[derive(Debug, Default)]
+struct Catalogue {
+    last_id: i64,
+    songs: BTreeMap<i64, Song>,
+    /// Fingerprints by scheme version and hash, with the song they belong to.
+    index: HashMap<(i64, i64), Vec<(i64, Fingerprint)>>,
+}
+
+#[derive(Debug)]
+struct Song {
+    info: SongInfo,
+    duration: f64,
+    fingerprints: usize,
+}
+
+impl Catalogue {
+    fn find(&self, song: &SongInfo) -> Option<i64> {
+        self.songs
+            .iter()
+            .find(|(_, s)| s.info.title == song.title && s.info.artist == song.artist)
+            .map(|(&id, _)| id)
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FingerprintStore for MemoryStore {
+    async fn song_exists(&self, song: &SongInfo) -> Result<Option<i64>, sqlx::Error> {
+        Ok(self.catalogue.read().unwrap().find(song))
+    }
+
+    async fn insert_song(
+        &self,
+        song: &SongInfo,
+        duration: f64,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<i64, sqlx::Error> {
+        let mut catalogue = self.catalogue.write().unwrap();
+        if let Some(song_id) = catalogue.find(song) {
+            return Ok(song_id);
+        }
+        catalogue.last_id += 1;
+        let song_id = catalogue.last_id;
+        catalogue.songs.insert(
+            song_id,
+            Song {
+                info: song.clone(),
+                duration,
+                fingerprints: fingerprints.len(),
+            },
+        );
+        for fingerprint in fingerprints {
+            catalogue
+                .index
+                .entry((scheme.version(), fingerprint.hash))
+                .or_default()
+                .push((song_id, fingerprint.clone()));
+        }
+        Ok(song_id)
+    }
+
+    async fn find_similar(
+        &self,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error> {
+        let catalogue = self.catalogue.read().unwrap();
+        let hashes: HashSet<i64> = fingerprints.iter().map(|fp| fp.hash).collect();
+        let mut result_map: HashMap<i64, Vec<Fingerprint>> = HashMap::new();
+        for hash in hashes {
+            for (song_id, fingerprint) in catalogue
+                .index
+                .get(&(scheme.version(), hash))
+                .into_iter()
+                .flatten()
+            {
+                result_map
+                    .entry(*song_id)
+                    .or_default()
+                    .push(fingerprint.clone());
+            }
+        }
+        Ok(result_map)
+    }
+
+    async fn song_info(
+        &self,
+        song_ids: &[i64],
+    ) -> Result<HashMap<i64, (String, String, f64)>, sqlx::Error> {
+        let catalogue = self.catalogue.read().unwrap();
+        Ok(song_ids
+            .iter()
+            .filter_map(|id| {
+                let song = catalogue.songs.get(id)?;
+                Some((
+                    *id,
+                    (
+                        song.info.title.clone(),
+                        song.info.artist.clone(),
+                        song.duration,
+                    ),
+                ))
+            })
+            .collect())
+    }
+
+    async fn delete_song(&self, song_id: i64) -> Result<bool, sqlx::Error> {
+        let mut catalogue = self.catalogue.write().unwrap();
+        if catalogue.songs.remove(&song_id).is_none() {
+            return Ok(false);
+        }
+        catalogue.index.retain(|_, entries| {
+            entries.retain(|(id, _)| *id != song_id);
+            !entries.is_empty()
+        });
+        Ok(true)
+    }
+
+    async fn stats(&self, scheme: HashScheme) -> Result<CatalogueStats, sqlx::Error> {
+        let catalogue = self.catalogue.read().unwrap();
+        let songs = catalogue.songs.len();
+        Ok(CatalogueStats {
+            songs,
+            fingerprints: catalogue.songs.values().map(|s| s.fingerprints).sum(),
+            distinct_hashes: catalogue
+                .index
+                .keys()
+                .filter(|&&(version, _)| version == scheme.version())
+                .count(),
+            mean_duration: if songs == 0 {
+                0.0
+            } else {
+                (catalogue.songs.values().map(|s| s.duration).sum::<f64>() / songs as f64) as f32
+            },
+        })
+    }
+}
@@ -1,70 +1,319 @@
+mod archive;
+mod index;
+mod memory;
+mod postgres;
+mod song_info;
+mod store;
+
 use std::collections::HashMap;
 
 use itertools::Itertools;
-use sqlx::{Row, SqlitePool, sqlite::SqlitePoolOptions};
+use rust_decimal::Decimal;
+use sqlx::{
+    Row, SqliteConnection, SqliteExecutor, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use std::str::FromStr;
 use tracing::{info, instrument, warn};
 
-use crate::{SongInfo, audio::Fingerprint};
+use crate::audio::{CatalogueStats, Fingerprint, FingerprintConfig, HashScheme};
+pub use archive::{ArchiveSummary, export_catalogue, import_catalogue};
+pub use index::{HashIndex, Posting};
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+pub use song_info::{SongInfo, SongRecord};
+pub use store::{FingerprintStore, NewSong, SqliteStore};
 
-pub async fn setup_database() -> Result<SqlitePool, sqlx::Error> {
+/// Open the catalogue at `url`, e.g. `sqlite:data/fingerprints.db`, and bring
+/// its schema up to date.
+pub async fn setup_database(url: &str) -> Result<SqlitePool, sqlx::Error> {
     // Connect to SQLite database (creates it if it doesn't exist)
+    let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
-        .connect("sqlite:data/fingerprints.db")
+        .connect_with(options)
         .await?;
     sqlx::migrate!().run(&pool).await?;
     // Create tables
     Ok(pool)
 }
 
-#[instrument(skip(pool))]
-pub async fn song_exists(pool: &SqlitePool, song: &SongInfo) -> Result<Option<i64>, sqlx::Error> {
-    // let result = sqlx::query!(
-    //     "SELECT id FROM songs WHERE title = ? AND artist = ?",
-    //     song.title,
-    //     song.artist
-    // )
-    // .fetch_optional(pool)
-    // .await?;
-    // Ok(result.map(|row| row.id))
-    Ok(Some(0))
+/// The configuration the catalogue was built with. A new catalogue adopts
+/// `default` and records it, so later runs keep using the same settings. One
+/// that already has songs but no configuration predates it being recorded, and
+/// gets [`FingerprintConfig::legacy`] instead.
+#[instrument(skip(pool, default))]
+pub async fn catalogue_config(
+    pool: &SqlitePool,
+    default: FingerprintConfig,
+) -> Result<FingerprintConfig, sqlx::Error> {
+    if let Some(config) = load_config(pool).await? {
+        return Ok(config);
+    }
+    let config = unrecorded_config(pool, default).await?;
+    info!("Recording the fingerprint configuration for the catalogue");
+    store_config(pool, &config).await?;
+    Ok(config)
+}
+
+/// The configuration [`catalogue_config`] would give, without recording it,
+/// for commands that only read the catalogue.
+pub async fn read_catalogue_config(
+    pool: &SqlitePool,
+    default: FingerprintConfig,
+) -> Result<FingerprintConfig, sqlx::Error> {
+    match load_config(pool).await? {
+        Some(config) => Ok(config),
+        None => unrecorded_config(pool, default).await,
+    }
+}
+
+/// The configuration of a catalogue that hasn't recorded one: `default` when
+/// it is empty, [`FingerprintConfig::legacy`] when it has songs.
+async fn unrecorded_config(
+    pool: &SqlitePool,
+    default: FingerprintConfig,
+) -> Result<FingerprintConfig, sqlx::Error> {
+    let catalogued: bool = sqlx::query("SELECT EXISTS (SELECT 1 FROM songs) AS catalogued")
+        .fetch_one(pool)
+        .await?
+        .get("catalogued");
+    if catalogued {
+        warn!("The catalogue predates recording its configuration; assuming the legacy one");
+        Ok(FingerprintConfig::legacy())
+    } else {
+        Ok(default)
+    }
+}
+
+pub async fn load_config(
+    executor: impl SqliteExecutor<'_>,
+) -> Result<Option<FingerprintConfig>, sqlx::Error> {
+    let row = sqlx::query("SELECT config FROM catalogue_config WHERE id = 1")
+        .fetch_optional(executor)
+        .await?;
+    row.map(|row| {
+        serde_json::from_str(row.get("config")).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+    })
+    .transpose()
+}
+
+pub async fn store_config(
+    executor: impl SqliteExecutor<'_>,
+    config: &FingerprintConfig,
+) -> Result<(), sqlx::Error> {
+    let config = serde_json::to_string(config).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    sqlx::query(
+        "INSERT INTO catalogue_config (id, config) VALUES (1, ?) ON CONFLICT (id) DO UPDATE SET config = excluded.config",
+    )
+    .bind(config)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// A number that changes whenever the catalogue's songs or fingerprints do,
+/// for telling whether something derived from it is out of date.
+pub async fn catalogue_generation(executor: impl SqliteExecutor<'_>) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query("SELECT generation FROM catalogue_generation WHERE id = 1")
+        .fetch_one(executor)
+        .await?;
+    Ok(row.get("generation"))
 }
 
+/// Rehash every fingerprint not already hashed with `scheme` and record the
+/// switch in the catalogue configuration, all in one transaction. `config` is
+/// the configuration the catalogue was built with; the updated one is returned.
+///
+/// Hashes are rebuilt from the stored columns, which keep frequencies to the
+/// nearest hertz, so a pair right on a bin boundary can hash differently from
+/// a fresh fingerprint of the same audio.
+#[instrument(skip(pool, config))]
+pub async fn migrate_hash_scheme(
+    pool: &SqlitePool,
+    config: &FingerprintConfig,
+    scheme: HashScheme,
+) -> Result<FingerprintConfig, sqlx::Error> {
+    let config = FingerprintConfig {
+        hash_scheme: scheme,
+        ..config.clone()
+    };
+    let mut tx = pool.begin().await?;
+    let mut last_id = 0_i64;
+    let mut migrated = 0;
+
+    loop {
+        let rows = sqlx::query(
+            "SELECT id, anchor_frequency, target_frequency, delta_time FROM fingerprints WHERE hash_scheme != ? AND id > ? ORDER BY id LIMIT 1000",
+        )
+        .bind(scheme.version())
+        .bind(last_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        last_id = last.get("id");
+
+        for row in &rows {
+            let anchor_freq: i64 = row.get("anchor_frequency");
+            let target_freq: i64 = row.get("target_frequency");
+            let delta_t: f64 = row.get("delta_time");
+            let hash = scheme.hash(
+                anchor_freq.into(),
+                target_freq.into(),
+                Decimal::try_from(delta_t).unwrap_or_default(),
+                &config,
+            );
+            sqlx::query("UPDATE fingerprints SET hash = ?, hash_scheme = ? WHERE id = ?")
+                .bind(hash)
+                .bind(scheme.version())
+                .bind(row.get::<i64, _>("id"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        migrated += rows.len();
+    }
+
+    store_config(&mut *tx, &config).await?;
+    // Rehashing leaves the songs alone, so the triggers don't see it
+    sqlx::query("UPDATE catalogue_generation SET generation = generation + 1 WHERE id = 1")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("Rehashed {} fingerprints to {:?}", migrated, scheme);
+    Ok(config)
+}
+
+/// Counts the significance of a match is judged against, with distinct hashes
+/// counted under `scheme` alone.
+pub async fn catalogue_stats(
+    pool: &SqlitePool,
+    scheme: HashScheme,
+) -> Result<CatalogueStats, sqlx::Error> {
+    let songs = sqlx::query("SELECT COUNT(*) AS songs, AVG(duration) AS mean_duration FROM songs")
+        .fetch_one(pool)
+        .await?;
+    let fingerprints = sqlx::query(
+        "SELECT COUNT(*) AS fingerprints, COUNT(DISTINCT CASE WHEN hash_scheme = ? THEN hash END) AS distinct_hashes FROM fingerprints",
+    )
+    .bind(scheme.version())
+    .fetch_one(pool)
+    .await?;
+    Ok(CatalogueStats {
+        songs: songs.get::<i64, _>("songs") as usize,
+        fingerprints: fingerprints.get::<i64, _>("fingerprints") as usize,
+        distinct_hashes: fingerprints.get::<i64, _>("distinct_hashes") as usize,
+        mean_duration: songs
+            .get::<Option<f64>, _>("mean_duration")
+            .unwrap_or_default() as f32,
+    })
+}
+
+/// The id of the song with this title and artist, if it is catalogued.
+#[instrument(skip(executor))]
+pub async fn song_exists(
+    executor: impl SqliteExecutor<'_>,
+    song: &SongInfo,
+) -> Result<Option<i64>, sqlx::Error> {
+    let row = sqlx::query("SELECT id FROM songs WHERE title = ? AND artist = ?")
+        .bind(&song.title)
+        .bind(&song.artist)
+        .fetch_optional(executor)
+        .await?;
+    Ok(row.map(|row| row.get("id")))
+}
+
+/// Catalogue `song` with its fingerprints and return its id. A song already
+/// in the catalogue is left as it is and its existing id returned.
 #[instrument(skip(pool, fingerprints))]
 pub async fn store_song_fingerprints(
     pool: &SqlitePool,
     song: &SongInfo,
-    duration: f32,
+    duration: f64,
+    scheme: HashScheme,
     fingerprints: &[Fingerprint],
 ) -> Result<i64, sqlx::Error> {
     // Begin a transaction
     let mut tx = pool.begin().await?;
+    let song_id = add_song(&mut tx, song, duration, scheme, fingerprints).await?;
+    // Commit transaction
+    tx.commit().await?;
 
-    // Check if song exists
+    Ok(song_id)
+}
 
-    if let Some(song_id) = song_exists(pool, song).await? {
-        // Song already exists, return the ID
-        warn!("Song already exists: {}", song);
-        return Ok(song_id);
+/// Catalogue each of `songs` like [`store_song_fingerprints`], all in one
+/// transaction, and return their ids in order.
+#[instrument(skip_all, fields(songs = songs.len()))]
+pub async fn store_songs(
+    pool: &SqlitePool,
+    songs: &[NewSong],
+    scheme: HashScheme,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut song_ids = Vec::with_capacity(songs.len());
+    for new in songs {
+        song_ids.push(add_song(&mut tx, &new.song, new.duration, scheme, &new.fingerprints).await?);
     }
+    tx.commit().await?;
+    Ok(song_ids)
+}
 
-    // Insert the song
-    // let song_id = sqlx::query!(
-    //     "INSERT INTO songs ( title, artist, duration) VALUES ( ?, ?, ?)",
-    //     song.title,
-    //     song.artist,
-    //     duration
-    // )
-    // .execute(&mut *tx)
-    // .await?
-    // .last_insert_rowid();
-    let song_id = 0;
+/// Add `song` and its fingerprints unless it is already catalogued, returning
+/// its id either way.
+async fn add_song(
+    conn: &mut SqliteConnection,
+    song: &SongInfo,
+    duration: f64,
+    scheme: HashScheme,
+    fingerprints: &[Fingerprint],
+) -> Result<i64, sqlx::Error> {
+    let Some(song_id) = insert_song(&mut *conn, song, duration).await? else {
+        // Song already exists, return the ID
+        warn!("Song already exists: {}", song);
+        return song_exists(&mut *conn, song)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound);
+    };
     info!("Inserted new song: {} ID: {}", song, song_id);
+    insert_fingerprints(conn, song_id, scheme, fingerprints).await?;
+    Ok(song_id)
+}
+
+/// Add a song row and return its id, or `None` if a song with its title and
+/// artist is already catalogued. Checking and inserting is one statement, so
+/// concurrent writers of the same song can't both get past the check.
+pub(crate) async fn insert_song(
+    conn: &mut SqliteConnection,
+    song: &SongInfo,
+    duration: f64,
+) -> Result<Option<i64>, sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO songs (title, artist, album, isrc, duration) VALUES (?, ?, ?, ?, ?) \
+         ON CONFLICT (title, artist) DO NOTHING RETURNING id",
+    )
+    .bind(&song.title)
+    .bind(&song.artist)
+    .bind(&song.album)
+    .bind(&song.isrc)
+    .bind(duration)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(row.map(|row| row.get("id")))
+}
 
+/// Add `fingerprints`, hashed with `scheme`, to a catalogued song.
+pub(crate) async fn insert_fingerprints(
+    conn: &mut SqliteConnection,
+    song_id: i64,
+    scheme: HashScheme,
+    fingerprints: &[Fingerprint],
+) -> Result<(), sqlx::Error> {
     // Insert fingerprints in batches
     for chunk in fingerprints.chunks(1000) {
         let mut query_builder = sqlx::QueryBuilder::new(
-            "INSERT INTO fingerprints (song_id, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time)",
+            "INSERT INTO fingerprints (song_id, hash_scheme, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time)",
         );
 
         query_builder.push_values(chunk, |mut b, fingerprint| {
@@ -78,6 +327,7 @@ pub async fn store_song_fingerprints(
             ) = fingerprint.into();
 
             b.push_bind(song_id)
+                .push_bind(scheme.version())
                 .push_bind(hash)
                 .push_bind(time_offset)
                 .push_bind(confidence)
@@ -86,18 +336,32 @@ pub async fn store_song_fingerprints(
                 .push_bind(delta_t);
         });
 
-        query_builder.build().execute(&mut *tx).await?;
+        query_builder.build().execute(&mut *conn).await?;
     }
+    Ok(())
+}
 
-    // Commit transaction
+/// Remove a song and its fingerprints, returning whether it was catalogued.
+#[instrument(skip(pool))]
+pub async fn delete_song(pool: &SqlitePool, song_id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM fingerprints WHERE song_id = ?")
+        .bind(song_id)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM songs WHERE id = ?")
+        .bind(song_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
     tx.commit().await?;
-
-    Ok(song_id)
+    Ok(deleted > 0)
 }
 
 #[instrument(skip(pool, fingerprints))]
 pub async fn find_similar_fingerprints(
     pool: &SqlitePool,
+    scheme: HashScheme,
     fingerprints: &[Fingerprint],
 ) -> Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error> {
     let mut result_map: HashMap<i64, Vec<Fingerprint>> = HashMap::new();
@@ -108,7 +372,9 @@ pub async fn find_similar_fingerprints(
         let mut builder = sqlx::QueryBuilder::new(
             "SELECT song_id, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time FROM fingerprints WHERE ",
         );
-        builder.push("hash IN (");
+        builder.push("hash_scheme = ");
+        builder.push_bind(scheme.version());
+        builder.push(" AND hash IN (");
         let mut separated = builder.separated(", ");
         for hash in &hashes {
             separated.push_bind(*hash);
@@ -142,15 +408,58 @@ pub async fn find_similar_fingerprints(
     Ok(result_map)
 }
 
+/// Every catalogued song, in the order they were added.
+pub async fn list_songs(pool: &SqlitePool) -> Result<Vec<SongRecord>, sqlx::Error> {
+    let rows = sqlx::query(SONG_RECORDS)
+        .bind(None::<i64>)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(song_record_from_row).collect())
+}
+
+/// The catalogued song with id `song_id`, if there is one.
+pub async fn song_record(
+    pool: &SqlitePool,
+    song_id: i64,
+) -> Result<Option<SongRecord>, sqlx::Error> {
+    let row = sqlx::query(SONG_RECORDS)
+        .bind(song_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(song_record_from_row))
+}
+
+/// Songs with their fingerprint counts; all of them when bound to `NULL`, or
+/// the one with the bound id.
+const SONG_RECORDS: &str = "SELECT songs.id, title, artist, album, isrc, CAST(duration AS REAL) AS duration, COUNT(fingerprints.id) AS fingerprints \
+     FROM songs LEFT JOIN fingerprints ON fingerprints.song_id = songs.id \
+     WHERE ?1 IS NULL OR songs.id = ?1 GROUP BY songs.id ORDER BY songs.id";
+
+fn song_record_from_row(row: &sqlx::sqlite::SqliteRow) -> SongRecord {
+    SongRecord {
+        id: row.get("id"),
+        song: SongInfo {
+            title: row.get("title"),
+            artist: row.get("artist"),
+            album: row.get("album"),
+            isrc: row.get("isrc"),
+        },
+        duration: row.get("duration"),
+        fingerprints: row.get::<i64, _>("fingerprints") as usize,
+    }
+}
+
 pub async fn get_song_info(
     pool: &SqlitePool,
     song_ids: &[i64],
-) -> Result<HashMap<i64, (String, String, u32)>, sqlx::Error> {
+) -> Result<HashMap<i64, (String, String, f64)>, sqlx::Error> {
     let mut result_map = HashMap::new();
 
     for chunk in song_ids.chunks(100) {
-        let mut builder =
-            sqlx::QueryBuilder::new("SELECT id, title, artist, duration FROM songs WHERE ");
+        let mut builder = sqlx::QueryBuilder::new(
+            // NUMERIC affinity stores whole durations as integers
+            "SELECT id, title, artist, CAST(duration AS REAL) AS duration FROM songs WHERE ",
+        );
         builder.push("id IN (");
         let mut separated = builder.separated(", ");
         for id in chunk {
@@ -164,10 +473,124 @@ pub async fn get_song_info(
             let id: i64 = row.get("id");
             let title: String = row.get("title");
             let artist: String = row.get("artist");
-            let duration: u32 = row.get("duration");
+            let duration: f64 = row.get("duration");
 
             result_map.insert(id, (title, artist, duration));
         }
     }
     Ok(result_map)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::song;
+
+    async fn memory_catalogue() -> SqlitePool {
+        // Every connection to `:memory:` is a separate database, so keep to one
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn catalogues_built_before_configs_were_recorded_keep_the_legacy_one() {
+        let pool = memory_catalogue().await;
+        assert_eq!(
+            read_catalogue_config(&pool, FingerprintConfig::speech())
+                .await
+                .unwrap(),
+            FingerprintConfig::speech()
+        );
+        assert_eq!(load_config(&pool).await.unwrap(), None);
+        assert_eq!(
+            catalogue_config(&pool, FingerprintConfig::speech())
+                .await
+                .unwrap(),
+            FingerprintConfig::speech()
+        );
+
+        let pool = memory_catalogue().await;
+        sqlx::query("INSERT INTO songs (title, artist, duration) VALUES ('Waxwing', 'Sorry', 180)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        // Reading it records nothing
+        assert_eq!(
+            read_catalogue_config(&pool, FingerprintConfig::music())
+                .await
+                .unwrap(),
+            FingerprintConfig::legacy()
+        );
+        assert_eq!(load_config(&pool).await.unwrap(), None);
+        let config = catalogue_config(&pool, FingerprintConfig::music())
+            .await
+            .unwrap();
+        assert_eq!(config, FingerprintConfig::legacy());
+        assert_eq!(load_config(&pool).await.unwrap(), Some(config));
+    }
+
+    #[tokio::test]
+    async fn migrated_catalogues_match_queries_hashed_with_the_new_scheme() {
+        let pool = memory_catalogue().await;
+        let legacy = FingerprintConfig {
+            hash_scheme: HashScheme::Fnv1a,
+            ..FingerprintConfig::music()
+        };
+        store_config(&pool, &legacy).await.unwrap();
+        let waxwing = song(&legacy, 0);
+        sqlx::query("INSERT INTO songs (id, title, artist, duration) VALUES (7, ?, ?, ?)")
+            .bind(&waxwing.song.title)
+            .bind(&waxwing.song.artist)
+            .bind(waxwing.duration)
+            .execute(&pool)
+            .await
+            .unwrap();
+        // Rows as an older catalogue would have stored them, before hash schemes
+        for fingerprint in &waxwing.fingerprints {
+            let (hash, time_offset, confidence, anchor_freq, target_freq, delta_t) =
+                fingerprint.into();
+            sqlx::query(
+                "INSERT INTO fingerprints (song_id, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time) VALUES (7, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(hash)
+            .bind(time_offset)
+            .bind(confidence)
+            .bind(anchor_freq)
+            .bind(target_freq)
+            .bind(delta_t)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let migrated = migrate_hash_scheme(&pool, &legacy, HashScheme::Packed)
+            .await
+            .unwrap();
+
+        assert_eq!(migrated.hash_scheme, HashScheme::Packed);
+        assert_eq!(load_config(&pool).await.unwrap(), Some(migrated.clone()));
+        let query = song(&migrated, 0).fingerprints;
+        let matches = find_similar_fingerprints(&pool, HashScheme::Packed, &query)
+            .await
+            .unwrap();
+        assert_eq!(matches[&7].len(), 20);
+        let stale = find_similar_fingerprints(&pool, HashScheme::Fnv1a, &query)
+            .await
+            .unwrap();
+        assert!(stale.is_empty());
+        assert_eq!(
+            catalogue_stats(&pool, HashScheme::Packed).await.unwrap(),
+            CatalogueStats {
+                songs: 1,
+                fingerprints: 20,
+                distinct_hashes: 20,
+                mean_duration: 181.5,
+            }
+        );
+    }
+}
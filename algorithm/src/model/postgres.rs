@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use sqlx::{PgConnection, PgPool, Row, postgres::PgPoolOptions};
+use tracing::{info, instrument, warn};
+
+use super::{FingerprintStore, NewSong, SongInfo};
+use crate::audio::{CatalogueStats, Fingerprint, HashScheme};
+
+/// A catalogue in Postgres, with the schema in `migrations/postgres`. Its
+/// tables are named `catalogue_songs` and `catalogue_fingerprints`, apart from
+/// the server's, but it keeps its own migration history, so give it a database
+/// of its own rather than the server's.
+#[derive(Debug, Clone)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    /// Connect to the catalogue at `url` and bring its schema up to date.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+        Self::new(pool).await
+    }
+
+    /// Use an existing pool, bringing its schema up to date.
+    pub async fn new(pool: PgPool) -> Result<Self, sqlx::Error> {
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+        Ok(Self { pool })
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+impl FingerprintStore for PostgresStore {
+    async fn song_exists(&self, song: &SongInfo) -> Result<Option<i64>, sqlx::Error> {
+        let row = sqlx::query("SELECT id FROM catalogue_songs WHERE title = $1 AND artist = $2")
+            .bind(&song.title)
+            .bind(&song.artist)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("id")))
+    }
+
+    #[instrument(skip(self, fingerprints))]
+    async fn insert_song(
+        &self,
+        song: &SongInfo,
+        duration: f64,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let song_id = add_song(&mut tx, song, duration, scheme, fingerprints).await?;
+        tx.commit().await?;
+        Ok(song_id)
+    }
+
+    #[instrument(skip_all, fields(songs = songs.len()))]
+    async fn insert_songs(
+        &self,
+        songs: &[NewSong],
+        scheme: HashScheme,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut song_ids = Vec::with_capacity(songs.len());
+        for new in songs {
+            song_ids
+                .push(add_song(&mut tx, &new.song, new.duration, scheme, &new.fingerprints).await?);
+        }
+        tx.commit().await?;
+        Ok(song_ids)
+    }
+
+    #[instrument(skip(self, fingerprints))]
+    async fn find_similar(
+        &self,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error> {
+        let hashes = fingerprints.iter().map(|f| f.hash).unique().collect_vec();
+        let rows = sqlx::query(
+            "SELECT song_id, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time FROM catalogue_fingerprints WHERE hash_scheme = $1 AND hash = ANY($2) ORDER BY song_id",
+        )
+        .bind(scheme.version())
+        .bind(&hashes)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut result_map: HashMap<i64, Vec<Fingerprint>> = HashMap::new();
+        for row in rows {
+            result_map.entry(row.get("song_id")).or_default().push(
+                (
+                    row.get::<i64, _>("hash"),
+                    row.get::<f64, _>("time_offset"),
+                    row.get::<i64, _>("confidence"),
+                    row.get::<i64, _>("anchor_frequency"),
+                    row.get::<i64, _>("target_frequency"),
+                    row.get::<f64, _>("delta_time"),
+                )
+                    .into(),
+            );
+        }
+        Ok(result_map)
+    }
+
+    async fn song_info(
+        &self,
+        song_ids: &[i64],
+    ) -> Result<HashMap<i64, (String, String, f64)>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT id, title, artist, duration FROM catalogue_songs WHERE id = ANY($1)",
+        )
+        .bind(song_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("id"),
+                    (row.get("title"), row.get("artist"), row.get("duration")),
+                )
+            })
+            .collect())
+    }
+
+    async fn delete_song(&self, song_id: i64) -> Result<bool, sqlx::Error> {
+        // Fingerprints go with the song
+        let deleted = sqlx::query("DELETE FROM catalogue_songs WHERE id = $1")
+            .bind(song_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    async fn stats(&self, scheme: HashScheme) -> Result<CatalogueStats, sqlx::Error> {
+        let songs = sqlx::query(
+            "SELECT COUNT(*) AS songs, AVG(duration) AS mean_duration FROM catalogue_songs",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let fingerprints = sqlx::query(
+            "SELECT COUNT(*) AS fingerprints, COUNT(DISTINCT hash) FILTER (WHERE hash_scheme = $1) AS distinct_hashes FROM catalogue_fingerprints",
+        )
+        .bind(scheme.version())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(CatalogueStats {
+            songs: songs.get::<i64, _>("songs") as usize,
+            fingerprints: fingerprints.get::<i64, _>("fingerprints") as usize,
+            distinct_hashes: fingerprints.get::<i64, _>("distinct_hashes") as usize,
+            mean_duration: songs
+                .get::<Option<f64>, _>("mean_duration")
+                .unwrap_or_default() as f32,
+        })
+    }
+}
+
+/// Add `song` and its fingerprints unless it is already catalogued, returning
+/// its id either way.
+async fn add_song(
+    conn: &mut PgConnection,
+    song: &SongInfo,
+    duration: f64,
+    scheme: HashScheme,
+    fingerprints: &[Fingerprint],
+) -> Result<i64, sqlx::Error> {
+    // Checked and inserted in one statement, so concurrent writers of the same
+    // song can't both get past the check
+    let inserted = sqlx::query(
+        "INSERT INTO catalogue_songs (title, artist, album, isrc, duration) VALUES ($1, $2, $3, $4, $5) \
+         ON CONFLICT (title, artist) DO NOTHING RETURNING id",
+    )
+    .bind(&song.title)
+    .bind(&song.artist)
+    .bind(&song.album)
+    .bind(&song.isrc)
+    .bind(duration)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(song_id) = inserted.map(|row| row.get::<i64, _>("id")) else {
+        warn!("Song already exists: {}", song);
+        let row = sqlx::query("SELECT id FROM catalogue_songs WHERE title = $1 AND artist = $2")
+            .bind(&song.title)
+            .bind(&song.artist)
+            .fetch_one(&mut *conn)
+            .await?;
+        return Ok(row.get("id"));
+    };
+    info!("Inserted new song: {} ID: {}", song, song_id);
+
+    // Insert fingerprints in batches
+    for chunk in fingerprints.chunks(1000) {
+        let mut query_builder = sqlx::QueryBuilder::new(
+            "INSERT INTO catalogue_fingerprints (song_id, hash_scheme, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time)",
+        );
+        query_builder.push_values(chunk, |mut b, fingerprint| {
+            let (hash, time_offset, confidence, anchor_freq, target_freq, delta_t): (
+                i64,
+                f64,
+                i64,
+                i64,
+                i64,
+                f64,
+            ) = fingerprint.into();
+
+            b.push_bind(song_id)
+                .push_bind(scheme.version())
+                .push_bind(hash)
+                .push_bind(time_offset)
+                .push_bind(confidence)
+                .push_bind(anchor_freq)
+                .push_bind(target_freq)
+                .push_bind(delta_t);
+        });
+        query_builder.build().execute(&mut *conn).await?;
+    }
+
+    Ok(song_id)
+}
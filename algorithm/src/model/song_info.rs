@@ -0,0 +1,50 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SongInfo {
+    pub title: String,
+    pub artist: String,
+    pub album: Option<String>,
+    /// International Standard Recording Code, when the source tags one.
+    pub isrc: Option<String>,
+}
+
+impl SongInfo {
+    pub fn new(title: impl Into<String>, artist: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            artist: artist.into(),
+            album: None,
+            isrc: None,
+        }
+    }
+
+    pub fn with_album(mut self, album: impl Into<String>) -> Self {
+        self.album = Some(album.into());
+        self
+    }
+
+    pub fn with_isrc(mut self, isrc: impl Into<String>) -> Self {
+        self.isrc = Some(isrc.into());
+        self
+    }
+}
+
+/// A catalogued song, as the catalogue lists it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SongRecord {
+    pub id: i64,
+    #[serde(flatten)]
+    pub song: SongInfo,
+    /// In seconds.
+    pub duration: f64,
+    pub fingerprints: usize,
+}
+
+impl Display for SongInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.title, self.artist)
+    }
+}
@@ -0,0 +1,172 @@
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use anyhow::ensure;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use super::{
+    HashIndex, SongInfo, catalogue_generation, catalogue_stats, delete_song,
+    find_similar_fingerprints, get_song_info, song_exists, store_song_fingerprints, store_songs,
+};
+use crate::audio::{CatalogueStats, Fingerprint, HashScheme};
+
+/// Where a catalogue's songs and fingerprints live. Identification only needs
+/// this, so it runs the same against SQLite in the CLI, Postgres behind the
+/// server and a [`super::MemoryStore`] in tests.
+pub trait FingerprintStore: Send + Sync {
+    /// The id of the song with this title and artist, if it is catalogued.
+    fn song_exists(
+        &self,
+        song: &SongInfo,
+    ) -> impl Future<Output = Result<Option<i64>, sqlx::Error>> + Send;
+
+    /// Catalogue `song` with its fingerprints and return its id. A song already
+    /// in the catalogue is left as it is and its existing id returned.
+    fn insert_song(
+        &self,
+        song: &SongInfo,
+        duration: f64,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> impl Future<Output = Result<i64, sqlx::Error>> + Send;
+
+    /// Catalogue each of `songs`, fingerprinted with `scheme`, like
+    /// [`FingerprintStore::insert_song`] and return their ids in order. Stores
+    /// that can write them all in one transaction do, so either every song is
+    /// added or none are.
+    fn insert_songs(
+        &self,
+        songs: &[NewSong],
+        scheme: HashScheme,
+    ) -> impl Future<Output = Result<Vec<i64>, sqlx::Error>> + Send {
+        async move {
+            let mut song_ids = Vec::with_capacity(songs.len());
+            for new in songs {
+                song_ids.push(
+                    self.insert_song(&new.song, new.duration, scheme, &new.fingerprints)
+                        .await?,
+                );
+            }
+            Ok(song_ids)
+        }
+    }
+
+    /// Catalogue fingerprints hashed with `scheme` that share a hash with any
+    /// of `fingerprints`, by song.
+    fn find_similar(
+        &self,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> impl Future<Output = Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error>> + Send;
+
+    /// Title, artist and duration of each of `song_ids` that is catalogued.
+    fn song_info(
+        &self,
+        song_ids: &[i64],
+    ) -> impl Future<Output = Result<HashMap<i64, (String, String, f64)>, sqlx::Error>> + Send;
+
+    /// Remove a song and its fingerprints, returning whether it was catalogued.
+    fn delete_song(&self, song_id: i64) -> impl Future<Output = Result<bool, sqlx::Error>> + Send;
+
+    /// Counts the significance of a match is judged against, with distinct
+    /// hashes counted under `scheme` alone.
+    fn stats(
+        &self,
+        scheme: HashScheme,
+    ) -> impl Future<Output = Result<CatalogueStats, sqlx::Error>> + Send;
+}
+
+/// A fingerprinted song, ready for [`FingerprintStore::insert_songs`].
+#[derive(Debug, Clone)]
+pub struct NewSong {
+    pub song: SongInfo,
+    /// In seconds.
+    pub duration: f64,
+    pub fingerprints: Vec<Fingerprint>,
+}
+
+/// A catalogue in a SQLite database, as set up by [`super::setup_database`],
+/// optionally with a [`HashIndex`] of it to look hashes up in.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+    index: Option<Arc<HashIndex>>,
+}
+
+impl SqliteStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool, index: None }
+    }
+
+    /// Look hashes up in `index` rather than the database. An index built
+    /// before the catalogue last changed is refused; one that goes stale later,
+    /// as songs are added or removed, is passed over for the database.
+    pub async fn with_index(self, index: HashIndex) -> anyhow::Result<Self> {
+        let generation = catalogue_generation(&self.pool).await?;
+        ensure!(
+            index.generation() == generation,
+            "the hash index is out of date: the catalogue has changed since it was built"
+        );
+        Ok(Self {
+            index: Some(Arc::new(index)),
+            ..self
+        })
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+impl FingerprintStore for SqliteStore {
+    async fn song_exists(&self, song: &SongInfo) -> Result<Option<i64>, sqlx::Error> {
+        song_exists(&self.pool, song).await
+    }
+
+    async fn insert_song(
+        &self,
+        song: &SongInfo,
+        duration: f64,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<i64, sqlx::Error> {
+        store_song_fingerprints(&self.pool, song, duration, scheme, fingerprints).await
+    }
+
+    async fn insert_songs(
+        &self,
+        songs: &[NewSong],
+        scheme: HashScheme,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        store_songs(&self.pool, songs, scheme).await
+    }
+
+    async fn find_similar(
+        &self,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error> {
+        if let Some(index) = self.index.as_ref().filter(|index| index.scheme() == scheme) {
+            if catalogue_generation(&self.pool).await? == index.generation() {
+                return Ok(index.find_similar(fingerprints)?);
+            }
+            warn!("The hash index is out of date; looking hashes up in the catalogue");
+        }
+        find_similar_fingerprints(&self.pool, scheme, fingerprints).await
+    }
+
+    async fn song_info(
+        &self,
+        song_ids: &[i64],
+    ) -> Result<HashMap<i64, (String, String, f64)>, sqlx::Error> {
+        get_song_info(&self.pool, song_ids).await
+    }
+
+    async fn delete_song(&self, song_id: i64) -> Result<bool, sqlx::Error> {
+        delete_song(&self.pool, song_id).await
+    }
+
+    async fn stats(&self, scheme: HashScheme) -> Result<CatalogueStats, sqlx::Error> {
+        catalogue_stats(&self.pool, scheme).await
+    }
+}
@@ -0,0 +1,222 @@
+//! Batch identification reports: one flat record per query, written as JSON
+//! Lines or CSV so runs of different algorithm versions can be diffed.
+
+use std::{fmt::Display, io::Write, path::Path, str::FromStr, time::Duration};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use crate::MatchResult;
+
+/// The outcome of identifying one query file: its best match, if any.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IdentifyRecord {
+    pub query: String,
+    pub song_id: Option<i64>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub confidence: Option<f32>,
+    /// Seconds into the song the query starts.
+    pub time_offset: Option<f32>,
+    pub matched_count: Option<usize>,
+    pub p_value: Option<f64>,
+    /// Wall-clock time to decode, fingerprint, look up and match the query.
+    pub runtime_ms: f64,
+    /// Why the query couldn't be identified at all, as opposed to not matching.
+    pub error: Option<String>,
+}
+
+impl IdentifyRecord {
+    /// A query and its best match, with the matched song's title and artist;
+    /// `None` when nothing matched.
+    pub fn new(
+        query: impl Into<String>,
+        best: Option<(&MatchResult, &str, &str)>,
+        runtime: Duration,
+    ) -> Self {
+        let mut record = Self::empty(query.into(), runtime);
+        if let Some((result, title, artist)) = best {
+            record.song_id = Some(result.song_id);
+            record.title = Some(title.to_string());
+            record.artist = Some(artist.to_string());
+            record.confidence = Some(result.confidence);
+            record.time_offset = Some(result.time_offset);
+            record.matched_count = Some(result.matched_count);
+            record.p_value = Some(result.p_value);
+        }
+        record
+    }
+
+    /// A query that failed before it could be matched.
+    pub fn failed(query: impl Into<String>, error: impl Display, runtime: Duration) -> Self {
+        Self {
+            error: Some(format!("{error:#}")),
+            ..Self::empty(query.into(), runtime)
+        }
+    }
+
+    fn empty(query: String, runtime: Duration) -> Self {
+        Self {
+            query,
+            song_id: None,
+            title: None,
+            artist: None,
+            confidence: None,
+            time_offset: None,
+            matched_count: None,
+            p_value: None,
+            runtime_ms: runtime.as_secs_f64() * 1000.0,
+            error: None,
+        }
+    }
+}
+
+/// How a report is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One JSON object per line.
+    JsonLines,
+    /// A header row, then one row per record; missing values are empty.
+    Csv,
+}
+
+impl ReportFormat {
+    /// The format a file's extension names: `.jsonl`/`.ndjson` or `.csv`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("unknown report format {s:?}; expected jsonl or csv"),
+        }
+    }
+}
+
+/// Writes [`IdentifyRecord`]s as they come, in a [`ReportFormat`].
+pub enum ReportWriter<W: Write> {
+    JsonLines(W),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> ReportWriter<W> {
+    pub fn new(format: ReportFormat, out: W) -> Self {
+        match format {
+            ReportFormat::JsonLines => Self::JsonLines(out),
+            ReportFormat::Csv => Self::Csv(Box::new(csv::Writer::from_writer(out))),
+        }
+    }
+
+    pub fn write(&mut self, record: &IdentifyRecord) -> Result<()> {
+        match self {
+            Self::JsonLines(out) => {
+                serde_json::to_writer(&mut *out, record)?;
+                out.write_all(b"\n")?;
+            }
+            Self::Csv(out) => out.serialize(record)?,
+        }
+        Ok(())
+    }
+
+    /// Flush everything written so far.
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::JsonLines(out) => out.flush()?,
+            Self::Csv(out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<IdentifyRecord> {
+        let result = MatchResult {
+            song_id: 7,
+            confidence: 0.5,
+            matched_count: 42,
+            time_offset: 12.25,
+            speed: 1.0,
+            bin_counts: vec![1, 42, 2],
+            p_value: 1e-12,
+            false_positives: 1e-8,
+        };
+        vec![
+            IdentifyRecord::new(
+                "clips/a.wav",
+                Some((&result, "Waxwing", "Sorry")),
+                Duration::from_millis(120),
+            ),
+            IdentifyRecord::new("clips/b.wav", None, Duration::from_millis(80)),
+            IdentifyRecord::failed(
+                "clips/c.mp3",
+                anyhow::anyhow!("unsupported, \"really\""),
+                Duration::ZERO,
+            ),
+        ]
+    }
+
+    fn written(format: ReportFormat) -> String {
+        let mut out = Vec::new();
+        let mut writer = ReportWriter::new(format, &mut out);
+        for record in records() {
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn reports_are_written_as_json_lines_or_csv() {
+        let jsonl = written(ReportFormat::JsonLines);
+        let lines: Vec<serde_json::Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["title"], "Waxwing");
+        assert_eq!(lines[0]["matched_count"], 42);
+        assert_eq!(lines[0]["runtime_ms"], 120.0);
+        assert!(lines[1]["song_id"].is_null());
+        assert_eq!(lines[2]["error"], "unsupported, \"really\"");
+
+        let csv = written(ReportFormat::Csv);
+        let mut rows = csv.lines();
+        assert_eq!(
+            rows.next().unwrap(),
+            "query,song_id,title,artist,confidence,time_offset,matched_count,p_value,runtime_ms,error"
+        );
+        assert_eq!(
+            rows.next().unwrap(),
+            "clips/a.wav,7,Waxwing,Sorry,0.5,12.25,42,1e-12,120.0,"
+        );
+        assert_eq!(rows.next().unwrap(), "clips/b.wav,,,,,,,,80.0,");
+        assert_eq!(
+            rows.next().unwrap(),
+            "clips/c.mp3,,,,,,,,0.0,\"unsupported, \"\"really\"\"\""
+        );
+    }
+
+    #[test]
+    fn formats_are_named_by_extension() {
+        assert_eq!(
+            ReportFormat::from_path(Path::new("out/run.JSONL")),
+            Some(ReportFormat::JsonLines)
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("run.csv")),
+            Some(ReportFormat::Csv)
+        );
+        assert_eq!(ReportFormat::from_path(Path::new("run.txt")), None);
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+}
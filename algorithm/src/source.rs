@@ -0,0 +1,446 @@
+//! Where the audio for ingestion comes from: local files, whole directories,
+//! HTTP URLs or YouTube searches, all handed to the same pipeline.
+
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File},
+    future::Future,
+    io::{BufReader, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, StandardTagKey, Tag},
+    probe::{Hint, ProbeResult},
+};
+use tracing::{debug, info, warn};
+
+use crate::{SongInfo, youtube};
+
+/// Extensions of the containers the decoder understands.
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aiff", "flac", "m4a", "mp3", "mp4", "oga", "ogg", "wav",
+];
+
+/// Encoded audio, ready for [`crate::Fingerprinter::decode`].
+pub trait EncodedAudio: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> EncodedAudio for T {}
+
+/// One song a source can provide, and where to get it from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub song: SongInfo,
+    /// A path, URL or search query, depending on the source.
+    pub location: String,
+}
+
+/// The tracks a source lists, and what it passed over finding them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listing {
+    pub tracks: Vec<Track>,
+    /// Files that aren't audio.
+    pub ignored: Vec<String>,
+    /// Directories that couldn't be read, and why.
+    pub unreadable: Vec<(String, String)>,
+}
+
+impl From<Vec<Track>> for Listing {
+    fn from(tracks: Vec<Track>) -> Self {
+        Self {
+            tracks,
+            ..Self::default()
+        }
+    }
+}
+
+/// A collection of songs to ingest. Listing is cheap; audio is only fetched,
+/// and tags only read, when a track is ingested.
+pub trait AudioSource: Send + Sync {
+    /// Every track this source provides.
+    fn tracks(&self) -> impl Future<Output = Result<Listing>> + Send;
+
+    /// The song one of [`AudioSource::tracks`] really is, once the source has
+    /// looked closer, as a file's tags say. Listed songs are named as well as
+    /// can be without that.
+    fn song(&self, track: &Track) -> impl Future<Output = Result<SongInfo>> + Send {
+        let song = track.song.clone();
+        async move { Ok(song) }
+    }
+
+    /// The encoded audio of one of [`AudioSource::tracks`].
+    fn open(&self, track: &Track) -> impl Future<Output = Result<Box<dyn EncodedAudio>>> + Send;
+}
+
+/// A single audio file on disk.
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    path: PathBuf,
+    /// The song it was given, or `None` to read it from its tags.
+    song: Option<SongInfo>,
+}
+
+impl LocalFile {
+    /// The file at `path`, catalogued from its tags (see [`song_from_file`]).
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            song: None,
+        }
+    }
+
+    /// The file at `path`, catalogued as `song`.
+    pub fn with_song(path: impl Into<PathBuf>, song: SongInfo) -> Self {
+        Self {
+            path: path.into(),
+            song: Some(song),
+        }
+    }
+}
+
+impl AudioSource for LocalFile {
+    async fn tracks(&self) -> Result<Listing> {
+        Ok(vec![Track {
+            song: self
+                .song
+                .clone()
+                .unwrap_or_else(|| song_from_path(&self.path)),
+            location: self.path.display().to_string(),
+        }]
+        .into())
+    }
+
+    async fn song(&self, track: &Track) -> Result<SongInfo> {
+        match &self.song {
+            Some(song) => Ok(song.clone()),
+            None => tags_of(track).await,
+        }
+    }
+
+    async fn open(&self, track: &Track) -> Result<Box<dyn EncodedAudio>> {
+        open_file(Path::new(&track.location))
+    }
+}
+
+/// Every audio file anywhere under a directory, catalogued from their tags
+/// (see [`song_from_file`]). Symbolic links to directories are not followed;
+/// subdirectories that can't be read are listed as unreadable rather than
+/// failing the rest.
+#[derive(Debug, Clone)]
+pub struct Directory {
+    path: PathBuf,
+}
+
+impl Directory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl AudioSource for Directory {
+    async fn tracks(&self) -> Result<Listing> {
+        let dir = self.path.clone();
+        let walk = tokio::task::spawn_blocking(move || walk(&dir)).await??;
+        debug!(
+            "Found {} audio files in {}",
+            walk.files.len(),
+            self.path.display()
+        );
+        Ok(Listing {
+            tracks: walk
+                .files
+                .into_iter()
+                .map(|path| Track {
+                    song: song_from_path(&path),
+                    location: path.display().to_string(),
+                })
+                .collect(),
+            ignored: walk
+                .ignored
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            unreadable: walk
+                .unreadable
+                .into_iter()
+                .map(|(path, error)| (path.display().to_string(), error))
+                .collect(),
+        })
+    }
+
+    async fn song(&self, track: &Track) -> Result<SongInfo> {
+        tags_of(track).await
+    }
+
+    async fn open(&self, track: &Track) -> Result<Box<dyn EncodedAudio>> {
+        open_file(Path::new(&track.location))
+    }
+}
+
+/// A song downloaded over HTTP.
+#[derive(Debug, Clone)]
+pub struct Http {
+    url: String,
+    song: SongInfo,
+    client: reqwest::Client,
+}
+
+impl Http {
+    pub fn new(url: impl Into<String>, song: SongInfo) -> Self {
+        Self {
+            url: url.into(),
+            song,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl AudioSource for Http {
+    async fn tracks(&self) -> Result<Listing> {
+        Ok(vec![Track {
+            song: self.song.clone(),
+            location: self.url.clone(),
+        }]
+        .into())
+    }
+
+    async fn open(&self, track: &Track) -> Result<Box<dyn EncodedAudio>> {
+        info!("Downloading {}", track.location);
+        let bytes = self
+            .client
+            .get(&track.location)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(Box::new(Cursor::new(bytes.to_vec())))
+    }
+}
+
+/// Songs found by searching YouTube for their title and artist.
+#[derive(Debug, Clone)]
+pub struct YouTube {
+    songs: Vec<SongInfo>,
+}
+
+impl YouTube {
+    pub fn new(songs: Vec<SongInfo>) -> Self {
+        Self { songs }
+    }
+}
+
+impl AudioSource for YouTube {
+    async fn tracks(&self) -> Result<Listing> {
+        Ok(self
+            .songs
+            .iter()
+            .map(|song| Track {
+                song: song.clone(),
+                location: song.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    async fn open(&self, track: &Track) -> Result<Box<dyn EncodedAudio>> {
+        Ok(Box::new(
+            youtube::get_audio_from_youtube(&track.location).await?,
+        ))
+    }
+}
+
+/// Whether `path` has the extension of a container the decoder understands.
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// The song a file is named after: `Title - Artist.ext`, as downloads are
+/// saved, or just `Title.ext` for an unknown artist.
+pub fn song_from_path(path: &Path) -> SongInfo {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match stem.split_once(" - ") {
+        Some((title, artist)) => SongInfo::new(title.trim(), artist.trim()),
+        None => SongInfo::new(stem.trim(), "Unknown"),
+    }
+}
+
+/// The song in the file at `path`: its title, artist, album and ISRC tags,
+/// whichever of ID3, Vorbis comments, MP4 atoms or RIFF INFO it has, with the
+/// title and artist falling back to [`song_from_path`].
+pub fn song_from_file(path: &Path) -> SongInfo {
+    let mut song = song_from_path(path);
+    let tags = match read_tags(path) {
+        Ok(tags) => tags,
+        Err(e) => {
+            debug!("Couldn't read tags from {}: {}", path.display(), e);
+            return song;
+        }
+    };
+    for tag in tags {
+        // RIFF INFO strings keep their NUL terminators
+        let value = tag
+            .value
+            .to_string()
+            .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+            .to_owned();
+        if value.is_empty() {
+            continue;
+        }
+        match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => song.title = value,
+            Some(StandardTagKey::Artist) => song.artist = value,
+            Some(StandardTagKey::Album) => song.album = Some(value),
+            Some(StandardTagKey::IdentIsrc) => song.isrc = Some(value),
+            _ => {}
+        }
+    }
+    song
+}
+
+/// Length of the audio file at `path` in seconds, as its container records
+/// it, without decoding; `None` when the container doesn't say.
+pub(crate) fn container_duration(path: &Path) -> Result<Option<f64>> {
+    let probed = probe(path)?;
+    let Some(track) = probed.format.default_track() else {
+        return Ok(None);
+    };
+    let params = &track.codec_params;
+    let Some(frames) = params.n_frames else {
+        return Ok(None);
+    };
+    Ok(match (params.time_base, params.sample_rate) {
+        (Some(time_base), _) => {
+            let time = time_base.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
+        }
+        (None, Some(sample_rate)) => Some(frames as f64 / sample_rate as f64),
+        (None, None) => None,
+    })
+}
+
+/// The container of the file at `path`, its format found from its contents
+/// and extension.
+fn probe(path: &Path) -> Result<ProbeResult> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    Ok(symphonia::default::get_probe().format(
+        &hint,
+        MediaSourceStream::new(Box::new(file), Default::default()),
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?)
+}
+
+/// Every tag in the file at `path`. Tags ahead of the container, like ID3v2 on
+/// an MP3, come before the container's own so the latter win.
+fn read_tags(path: &Path) -> Result<Vec<Tag>> {
+    let mut probed = probe(path)?;
+    let mut tags = Vec::new();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        tags.extend_from_slice(revision.tags());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend_from_slice(revision.tags());
+    }
+    Ok(tags)
+}
+
+/// Every audio file under `dir`, at any depth, in order, without reading
+/// them. Symbolic links to directories are not followed, and subdirectories
+/// that can't be read are logged and passed over.
+pub fn audio_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(walk(dir)?.files)
+}
+
+/// The song the file `track` is located at, from its tags, read on a blocking
+/// thread.
+async fn tags_of(track: &Track) -> Result<SongInfo> {
+    let path = PathBuf::from(&track.location);
+    Ok(tokio::task::spawn_blocking(move || song_from_file(&path)).await?)
+}
+
+/// What [`walk`] found under a directory.
+#[derive(Debug, Default)]
+struct Walk {
+    /// Audio files, in order.
+    files: Vec<PathBuf>,
+    /// Everything else that isn't a directory.
+    ignored: Vec<PathBuf>,
+    /// Subdirectories, or entries of them, that couldn't be read, and why.
+    unreadable: Vec<(PathBuf, String)>,
+}
+
+/// Every audio file under `dir`, at any depth. Only `dir` itself not being
+/// readable is an error; anything below that can't be read is recorded and
+/// passed over.
+fn walk(dir: &Path) -> Result<Walk> {
+    let entries = fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
+    let mut walk = Walk::default();
+    walk_entries(dir, entries, &mut walk);
+    walk.files.sort();
+    walk.ignored.sort();
+    for path in &walk.ignored {
+        debug!("Skipping {}", path.display());
+    }
+    for (path, error) in &walk.unreadable {
+        warn!("Couldn't read {}: {}", path.display(), error);
+    }
+    Ok(walk)
+}
+
+fn walk_entries(dir: &Path, entries: fs::ReadDir, walk: &mut Walk) {
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                walk.unreadable.push((dir.to_path_buf(), e.to_string()));
+                continue;
+            }
+        };
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => match fs::read_dir(&path) {
+                Ok(entries) => walk_entries(&path, entries, walk),
+                Err(e) => walk.unreadable.push((path, e.to_string())),
+            },
+            Ok(_) if path.is_file() && is_audio(&path) => walk.files.push(path),
+            Ok(_) => walk.ignored.push(path),
+            Err(e) => walk.unreadable.push((path, e.to_string())),
+        }
+    }
+}
+
+fn open_file(path: &Path) -> Result<Box<dyn EncodedAudio>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn songs_are_named_after_their_files() {
+        assert_eq!(
+            song_from_path(Path::new("library/Waxwing - Sorry.flac")),
+            SongInfo::new("Waxwing", "Sorry")
+        );
+        assert_eq!(
+            song_from_path(Path::new("Dog Dribble.mp3")),
+            SongInfo::new("Dog Dribble", "Unknown")
+        );
+        assert!(is_audio(Path::new("a.MP3")));
+        assert!(!is_audio(Path::new("cover.jpg")));
+        assert!(!is_audio(Path::new("README")));
+    }
+}
@@ -0,0 +1,76 @@
+//! Synthetic audio and catalogue songs shared by the unit and integration
+//! tests and the benches, so they all work with the same thing. Only built
+//! for them, with the `testing` feature.
+
+use std::f32::consts::PI;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+pub use crate::rng::XorShift;
+use crate::{ConstellationPoint, Fingerprint, FingerprintConfig, SongInfo, model::NewSong};
+
+/// Title, artist, duration and [`fingerprints`] base of each of [`song`]'s
+/// songs.
+const SONGS: [(&str, &str, f64, i64); 3] = [
+    ("Waxwing", "Sorry", 181.5, 300),
+    ("Lemon to a Knife Fight", "The Wombats", 212.0, 1300),
+    ("The fish needs a bike", "Snapped Ankles", 200.0, 2300),
+];
+
+/// The `n`th of three songs, fingerprinted for `config`, sharing no hashes
+/// with each other.
+pub fn song(config: &FingerprintConfig, n: usize) -> NewSong {
+    let (title, artist, duration, base) = SONGS[n];
+    NewSong {
+        song: SongInfo::new(title, artist),
+        duration,
+        fingerprints: fingerprints(config, base),
+    }
+}
+
+/// Twenty fingerprints a quarter second apart, their frequencies climbing from
+/// `base` Hz. Bases 1000 Hz apart give songs sharing no hashes.
+pub fn fingerprints(config: &FingerprintConfig, base: i64) -> Vec<Fingerprint> {
+    (0..20)
+        .map(|i| {
+            let time = Decimal::from(i) / dec!(4);
+            let anchor = ConstellationPoint {
+                time,
+                frequency: Decimal::from(base + 10 * i),
+                magnitude: dec!(80),
+            };
+            let target = ConstellationPoint {
+                time: time + dec!(0.5),
+                frequency: Decimal::from(base + 10 * i + 200),
+                magnitude: dec!(70),
+            };
+            Fingerprint::new(&anchor, &target, config)
+        })
+        .collect()
+}
+
+/// Chords of harmonically related tones changing every tenth of a second, with
+/// a little noise, as interleaved samples. Different seeds give unrelated songs.
+pub fn music(seed: u64, seconds: f32, sample_rate: u32, channels: u16) -> Vec<i16> {
+    let mut random = XorShift::new(seed);
+    let frames = (seconds * sample_rate as f32) as usize;
+    let segment = sample_rate as usize / 10;
+    let chords: Vec<f32> = (0..frames.div_ceil(segment))
+        .map(|_| 150.0 + 1850.0 * random.next_f32())
+        .collect();
+    let mut samples = Vec::with_capacity(frames * channels as usize);
+    for i in 0..frames {
+        let root = chords[i / segment];
+        let t = i as f32 / sample_rate as f32;
+        let tone: f32 = [1.0, 1.25, 1.5, 2.0]
+            .iter()
+            .map(|ratio| (2.0 * PI * root * ratio * t).sin())
+            .sum();
+        let sample = 5000.0 * tone + 600.0 * (random.next_f32() - 0.5);
+        for _ in 0..channels {
+            samples.push(sample as i16);
+        }
+    }
+    samples
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use audio_identifier::{
+    FingerprintConfig,
+    audio::HashScheme,
+    model::{
+        ArchiveSummary, catalogue_stats, export_catalogue, find_similar_fingerprints,
+        import_catalogue, load_config, song_exists, store_config, store_song_fingerprints,
+    },
+};
+use common::{catalogue, song};
+use sqlx::SqlitePool;
+
+/// Catalogue the `n`th of the shared test songs, with an album and ISRC.
+async fn add(pool: &SqlitePool, config: &FingerprintConfig, n: usize) -> i64 {
+    let new = song(config, n);
+    store_song_fingerprints(
+        pool,
+        &new.song
+            .with_album("Album")
+            .with_isrc(format!("GBAYE25{n:05}")),
+        new.duration,
+        config.hash_scheme,
+        &new.fingerprints,
+    )
+    .await
+    .unwrap()
+}
+
+async fn export(pool: &SqlitePool) -> Vec<u8> {
+    let mut archive = Vec::new();
+    export_catalogue(pool, &mut archive).await.unwrap();
+    archive
+}
+
+#[tokio::test]
+async fn an_exported_catalogue_imports_into_an_empty_one() {
+    let config = FingerprintConfig::music();
+    let (_source_dir, source) = catalogue().await;
+    store_config(&source, &config).await.unwrap();
+    add(&source, &config, 0).await;
+    add(&source, &config, 1).await;
+    let archive = export(&source).await;
+
+    let (_dir, target) = catalogue().await;
+    let summary = import_catalogue(&target, archive.as_slice()).await.unwrap();
+
+    assert_eq!(
+        summary,
+        ArchiveSummary {
+            songs: 2,
+            duplicates: 0,
+            fingerprints: 40,
+        }
+    );
+    assert_eq!(load_config(&target).await.unwrap(), Some(config.clone()));
+    let lemon = song(&config, 1);
+    let two = song_exists(&target, &lemon.song).await.unwrap().unwrap();
+    let found = find_similar_fingerprints(&target, config.hash_scheme, &lemon.fingerprints)
+        .await
+        .unwrap();
+    assert_eq!(found[&two].len(), 20);
+    let (album, isrc): (String, String) =
+        sqlx::query_as("SELECT album, isrc FROM songs WHERE id = ?")
+            .bind(two)
+            .fetch_one(&target)
+            .await
+            .unwrap();
+    assert_eq!((album.as_str(), isrc.as_str()), ("Album", "GBAYE2500001"));
+}
+
+#[tokio::test]
+async fn importing_merges_and_skips_songs_already_catalogued() {
+    let config = FingerprintConfig::music();
+    let (_source_dir, source) = catalogue().await;
+    store_config(&source, &config).await.unwrap();
+    add(&source, &config, 0).await;
+    add(&source, &config, 1).await;
+    let archive = export(&source).await;
+
+    let (_dir, target) = catalogue().await;
+    store_config(&target, &config).await.unwrap();
+    add(&target, &config, 1).await;
+    add(&target, &config, 2).await;
+    let summary = import_catalogue(&target, archive.as_slice()).await.unwrap();
+
+    assert_eq!((summary.songs, summary.duplicates), (1, 1));
+    let stats = catalogue_stats(&target, config.hash_scheme).await.unwrap();
+    assert_eq!((stats.songs, stats.fingerprints), (3, 60));
+}
+
+#[tokio::test]
+async fn corrupt_archives_change_nothing() {
+    let config = FingerprintConfig::music();
+    let (_source_dir, source) = catalogue().await;
+    store_config(&source, &config).await.unwrap();
+    add(&source, &config, 0).await;
+    let mut archive = export(&source).await;
+    let middle = archive.len() / 2;
+    archive[middle] ^= 0x40;
+
+    let (_dir, target) = catalogue().await;
+    let error = import_catalogue(&target, archive.as_slice())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("checksum"), "{error}");
+    assert_eq!(
+        catalogue_stats(&target, config.hash_scheme)
+            .await
+            .unwrap()
+            .songs,
+        0
+    );
+    assert_eq!(load_config(&target).await.unwrap(), None);
+
+    // Truncation is caught too
+    let archive = export(&source).await;
+    assert!(
+        import_catalogue(&target, &archive[..archive.len() - 10])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn archives_from_a_differently_configured_catalogue_are_refused() {
+    let config = FingerprintConfig::music();
+    let (_source_dir, source) = catalogue().await;
+    store_config(&source, &config).await.unwrap();
+    add(&source, &config, 0).await;
+    let archive = export(&source).await;
+
+    let other = FingerprintConfig {
+        hash_scheme: HashScheme::PitchInvariant,
+        ..config
+    };
+    let (_dir, target) = catalogue().await;
+    store_config(&target, &other).await.unwrap();
+
+    assert!(import_catalogue(&target, archive.as_slice()).await.is_err());
+    assert_eq!(
+        catalogue_stats(&target, config.hash_scheme)
+            .await
+            .unwrap()
+            .songs,
+        0
+    );
+}
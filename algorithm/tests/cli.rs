@@ -0,0 +1,292 @@
+mod common;
+
+use std::{path::Path, process::Command};
+
+use common::{SAMPLE_RATE, write_song, write_wav};
+use serde_json::Value;
+
+/// Run the binary against the catalogue at `db`, returning what it printed.
+fn run(db: &Path, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_audioIdentifier-rust"))
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn json(db: &Path, args: &[&str]) -> Value {
+    let mut args = args.to_vec();
+    args.push("--json");
+    let (success, stdout) = run(db, &args);
+    assert!(success, "{args:?} failed");
+    serde_json::from_str(&stdout).unwrap()
+}
+
+#[test]
+fn a_catalogue_is_managed_from_the_command_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = dir.path().join("library");
+    std::fs::create_dir(&library).unwrap();
+    write_song(&library.join("Waxwing - Sorry.wav"), 1, 20.0);
+    write_song(&library.join("Dog Dribble - Getdown Services.wav"), 2, 20.0);
+    // The catalogue's directory is created as needed
+    let db = dir.path().join("catalogue/songs.db");
+
+    let report = json(&db, &["ingest", library.to_str().unwrap()]);
+    assert_eq!(report["ingested"].as_array().unwrap().len(), 2);
+    assert!(report["failed"].as_array().unwrap().is_empty());
+
+    let songs = json(&db, &["list"]);
+    assert_eq!(songs.as_array().unwrap().len(), 2);
+    let waxwing = songs
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|song| song["title"] == "Waxwing")
+        .unwrap();
+    let id = waxwing["id"].as_i64().unwrap();
+    assert_eq!(waxwing["artist"], "Sorry");
+    assert!((waxwing["duration"].as_f64().unwrap() - 20.0).abs() < 0.01);
+
+    let shown = json(&db, &["show", &id.to_string()]);
+    assert_eq!(shown["title"], "Waxwing");
+    assert!(shown["fingerprints"].as_u64().unwrap() > 0);
+
+    let results = json(
+        &db,
+        &[
+            "identify",
+            library.join("Waxwing - Sorry.wav").to_str().unwrap(),
+            "--start",
+            "5",
+            "--duration",
+            "8",
+        ],
+    );
+    let best = &results[0];
+    assert_eq!(best["song_id"], id);
+    assert_eq!(best["title"], "Waxwing");
+    assert!((best["time_offset"].as_f64().unwrap() - 5.0).abs() < 0.3);
+
+    // The same answer from the hash index
+    let index = dir.path().join("catalogue.idx");
+    let indexed = json(&db, &["index", index.to_str().unwrap()]);
+    assert!(indexed["hashes"].as_u64().unwrap() > 0);
+    let query = library.join("Waxwing - Sorry.wav");
+    let identify_indexed = [
+        "identify",
+        query.to_str().unwrap(),
+        "--index",
+        index.to_str().unwrap(),
+    ];
+    let results = json(&db, &identify_indexed);
+    assert_eq!(results[0]["song_id"], id);
+
+    let archive = dir.path().join("catalogue.aida");
+    let exported = json(&db, &["export", archive.to_str().unwrap()]);
+    assert_eq!(exported["songs"], 2);
+
+    assert_eq!(json(&db, &["delete", &id.to_string()])["deleted"], id);
+    // The index is out of date now
+    assert!(!run(&db, &identify_indexed).0);
+    assert!(!run(&db, &["delete", &id.to_string()]).0);
+    assert!(!run(&db, &["show", &id.to_string()]).0);
+    assert_eq!(json(&db, &["stats"])["songs"], 1);
+
+    let imported = json(&db, &["import", archive.to_str().unwrap()]);
+    assert_eq!(
+        (&imported["songs"], &imported["duplicates"]),
+        (&1.into(), &1.into())
+    );
+
+    let (success, stdout) = run(&db, &["stats"]);
+    assert!(success);
+    assert!(stdout.contains("songs:           2"));
+}
+
+#[test]
+fn a_batch_of_queries_is_reported_as_json_lines_or_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = dir.path().join("library");
+    let queries = dir.path().join("queries");
+    std::fs::create_dir_all(&queries).unwrap();
+    std::fs::create_dir(&library).unwrap();
+    for (seed, name) in [(1, "One - Artist"), (2, "Two - Artist")] {
+        let samples = write_song(&library.join(format!("{name}.wav")), seed, 20.0);
+        // Five seconds from the middle of each
+        write_wav(
+            &queries.join(format!("{name} clip.wav")),
+            &samples[8 * SAMPLE_RATE as usize..13 * SAMPLE_RATE as usize],
+            SAMPLE_RATE,
+            1,
+        );
+    }
+    write_song(&queries.join("Unknown.wav"), 99, 5.0);
+    let db = dir.path().join("songs.db");
+    assert!(run(&db, &["ingest", library.to_str().unwrap()]).0);
+
+    let jsonl = dir.path().join("report.jsonl");
+    let csv = dir.path().join("report.txt");
+    assert!(
+        run(
+            &db,
+            &[
+                "identify",
+                queries.to_str().unwrap(),
+                "--report",
+                jsonl.to_str().unwrap()
+            ]
+        )
+        .0
+    );
+    assert!(
+        run(
+            &db,
+            &[
+                "identify",
+                queries.to_str().unwrap(),
+                "--report",
+                csv.to_str().unwrap(),
+                "--format",
+                "csv"
+            ]
+        )
+        .0
+    );
+
+    let records: Vec<Value> = std::fs::read_to_string(&jsonl)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert!(
+        records[0]["query"]
+            .as_str()
+            .unwrap()
+            .ends_with("One - Artist clip.wav")
+    );
+    assert_eq!(records[0]["title"], "One");
+    assert!((records[0]["time_offset"].as_f64().unwrap() - 8.0).abs() < 0.3);
+    assert_eq!(records[1]["title"], "Two");
+    assert!(records[2]["song_id"].is_null());
+    assert!(
+        records
+            .iter()
+            .all(|record| record["runtime_ms"].as_f64().unwrap() > 0.0)
+    );
+
+    // Printed, each query's results are listed under it
+    let printed = json(&db, &["identify", queries.to_str().unwrap()]);
+    assert_eq!(printed.as_array().unwrap().len(), 3);
+    assert_eq!(printed[0]["query"], records[0]["query"]);
+    assert_eq!(printed[0]["results"][0]["title"], "One");
+    assert!(printed[2]["results"].as_array().unwrap().is_empty());
+
+    let csv = std::fs::read_to_string(&csv).unwrap();
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[0].starts_with("query,song_id,title,artist,"));
+    assert!(rows[1].contains(",One,Artist,"));
+    assert!(rows[3].contains("Unknown.wav,,,,"));
+
+    // A missing query is reported, and fails the run
+    let missing = dir.path().join("missing.jsonl");
+    let gone = dir.path().join("gone.wav");
+    assert!(
+        !run(
+            &db,
+            &[
+                "identify",
+                gone.to_str().unwrap(),
+                "--report",
+                missing.to_str().unwrap()
+            ]
+        )
+        .0
+    );
+    let record: Value = serde_json::from_str(&std::fs::read_to_string(&missing).unwrap()).unwrap();
+    assert!(record["error"].as_str().unwrap().contains("gone.wav"));
+}
+
+#[test]
+fn accuracy_is_evaluated_on_cut_and_labelled_clips() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = dir.path().join("library");
+    let others = dir.path().join("others");
+    std::fs::create_dir(&library).unwrap();
+    std::fs::create_dir(&others).unwrap();
+    for seed in 1..=3 {
+        write_song(
+            &library.join(format!("Song {seed} - Artist.wav")),
+            seed,
+            20.0,
+        );
+        write_song(
+            &others.join(format!("Other {seed} - Artist.wav")),
+            10 + seed,
+            20.0,
+        );
+    }
+    std::fs::write(
+        dir.path().join("labels.csv"),
+        "query,title,artist\n\
+         library/Song 2 - Artist.wav,Song 2,Artist\n\
+         others/Other 1 - Artist.wav,,\n",
+    )
+    .unwrap();
+    let db = dir.path().join("songs.db");
+    assert!(run(&db, &["ingest", library.to_str().unwrap()]).0);
+
+    let summary = json(
+        &db,
+        &[
+            "eval",
+            "--cut",
+            library.to_str().unwrap(),
+            "--negatives",
+            others.to_str().unwrap(),
+            "--labels",
+            dir.path().join("labels.csv").to_str().unwrap(),
+            "--clips-per-file",
+            "2",
+            "--clip-seconds",
+            "6",
+            "--thresholds",
+            "1,0.01",
+        ],
+    );
+
+    assert_eq!(summary["queries"], 14);
+    assert_eq!(summary["in_catalogue"], 7);
+    assert_eq!(summary["out_of_catalogue"], 7);
+    assert_eq!(summary["errors"], 0);
+    // Short clips of synthetic songs aren't all found, but none are confused
+    let accuracy = summary["top1_accuracy"].as_f64().unwrap();
+    assert!(accuracy >= 0.8, "top-1 accuracy {accuracy}");
+    let strict = &summary["thresholds"][1];
+    assert_eq!(strict["max_false_positives"], 0.01);
+    assert_eq!(strict["precision"], 1.0);
+    assert_eq!(strict["recall"].as_f64().unwrap(), accuracy);
+    assert_eq!(strict["false_positive_rate"], 0.0);
+    let latency = &summary["latency"];
+    assert!(latency["p50_ms"].as_f64().unwrap() > 0.0);
+    assert!(latency["p50_ms"].as_f64() <= latency["max_ms"].as_f64());
+
+    let (success, stdout) = run(
+        &db,
+        &[
+            "eval",
+            "--labels",
+            dir.path().join("labels.csv").to_str().unwrap(),
+        ],
+    );
+    assert!(success);
+    assert!(stdout.contains("top-1 accuracy: 100.0%"));
+    assert!(!run(&db, &["eval"]).0);
+}
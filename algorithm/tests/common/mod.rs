@@ -0,0 +1,81 @@
+//! Catalogue and audio fixtures shared by the integration tests.
+#![allow(dead_code, unused_imports)]
+
+use std::{fs, path::Path};
+
+use audio_identifier::model::setup_database;
+pub use audio_identifier::testing::{fingerprints, music, song};
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+
+pub const SAMPLE_RATE: u32 = 22_050;
+
+/// A fresh catalogue in a temporary file, removed when the directory is dropped.
+pub async fn catalogue() -> (TempDir, SqlitePool) {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite:{}", dir.path().join("fingerprints.db").display());
+    let pool = setup_database(&url).await.unwrap();
+    (dir, pool)
+}
+
+/// Write interleaved 16-bit PCM `samples` to `path` as a WAV file.
+pub fn write_wav(path: &Path, samples: &[i16], sample_rate: u32, channels: u16) {
+    write_tagged_wav(path, samples, sample_rate, channels, &[]);
+}
+
+/// Write a WAV file like [`write_wav`], with a RIFF INFO chunk holding `tags`,
+/// such as `(b"INAM", title)`, `(b"IART", artist)` and `(b"IPRD", album)`.
+pub fn write_tagged_wav(
+    path: &Path,
+    samples: &[i16],
+    sample_rate: u32,
+    channels: u16,
+    tags: &[(&[u8; 4], &str)],
+) {
+    let mut info = Vec::new();
+    for (id, value) in tags {
+        // NUL terminated, padded to an even length
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        info.extend_from_slice(*id);
+        info.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        if value.len() % 2 == 1 {
+            value.push(0);
+        }
+        info.extend_from_slice(&value);
+    }
+    let list_len = if tags.is_empty() { 0 } else { 12 + info.len() };
+
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+    let mut bytes = Vec::with_capacity(44 + list_len + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + list_len as u32 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    if !tags.is_empty() {
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&(4 + info.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"INFO");
+        bytes.extend_from_slice(&info);
+    }
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    fs::write(path, bytes).unwrap();
+}
+
+/// Write `seconds` of [`music`] for `seed` to `path`, mono at [`SAMPLE_RATE`].
+pub fn write_song(path: &Path, seed: u64, seconds: f32) -> Vec<i16> {
+    let samples = music(seed, seconds, SAMPLE_RATE, 1);
+    write_wav(path, &samples, SAMPLE_RATE, 1);
+    samples
+}
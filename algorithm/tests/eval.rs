@@ -0,0 +1,128 @@
+mod common;
+
+use std::{collections::HashMap, time::Duration};
+
+use audio_identifier::{
+    Fingerprint, FingerprintConfig, Fingerprinter, Matcher, SongInfo,
+    audio::{CatalogueStats, HashScheme},
+    eval::{ClipOptions, EvalQuery, cut_clips, evaluate},
+    ingest::ingest,
+    model::{FingerprintStore, MemoryStore},
+    source::LocalFile,
+};
+use common::write_song;
+
+/// A [`MemoryStore`] that has lost every song's details, as if each were
+/// deleted between being matched and being named.
+#[derive(Default)]
+struct Forgetful {
+    inner: MemoryStore,
+}
+
+impl FingerprintStore for Forgetful {
+    async fn song_exists(&self, song: &SongInfo) -> Result<Option<i64>, sqlx::Error> {
+        self.inner.song_exists(song).await
+    }
+
+    async fn insert_song(
+        &self,
+        song: &SongInfo,
+        duration: f64,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<i64, sqlx::Error> {
+        self.inner
+            .insert_song(song, duration, scheme, fingerprints)
+            .await
+    }
+
+    async fn find_similar(
+        &self,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error> {
+        self.inner.find_similar(scheme, fingerprints).await
+    }
+
+    async fn song_info(
+        &self,
+        _song_ids: &[i64],
+    ) -> Result<HashMap<i64, (String, String, f64)>, sqlx::Error> {
+        Ok(HashMap::new())
+    }
+
+    async fn delete_song(&self, song_id: i64) -> Result<bool, sqlx::Error> {
+        self.inner.delete_song(song_id).await
+    }
+
+    async fn stats(&self, scheme: HashScheme) -> Result<CatalogueStats, sqlx::Error> {
+        self.inner.stats(scheme).await
+    }
+}
+
+#[tokio::test]
+async fn a_match_that_cant_be_named_fails_only_its_own_query() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Waxwing - Sorry.wav");
+    write_song(&path, 1, 10.0);
+    let store = Forgetful::default();
+    let fingerprinter = Fingerprinter::new(FingerprintConfig::music());
+    ingest(&store, &fingerprinter, &LocalFile::new(&path))
+        .await
+        .unwrap();
+    let query = |path| EvalQuery {
+        path,
+        start: Duration::ZERO,
+        duration: None,
+        expected: Some(SongInfo::new("Waxwing", "Sorry")),
+    };
+
+    let outcomes = evaluate(
+        &store,
+        &fingerprinter,
+        &Matcher::default(),
+        vec![query(path.clone()), query(dir.path().join("gone.wav"))],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(outcomes[0].best.is_none());
+    assert!(
+        outcomes[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("isn't catalogued")
+    );
+    assert!(outcomes[1].error.as_deref().unwrap().contains("gone.wav"));
+}
+
+#[test]
+fn clips_are_cut_from_every_file_that_can_be_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let long = dir.path().join("Long - Artist.wav");
+    let short = dir.path().join("Short - Artist.wav");
+    let broken = dir.path().join("Broken - Artist.mp3");
+    write_song(&long, 1, 7.5);
+    write_song(&short, 2, 1.5);
+    std::fs::write(&broken, "not really an mp3").unwrap();
+    let options = ClipOptions {
+        clips_per_file: 3,
+        seconds: 2.0,
+        seed: 7,
+    };
+
+    let clips = cut_clips(&[long.clone(), broken, short.clone()], true, options).unwrap();
+
+    assert_eq!(clips.len(), 4);
+    for clip in &clips[..3] {
+        assert_eq!(clip.path, long);
+        assert_eq!(clip.expected, Some(SongInfo::new("Long", "Artist")));
+        assert!(clip.start.as_secs_f64() <= 5.5, "{:?}", clip.start);
+        assert_eq!(clip.duration, Some(Duration::from_secs(2)));
+    }
+    // Shorter than a clip, so used whole
+    assert_eq!(clips[3].path, short);
+    assert_eq!(clips[3].duration, None);
+}
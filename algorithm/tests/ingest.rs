@@ -0,0 +1,159 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use audio_identifier::{
+    Fingerprint, FingerprintConfig, Fingerprinter, SongInfo,
+    audio::{CatalogueStats, HashScheme},
+    ingest::{IngestOptions, ingest_with},
+    model::{FingerprintStore, MemoryStore, NewSong},
+    source::Directory,
+};
+use common::write_song;
+
+/// A library of `songs` short tracks, with a file that isn't really audio
+/// after every fourth.
+fn library(songs: u64) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for seed in 0..songs {
+        write_song(
+            &dir.path().join(format!("Song {seed:02} - Artist.wav")),
+            seed,
+            3.0,
+        );
+        if seed % 4 == 3 {
+            std::fs::write(
+                dir.path().join(format!("Song {seed:02} b - Artist.mp3")),
+                "not really an mp3",
+            )
+            .unwrap();
+        }
+    }
+    dir
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn tracks_are_ingested_in_parallel_and_reported_in_order() {
+    let dir = library(12);
+    let store = MemoryStore::new();
+    let fingerprinter = Fingerprinter::new(FingerprintConfig::music());
+    let options = IngestOptions {
+        workers: 4,
+        batch_size: 5,
+    };
+
+    let report = ingest_with(&store, &fingerprinter, &Directory::new(dir.path()), options)
+        .await
+        .unwrap();
+
+    assert_eq!(report.ingested.len(), 12);
+    assert_eq!(report.failed.len(), 3);
+    let titles: Vec<_> = report
+        .ingested
+        .iter()
+        .map(|(track, _)| track.song.title.as_str())
+        .collect();
+    let expected: Vec<_> = (0..12).map(|seed| format!("Song {seed:02}")).collect();
+    assert_eq!(titles, expected);
+    let stats = store
+        .stats(fingerprinter.config().hash_scheme)
+        .await
+        .unwrap();
+    assert_eq!(stats.songs, 12);
+    assert!((stats.mean_duration - 3.0).abs() < 0.01);
+
+    let again = ingest_with(&store, &fingerprinter, &Directory::new(dir.path()), options)
+        .await
+        .unwrap();
+    assert!(again.ingested.is_empty());
+    assert_eq!(again.skipped.len(), 12);
+    assert_eq!(again.failed.len(), 3);
+}
+
+/// A [`MemoryStore`] whose batches always fail, and that refuses one song.
+#[derive(Default)]
+struct Flaky {
+    inner: MemoryStore,
+    batches: AtomicUsize,
+}
+
+impl FingerprintStore for Flaky {
+    async fn song_exists(&self, song: &SongInfo) -> Result<Option<i64>, sqlx::Error> {
+        self.inner.song_exists(song).await
+    }
+
+    async fn insert_song(
+        &self,
+        song: &SongInfo,
+        duration: f64,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<i64, sqlx::Error> {
+        if song.title == "Song 02" {
+            return Err(sqlx::Error::Protocol("refused".into()));
+        }
+        self.inner
+            .insert_song(song, duration, scheme, fingerprints)
+            .await
+    }
+
+    async fn insert_songs(
+        &self,
+        _songs: &[NewSong],
+        _scheme: HashScheme,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        Err(sqlx::Error::PoolTimedOut)
+    }
+
+    async fn find_similar(
+        &self,
+        scheme: HashScheme,
+        fingerprints: &[Fingerprint],
+    ) -> Result<HashMap<i64, Vec<Fingerprint>>, sqlx::Error> {
+        self.inner.find_similar(scheme, fingerprints).await
+    }
+
+    async fn song_info(
+        &self,
+        song_ids: &[i64],
+    ) -> Result<HashMap<i64, (String, String, f64)>, sqlx::Error> {
+        self.inner.song_info(song_ids).await
+    }
+
+    async fn delete_song(&self, song_id: i64) -> Result<bool, sqlx::Error> {
+        self.inner.delete_song(song_id).await
+    }
+
+    async fn stats(&self, scheme: HashScheme) -> Result<CatalogueStats, sqlx::Error> {
+        self.inner.stats(scheme).await
+    }
+}
+
+#[tokio::test]
+async fn a_failed_batch_is_retried_one_song_at_a_time() {
+    let dir = library(3);
+    let store = Flaky::default();
+    let fingerprinter = Fingerprinter::new(FingerprintConfig::music());
+
+    let report = ingest_with(
+        &store,
+        &fingerprinter,
+        &Directory::new(dir.path()),
+        IngestOptions {
+            workers: 2,
+            batch_size: 8,
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(store.batches.load(Ordering::Relaxed), 1);
+    assert_eq!(report.ingested.len(), 2);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0.song.title, "Song 02");
+    assert!(report.failed[0].1.contains("refused"));
+}
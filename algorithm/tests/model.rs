@@ -0,0 +1,188 @@
+mod common;
+
+use std::str::FromStr;
+
+use audio_identifier::{
+    Fingerprint, FingerprintConfig,
+    audio::HashScheme,
+    model::{
+        FingerprintStore, HashIndex, NewSong, SqliteStore, catalogue_stats, delete_song,
+        find_similar_fingerprints, setup_database, song_exists, store_song_fingerprints,
+    },
+};
+use common::{catalogue, song};
+use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
+
+async fn store(pool: &SqlitePool, scheme: HashScheme, new: &NewSong) -> i64 {
+    store_song_fingerprints(pool, &new.song, new.duration, scheme, &new.fingerprints)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn titles_and_artists_are_unique_together() {
+    let (_dir, pool) = catalogue().await;
+    let insert = |title: &'static str, artist: &'static str| {
+        sqlx::query("INSERT INTO songs (title, artist, duration) VALUES (?, ?, 200)")
+            .bind(title)
+            .bind(artist)
+            .execute(&pool)
+    };
+
+    insert("Waxwing", "Sorry").await.unwrap();
+    insert("Waxwing", "Someone Else").await.unwrap();
+    assert!(insert("Waxwing", "Sorry").await.is_err());
+}
+
+#[tokio::test]
+async fn songs_catalogued_twice_are_merged_when_titles_and_artists_become_unique() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite:{}", dir.path().join("fingerprints.db").display());
+    let options = SqliteConnectOptions::from_str(&url)
+        .unwrap()
+        .create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await.unwrap();
+    // The catalogue as it was before songs were unique
+    let mut before = sqlx::migrate!();
+    before.migrations = before
+        .migrations
+        .iter()
+        .filter(|migration| migration.version < 20250412140000)
+        .cloned()
+        .collect::<Vec<_>>()
+        .into();
+    before.run(&pool).await.unwrap();
+    let config = FingerprintConfig::music();
+    let [waxwing, lemon] = [0, 1].map(|n| song(&config, n));
+    // The second copy of Waxwing shares half its fingerprints with the first
+    for (id, new, fingerprints) in [
+        (1, &waxwing, &waxwing.fingerprints[..10]),
+        (2, &waxwing, &waxwing.fingerprints[..]),
+        (3, &lemon, &lemon.fingerprints[..]),
+    ] {
+        sqlx::query("INSERT INTO songs (id, title, artist, duration) VALUES (?, ?, ?, ?)")
+            .bind(id)
+            .bind(&new.song.title)
+            .bind(&new.song.artist)
+            .bind(new.duration)
+            .execute(&pool)
+            .await
+            .unwrap();
+        for fingerprint in fingerprints {
+            let (hash, time_offset, confidence, anchor_freq, target_freq, delta_t) =
+                fingerprint.into();
+            sqlx::query(
+                "INSERT INTO fingerprints (song_id, hash_scheme, hash, time_offset, confidence, anchor_frequency, target_frequency, delta_time) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(config.hash_scheme.version())
+            .bind(hash)
+            .bind(time_offset)
+            .bind(confidence)
+            .bind(anchor_freq)
+            .bind(target_freq)
+            .bind(delta_t)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+    }
+    pool.close().await;
+
+    let pool = setup_database(&url).await.unwrap();
+
+    assert_eq!(song_exists(&pool, &waxwing.song).await.unwrap(), Some(1));
+    let found = find_similar_fingerprints(&pool, config.hash_scheme, &waxwing.fingerprints)
+        .await
+        .unwrap();
+    assert_eq!(found.keys().collect::<Vec<_>>(), vec![&1]);
+    assert_eq!(found[&1].len(), 20);
+    let stats = catalogue_stats(&pool, config.hash_scheme).await.unwrap();
+    assert_eq!((stats.songs, stats.fingerprints), (2, 40));
+}
+
+#[tokio::test]
+async fn the_catalogue_persists_across_connections() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite:{}", dir.path().join("fingerprints.db").display());
+    let config = FingerprintConfig::music();
+    let waxwing = song(&config, 0);
+
+    let pool = setup_database(&url).await.unwrap();
+    let id = store(&pool, config.hash_scheme, &waxwing).await;
+    pool.close().await;
+
+    let pool = setup_database(&url).await.unwrap();
+    assert_eq!(song_exists(&pool, &waxwing.song).await.unwrap(), Some(id));
+}
+
+#[tokio::test]
+async fn the_hash_index_finds_what_the_catalogue_does() {
+    let (dir, pool) = catalogue().await;
+    let config = FingerprintConfig::music();
+    let songs = [0, 1, 2].map(|n| song(&config, n));
+    for new in &songs {
+        store(&pool, config.hash_scheme, new).await;
+    }
+
+    let index = HashIndex::build(&pool, config.hash_scheme, &dir.path().join("index"))
+        .await
+        .unwrap();
+
+    let query = [&songs[1].fingerprints[..], &songs[2].fingerprints[..]].concat();
+    let expected = find_similar_fingerprints(&pool, config.hash_scheme, &query)
+        .await
+        .unwrap();
+    let found = index.find_similar(&query).unwrap();
+    assert_eq!(found.len(), 2);
+    for (song_id, fps) in &expected {
+        let key = |fp: &Fingerprint| (fp.hash, fp.time_offset);
+        let mut want: Vec<_> = fps.iter().map(key).collect();
+        let mut got: Vec<_> = found[song_id].iter().map(key).collect();
+        want.sort();
+        got.sort();
+        assert_eq!(got, want);
+    }
+}
+
+#[tokio::test]
+async fn a_hash_index_the_catalogue_has_moved_on_from_is_refused() {
+    let (dir, pool) = catalogue().await;
+    let config = FingerprintConfig::music();
+    let scheme = config.hash_scheme;
+    let [waxwing, lemon] = [0, 1].map(|n| song(&config, n));
+    let id = store(&pool, scheme, &waxwing).await;
+    let path = dir.path().join("index");
+    HashIndex::build(&pool, scheme, &path).await.unwrap();
+    let indexed = SqliteStore::new(pool.clone())
+        .with_index(HashIndex::open(&path).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        indexed
+            .find_similar(scheme, &waxwing.fingerprints)
+            .await
+            .unwrap()[&id]
+            .len(),
+        20
+    );
+
+    // An index already in use is passed over once it is stale
+    let added = store(&pool, scheme, &lemon).await;
+    let found = indexed
+        .find_similar(scheme, &lemon.fingerprints)
+        .await
+        .unwrap();
+    assert_eq!(found.keys().collect::<Vec<_>>(), vec![&added]);
+
+    // A stale one isn't taken on at all, whatever the change was
+    let stale = SqliteStore::new(pool.clone()).with_index(HashIndex::open(&path).unwrap());
+    assert!(stale.await.is_err());
+    HashIndex::build(&pool, scheme, &path).await.unwrap();
+    delete_song(&pool, added).await.unwrap();
+    let stale = SqliteStore::new(pool.clone()).with_index(HashIndex::open(&path).unwrap());
+    assert!(stale.await.is_err());
+    HashIndex::build(&pool, scheme, &path).await.unwrap();
+    let fresh = SqliteStore::new(pool).with_index(HashIndex::open(&path).unwrap());
+    assert!(fresh.await.is_ok());
+}
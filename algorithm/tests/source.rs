@@ -0,0 +1,228 @@
+mod common;
+
+use audio_identifier::{
+    FingerprintConfig, Fingerprinter, Matcher, SongInfo,
+    ingest::ingest,
+    model::{FingerprintStore, MemoryStore, SqliteStore, setup_database},
+    source::{AudioSource, Directory, LocalFile, audio_files, song_from_file},
+};
+use common::{SAMPLE_RATE, music, write_song, write_tagged_wav};
+use rodio::buffer::SamplesBuffer;
+
+#[tokio::test]
+async fn a_local_file_is_ingested_and_identified() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Waxwing - Sorry.wav");
+    let samples = write_song(&path, 7, 30.0);
+    let store = MemoryStore::new();
+    let fingerprinter = Fingerprinter::new(FingerprintConfig::music());
+
+    let report = ingest(&store, &fingerprinter, &LocalFile::new(&path))
+        .await
+        .unwrap();
+
+    assert_eq!(report.ingested.len(), 1);
+    let ids = report.song_ids();
+    let info = store.song_info(&ids).await.unwrap();
+    assert_eq!(info[&ids[0]].0, "Waxwing");
+    assert_eq!(info[&ids[0]].1, "Sorry");
+    assert!((info[&ids[0]].2 - 30.0).abs() < 0.01);
+
+    // Ten seconds from the middle of the song
+    let clip = samples[10 * SAMPLE_RATE as usize..20 * SAMPLE_RATE as usize].to_vec();
+    let query =
+        fingerprinter.fingerprint_source(Box::new(SamplesBuffer::new(1, SAMPLE_RATE, clip)));
+    let candidates = store
+        .find_similar(fingerprinter.config().hash_scheme, &query)
+        .await
+        .unwrap();
+    let results = Matcher::default().identify(&query, candidates);
+    assert_eq!(results[0].song_id, ids[0]);
+    assert!((results[0].time_offset - 10.0).abs() < 0.3);
+}
+
+#[tokio::test]
+async fn a_directory_provides_each_audio_file_in_its_tree_once() {
+    let dir = tempfile::tempdir().unwrap();
+    write_song(&dir.path().join("Two - Artist.wav"), 2, 5.0);
+    write_song(&dir.path().join("One - Artist.WAV"), 1, 5.0);
+    std::fs::write(dir.path().join("notes.txt"), "not audio").unwrap();
+    std::fs::create_dir_all(dir.path().join("nested.wav/deeper")).unwrap();
+    write_song(
+        &dir.path().join("nested.wav/deeper/Three - Artist.wav"),
+        3,
+        5.0,
+    );
+    let source = Directory::new(dir.path());
+
+    let tracks = source.tracks().await.unwrap().tracks;
+
+    assert_eq!(
+        tracks.iter().map(|t| &t.song).collect::<Vec<_>>(),
+        vec![
+            &SongInfo::new("One", "Artist"),
+            &SongInfo::new("Two", "Artist"),
+            &SongInfo::new("Three", "Artist"),
+        ]
+    );
+    // The plain walker finds the same files
+    assert_eq!(
+        audio_files(dir.path()).unwrap(),
+        tracks
+            .iter()
+            .map(|t| std::path::PathBuf::from(&t.location))
+            .collect::<Vec<_>>()
+    );
+    let ignored = source.tracks().await.unwrap().ignored;
+    assert_eq!(ignored.len(), 1);
+    assert!(ignored[0].ends_with("notes.txt"));
+
+    let store = MemoryStore::new();
+    let fingerprinter = Fingerprinter::new(FingerprintConfig::music());
+    let ids = ingest(&store, &fingerprinter, &source)
+        .await
+        .unwrap()
+        .song_ids();
+    assert_eq!(ids.len(), 3);
+    assert_ne!(ids[0], ids[1]);
+    // Ingesting again finds the songs already catalogued
+    let again = ingest(&store, &fingerprinter, &source).await.unwrap();
+    assert!(again.ingested.is_empty());
+    assert_eq!(again.song_ids(), ids);
+    assert_eq!(
+        store
+            .stats(fingerprinter.config().hash_scheme)
+            .await
+            .unwrap()
+            .songs,
+        3
+    );
+}
+
+#[tokio::test]
+async fn tags_name_songs_before_file_names_do() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("track01.wav");
+    let samples = music(4, 5.0, SAMPLE_RATE, 2);
+    write_tagged_wav(
+        &path,
+        &samples,
+        SAMPLE_RATE,
+        2,
+        &[
+            (b"INAM", "Lemon to a Knife Fight"),
+            (b"IART", "The Wombats"),
+            (b"IPRD", "Glitterbug"),
+        ],
+    );
+    // Untagged fields still come from the file name
+    write_tagged_wav(
+        &dir.path().join("Untitled - Someone.wav"),
+        &samples,
+        SAMPLE_RATE,
+        2,
+        &[(b"IPRD", "Demos")],
+    );
+
+    assert_eq!(
+        song_from_file(&path),
+        SongInfo::new("Lemon to a Knife Fight", "The Wombats").with_album("Glitterbug")
+    );
+    assert_eq!(
+        song_from_file(&dir.path().join("Untitled - Someone.wav")),
+        SongInfo::new("Untitled", "Someone").with_album("Demos")
+    );
+
+    let catalogue = tempfile::tempdir().unwrap();
+    let url = format!("sqlite:{}", catalogue.path().join("songs.db").display());
+    let store = SqliteStore::new(setup_database(&url).await.unwrap());
+    let fingerprinter = Fingerprinter::new(FingerprintConfig::music());
+    let report = ingest(&store, &fingerprinter, &Directory::new(dir.path()))
+        .await
+        .unwrap();
+
+    assert_eq!(report.ingested.len(), 2);
+    // Tags are read as each track is ingested, not as the directory is listed
+    assert_eq!(
+        report.ingested[0].0.song,
+        SongInfo::new("Untitled", "Someone").with_album("Demos")
+    );
+    let albums: Vec<(String, Option<String>, f64)> =
+        sqlx::query_as("SELECT title, album, CAST(duration AS REAL) FROM songs ORDER BY title")
+            .fetch_all(store.pool())
+            .await
+            .unwrap();
+    assert_eq!(albums[0].0, "Lemon to a Knife Fight");
+    assert_eq!(albums[0].1.as_deref(), Some("Glitterbug"));
+    assert!((albums[0].2 - 5.0).abs() < 0.01);
+    assert_eq!(albums[1].1.as_deref(), Some("Demos"));
+}
+
+#[tokio::test]
+async fn failed_files_are_reported_without_stopping_the_rest() {
+    let dir = tempfile::tempdir().unwrap();
+    write_song(&dir.path().join("Fine - Artist.wav"), 5, 5.0);
+    std::fs::write(dir.path().join("Broken - Artist.mp3"), "not really an mp3").unwrap();
+    let store = MemoryStore::new();
+    let fingerprinter = Fingerprinter::default();
+
+    let report = ingest(&store, &fingerprinter, &Directory::new(dir.path()))
+        .await
+        .unwrap();
+
+    assert_eq!(report.ingested.len(), 1);
+    assert_eq!(report.ingested[0].0.song, SongInfo::new("Fine", "Artist"));
+    assert_eq!(report.failed.len(), 1);
+    assert!(report.failed[0].0.location.ends_with("Broken - Artist.mp3"));
+
+    let missing = ingest(
+        &store,
+        &fingerprinter,
+        &LocalFile::new(dir.path().join("gone.wav")),
+    )
+    .await
+    .unwrap();
+    assert!(missing.failed[0].1.contains("gone.wav"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unreadable_directories_are_reported_without_stopping_the_rest() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    write_song(&dir.path().join("Fine - Artist.wav"), 5, 5.0);
+    let locked = dir.path().join("locked");
+    std::fs::create_dir(&locked).unwrap();
+    write_song(&locked.join("Hidden - Artist.wav"), 6, 5.0);
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions don't stop root
+    let readable = std::fs::read_dir(&locked).is_ok();
+
+    let report = ingest(
+        &MemoryStore::new(),
+        &Fingerprinter::new(FingerprintConfig::music()),
+        &Directory::new(dir.path()),
+    )
+    .await;
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let report = report.unwrap();
+
+    assert!(report.failed.is_empty());
+    if readable {
+        assert_eq!(report.ingested.len(), 2);
+        assert!(report.unreadable.is_empty());
+    } else {
+        assert_eq!(report.ingested.len(), 1);
+        assert_eq!(report.unreadable.len(), 1);
+        assert!(report.unreadable[0].0.ends_with("locked"));
+    }
+
+    // Only the directory itself being missing is an error
+    assert!(
+        Directory::new(dir.path().join("gone"))
+            .tracks()
+            .await
+            .is_err()
+    );
+}
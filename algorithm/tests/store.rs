@@ -0,0 +1,113 @@
+mod common;
+
+use audio_identifier::{
+    FingerprintConfig, SongInfo,
+    audio::HashScheme,
+    model::{FingerprintStore, MemoryStore, NewSong, PostgresStore, SqliteStore},
+};
+use common::{catalogue, fingerprints, song};
+
+async fn insert(store: &impl FingerprintStore, scheme: HashScheme, new: &NewSong) -> i64 {
+    store
+        .insert_song(&new.song, new.duration, scheme, &new.fingerprints)
+        .await
+        .unwrap()
+}
+
+/// What every backend must do the same way.
+async fn behaves_like_a_catalogue(store: impl FingerprintStore) {
+    let config = FingerprintConfig::music();
+    let scheme = config.hash_scheme;
+    let [waxwing, lemon, fish] = [0, 1, 2].map(|n| song(&config, n));
+
+    assert_eq!(store.song_exists(&waxwing.song).await.unwrap(), None);
+    let first = insert(&store, scheme, &waxwing).await;
+    let second = insert(&store, scheme, &lemon).await;
+    assert_ne!(first, second);
+    assert_eq!(store.song_exists(&waxwing.song).await.unwrap(), Some(first));
+    assert_eq!(store.song_exists(&lemon.song).await.unwrap(), Some(second));
+    // Storing a catalogued song again keeps the original, and adds nothing
+    let again = insert(&store, scheme, &waxwing).await;
+    assert_eq!(again, first);
+
+    let query = lemon.fingerprints.clone();
+    let similar = store.find_similar(scheme, &query).await.unwrap();
+    assert_eq!(similar.keys().collect::<Vec<_>>(), vec![&second]);
+    assert_eq!(similar[&second].len(), 20);
+    let other_scheme = store.find_similar(HashScheme::Fnv1a, &query).await.unwrap();
+    assert!(other_scheme.is_empty());
+
+    let info = store.song_info(&[first, second, 999]).await.unwrap();
+    assert_eq!(info.len(), 2);
+    assert_eq!(
+        info[&second],
+        (lemon.song.title.clone(), lemon.song.artist.clone(), 212.0)
+    );
+
+    let stats = store.stats(scheme).await.unwrap();
+    assert_eq!((stats.songs, stats.fingerprints), (2, 40));
+    assert!((stats.mean_duration - 196.75).abs() < 1e-3);
+
+    assert!(store.delete_song(second).await.unwrap());
+    assert!(!store.delete_song(second).await.unwrap());
+    assert_eq!(store.song_exists(&lemon.song).await.unwrap(), None);
+    assert!(store.find_similar(scheme, &query).await.unwrap().is_empty());
+    let stats = store.stats(scheme).await.unwrap();
+    assert_eq!((stats.songs, stats.fingerprints), (1, 20));
+
+    // A batch keeps catalogued songs, and repeats within it, as they are
+    let ids = store
+        .insert_songs(&[fish.clone(), waxwing, fish], scheme)
+        .await
+        .unwrap();
+    assert_eq!(ids[1], first);
+    assert_eq!(ids[0], ids[2]);
+    assert_ne!(ids[0], first);
+    let stats = store.stats(scheme).await.unwrap();
+    assert_eq!((stats.songs, stats.fingerprints), (2, 40));
+
+    // Concurrent writers of one song all get its id
+    let ids = futures::future::try_join_all(
+        (0..4).map(|_| store.insert_song(&lemon.song, lemon.duration, scheme, &lemon.fingerprints)),
+    )
+    .await
+    .unwrap();
+    assert!(ids.iter().all(|&id| id == ids[0]), "{ids:?}");
+    let stats = store.stats(scheme).await.unwrap();
+    assert_eq!((stats.songs, stats.fingerprints), (3, 60));
+
+    // Hashes under another scheme aren't counted among the distinct ones
+    let legacy = FingerprintConfig {
+        hash_scheme: HashScheme::Fnv1a,
+        ..config
+    };
+    let bonus = NewSong {
+        song: SongInfo::new("Bonus", "Track"),
+        duration: 200.0,
+        fingerprints: fingerprints(&legacy, 3300),
+    };
+    insert(&store, legacy.hash_scheme, &bonus).await;
+    let stats = store.stats(scheme).await.unwrap();
+    assert_eq!(
+        (stats.songs, stats.fingerprints, stats.distinct_hashes),
+        (4, 80, 60)
+    );
+}
+
+#[tokio::test]
+async fn sqlite_store() {
+    let (_dir, pool) = catalogue().await;
+    behaves_like_a_catalogue(SqliteStore::new(pool)).await;
+}
+
+#[tokio::test]
+async fn memory_store() {
+    behaves_like_a_catalogue(MemoryStore::new()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs an empty Postgres database at TEST_POSTGRES_URL"]
+async fn postgres_store() {
+    let url = std::env::var("TEST_POSTGRES_URL").expect("TEST_POSTGRES_URL is not set");
+    behaves_like_a_catalogue(PostgresStore::connect(&url).await.unwrap()).await;
+}